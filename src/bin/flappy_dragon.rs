@@ -7,19 +7,19 @@
 // * Scoring
 // * Multiple obstacles
 // * Background objects
-// * Bird animation
 //
 
 
 use macroquad::prelude::*;
 use macroquad::rand::gen_range;
+use macroquad_sandbox::mqx::animation::{AnimationPlayer, SpriteSheet};
+use macroquad_sandbox::mqx::import::{SheetData, Slice};
 
 const SCREEN_WIDTH: i32 = 1024;
 const SCREEN_HEIGHT: i32 = 700;
 const SINK_SPEED: f32 = 100.0;
 const FLAP_SPEED: f32 = 150.0;
 const PLAYER_WIDTH: f32 = 60.0;
-const PLAYER_HEIGHT: f32 = 40.0;
 
 pub trait GameState {
     fn tick(&mut self);
@@ -37,6 +37,7 @@ struct Player {
     y: f32,
     width: f32,
     height: f32,
+    animation: AnimationPlayer,
 }
 
 struct Obstacle {
@@ -48,6 +49,9 @@ struct Obstacle {
 
 struct State {
     game_mode: GameMode,
+    sheet: SpriteSheet,
+    /// Hitbox in the frame
+    body: Slice,
     player: Player,
     obstacles: Vec<Obstacle>,
     score: i32,
@@ -55,20 +59,35 @@ struct State {
 
 
 impl Player {
-    fn new(x: f32, y: f32) -> Self {
+    // Player is the size of the bird's body, which is PLAYER_WIDTH wide
+    fn new(x: f32, y: f32, sheet: &SpriteSheet, body: &Slice) -> Self {
+        let mut animation = AnimationPlayer::new(sheet.clip_id("dive").unwrap_or(0));
+        let scale = PLAYER_WIDTH / body.rect.w;
+        let height = body.rect.h * scale;
+        // Center of the body is the player's position. Slice is given relative to the frame
+        let frame = sheet.frames.rect(0).unwrap_or(body.rect);
+        let center = body.rect.point() + body.pivot.unwrap_or(body.rect.size() / 2.0);
+        animation.pivot = center / frame.size();
+        animation.scale = Vec2::splat(scale);
         Self { 
             x,
-            y: y + PLAYER_HEIGHT / 2.0, 
+            y: y + height / 2.0, 
             width: PLAYER_WIDTH,
-            height: PLAYER_HEIGHT,
+            height,
+            animation,
         }
     }
 
-    fn render(&self) {
-        draw_rectangle_lines(0.0, self.y-self.height/2.0, self.width, self.height, 4.0, RED);
+    fn render(&self, sheet: &SpriteSheet) {
+        self.animation.draw(sheet, vec2(self.width / 2.0, self.y));
     }
 
-    fn gravity_and_move(&mut self, dt: f32, is_flapping: bool) {
+    fn gravity_and_move(&mut self, dt: f32, is_flapping: bool, sheet: &SpriteSheet) {
+        let clip = if is_flapping { "flap" } else { "dive" };
+        if let Some(clip) = sheet.clip_id(clip) {
+            self.animation.play(clip);
+        }
+        self.animation.update(dt);
         if is_flapping {
             self.y -= dt * FLAP_SPEED;
         } else {
@@ -100,17 +119,19 @@ impl Obstacle {
     fn hit_obstacle(&self, player: &Player) -> bool {
         let half_width = player.width;
         let x_match = self.x > player.x - half_width && self.x < player.x + half_width;
-        let above_gap = player.y < self.gap_y;
-        let below_gap = player.y > self.gap_y + self.size;
+        let above_gap = player.y - player.height / 2.0 < self.gap_y;
+        let below_gap = player.y + player.height / 2.0 > self.gap_y + self.size;
         x_match && (above_gap || below_gap)
     }
 }
 
 impl State {
-    fn new() -> Self {
+    fn new(sheet: SpriteSheet, body: Slice) -> Self {
         Self { 
             game_mode: GameMode::Menu,
-            player: Player::new(0.0, 0.0),
+            player: Player::new(0.0, 0.0, &sheet, &body),
+            sheet,
+            body,
             obstacles: Vec::new(),
             score: 0
         }
@@ -134,7 +155,7 @@ impl State {
 
         let dt = get_frame_time();
         let is_flapping = is_key_down(KeyCode::Space);
-        self.player.gravity_and_move(dt, is_flapping);
+        self.player.gravity_and_move(dt, is_flapping, &self.sheet);
         // check if bird collides
         for obstacle in self.obstacles.iter() {
             if obstacle.hit_obstacle(&self.player) || self.player.y > SCREEN_HEIGHT as f32 {
@@ -149,7 +170,7 @@ impl State {
             self.obstacles.push(Obstacle::new(self.player.x + 1200., 10));
         }
 
-        self.player.render();
+        self.player.render(&self.sheet);
         for obstacle in self.obstacles.iter() {
             obstacle.render(self.player.x);
         }
@@ -167,7 +188,7 @@ impl State {
     }
 
     fn new_game(&mut self) {
-        self.player = Player::new(5.0, 25.0);
+        self.player = Player::new(5.0, 25.0, &self.sheet, &self.body);
         self.game_mode = GameMode::Playing;
         self.obstacles = vec![Obstacle::new(400.0, 10), Obstacle::new(800.0, 11), Obstacle::new(1200.0, 12)];
         self.score = 0;
@@ -198,7 +219,13 @@ fn window_conf() -> Conf {
 #[macroquad::main(window_conf)]
 async fn main() {
 
-    let mut state = State::new();
+    let data = SheetData::load("assets/flapping_bird.json").await.unwrap();
+    let texture = load_texture(data.image.as_deref().unwrap()).await.unwrap();
+    // Whole frame if the sheet has no body
+    let size = data.rects[0].size();
+    let body = data.slice("body").cloned()
+        .unwrap_or_else(|| Slice { name: "body".to_owned(), rect: Rect::new(0., 0., size.x, size.y), pivot: None });
+    let mut state = State::new(data.sprite_sheet(texture), body);
 
     loop {
         // Process input
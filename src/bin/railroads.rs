@@ -1,10 +1,25 @@
-// Draw map on the screen
+// Draw map on the screen. Track pieces are placed on the tiles and trains run on them.
+//
+// Keys:
+//   1-6           select track piece: ━ ┃ ┛ ┗ ┓ ┏
+//   Left click    put the selected piece on the tile. Pieces on one tile make a junction
+//   Right click   remove all pieces from the tile
+//   S             throw the switches of the tile under the mouse
+//   T             add train on the tile under the mouse
+//   [ ]           zoom
+//   Arrows        move the map
+
+use std::collections::HashMap;
 
 use macroquad::prelude::*;
+use macroquad_sandbox::transnet::{Consist, Graph, GraphPos, Junction, Node, TileTracks, TrackPiece};
 
 
 const WINDOW_WIDTH: usize = 800;
 const WINDOW_HEIGHT: usize = 600;
+// Size of the tile in meters
+const TILE_SIZE: f32 = 100.;
+const TRAIN_SPEED: f32 = 20.;
 
 
 #[derive(Clone)]
@@ -12,37 +27,23 @@ struct Tile {
     color: Color,
 }
 
-#[derive(Clone)]
-enum Track {
-    Straight, // ━
-    // NorthSouth, // │
-    // NorthWest, // ┐
-    // NorthEast, // ┌
-    // SouthWest, // ┘
-    // SouthEast, // └
-}
-
-#[allow(dead_code)]
-struct TrackPos {
-    track_id: usize,
-    distance: f32,
-}
-
 struct Map {
     width: usize,
     height: usize,
     tiles: Vec<Tile>,
 }
 
-#[allow(dead_code)]
 struct Train {
-    position: TrackPos,
+    position: GraphPos,
     speed: f32,
+    consist: Consist,
 }
 
 struct World {
     map: Map,
-    tracks: Vec<Option<Track>>,
+    tracks: TileTracks,
+    /// Selected turn at every node
+    switches: Vec<usize>,
     trains: Vec<Train>,
 }
 
@@ -53,12 +54,6 @@ impl Tile {
     }
 }
 
-impl TrackPos {
-    fn new(track_id: usize) -> Self {
-        Self { track_id, distance: 0. }
-    }
-}
-
 impl Map {
     fn new(width: usize, height: usize, tiles: Vec<Tile>) -> Self {
         Self {
@@ -75,45 +70,137 @@ impl Map {
         let idx = y * self.width + x;
         self.tiles.get(idx)
     }
+}
+
+
+impl Train {
+    fn new(position: GraphPos) -> Self {
+        Self { position, speed: TRAIN_SPEED, consist: Consist::uniform(3, 25., 3.) }
+    }
+
+    fn update(&mut self, graph: &Graph, switches: &[usize], dt: f32) {
+        let edge = &graph.edges[self.position.edge_id()];
+        let Some(next) = next_edge(graph, &self.position, switches) else {
+            if self.position.progress(edge.length()) >= edge.length() {
+                self.reverse(graph);
+            } else {
+                self.advance(graph, &[self.position.edge_id()], dt);
+            }
+            return;
+        };
+        self.advance(graph, &[self.position.edge_id(), next], dt);
+    }
+
+    fn advance(&mut self, graph: &Graph, route: &[usize], dt: f32) {
+        let position = graph.update_pos(&self.position, route, self.speed * dt);
+        self.consist.follow(graph, route, &self.position, &position);
+        self.position = position;
+    }
 
-    fn xy_to_idx(&self, x: usize, y: usize) -> usize {
-        y * self.width + x
+    // Turn back at the end of the track: the last car becomes the head
+    fn reverse(&mut self, graph: &Graph) {
+        let route = [self.position.edge_id()];
+        self.position = self.consist.reverse(graph, &self.position, &route);
     }
 }
 
+// Nodes are built at the same points every time, so the exact position identifies the node
+fn node_key(node: &Node) -> (u32, u32) {
+    (node.x.to_bits(), node.y.to_bits())
+}
 
-impl Train {
-    fn new(position: TrackPos) -> Self {
-        Self { position, speed: 0. }
+// Edge taken at the end of the current one, as set by the switch
+fn next_edge(graph: &Graph, pos: &GraphPos, switches: &[usize]) -> Option<usize> {
+    let node_id = graph.edges[pos.edge_id()].end_node(pos.is_reversed());
+    let options: Vec<usize> = (0..graph.edges.len())
+        .filter(|&e| graph.turn(node_id, pos.edge_id(), e).is_some())
+        .collect();
+    if options.is_empty() {
+        return None;
     }
+    Some(options[switches.get(node_id).copied().unwrap_or(0) % options.len()])
 }
 
 
 impl World {
     fn new(map: Map) -> Self {
-        let tracks = vec![None; map.width*map.height];
-        Self { map, tracks, trains: vec![] }
-    }
-    
-    fn put_track(&mut self, x: usize, y: usize, track: Track) -> usize {
-        let idx = self.map.xy_to_idx(x, y);
-        if idx < self.tracks.len() {
-            self.tracks[idx] = Some(track);
-        }
+        let tracks = TileTracks::new(map.width, map.height, TILE_SIZE);
+        Self { map, tracks, switches: vec![], trains: vec![] }
+    }
 
-        idx
+    fn put_track(&mut self, x: usize, y: usize, piece: TrackPiece) {
+        self.change_tracks(|tracks| tracks.put(x, y, piece));
     }
 
-    fn track_at(&self, x: usize, y: usize) -> Option<&Track> {
-        let idx = self.map.xy_to_idx(x, y);
-        self.tracks.get(idx).map(|v| v.as_ref()).unwrap_or(None)
+    fn clear_tile(&mut self, x: usize, y: usize) {
+        self.change_tracks(|tracks| tracks.clear(x, y));
     }
 
-    fn add_train(&mut self, train: Train) {
-        self.trains.push(train);
+    // Edge and node ids change when the graph is built again, so trains are moved to the new
+    // edges of their pieces and switches to the nodes at the same place. Trains on the removed
+    // pieces are removed
+    fn change_tracks(&mut self, change: impl FnOnce(&mut TileTracks) -> bool) {
+        let graph = self.tracks.graph();
+        let pieces: Vec<_> = (0..graph.edges.len())
+            .map(|edge_id| self.tracks.piece_of(edge_id))
+            .collect();
+        let settings: HashMap<(u32, u32), usize> = graph.nodes.iter().zip(&self.switches)
+            .map(|(node, &switch)| (node_key(node), switch))
+            .collect();
+        if !change(&mut self.tracks) {
+            return;
+        }
+        let tracks = &self.tracks;
+        let new_edge = |edge_id: usize| {
+            let (x, y, piece) = pieces.get(edge_id).copied().flatten()?;
+            tracks.edge_of(x, y, piece)
+        };
+        let trains = std::mem::take(&mut self.trains);
+        self.trains = trains.into_iter()
+            .filter_map(|mut train| {
+                let edge_id = new_edge(train.position.edge_id())?;
+                let pos = &train.position;
+                train.position = if pos.is_reversed() {
+                    GraphPos::new_reversed(edge_id, pos.distance())
+                } else {
+                    GraphPos::new(edge_id, pos.distance())
+                };
+                train.consist.map_trail(new_edge);
+                Some(train)
+            })
+            .collect();
+        self.switches = tracks.graph().nodes.iter()
+            .map(|node| settings.get(&node_key(node)).copied().unwrap_or(0))
+            .collect();
     }
 
-    fn update(&mut self, _dt: f32) {
+    // Select the next turn at every junction of the tile
+    fn throw_switches(&mut self, x: usize, y: usize) {
+        let graph = self.tracks.graph();
+        for &piece in self.tracks.pieces_at(x, y) {
+            let Some(edge_id) = self.tracks.edge_of(x, y, piece) else {
+                continue;
+            };
+            let edge = &graph.edges[edge_id];
+            for node_id in [edge.from_node_id, edge.to_node_id] {
+                if graph.junctions[node_id] != Junction::Free {
+                    self.switches[node_id] += 1;
+                }
+            }
+        }
+    }
+
+    fn add_train(&mut self, x: usize, y: usize) {
+        if let Some(edge_id) = self.tracks.pieces_at(x, y).first().and_then(|&p| self.tracks.edge_of(x, y, p)) {
+            self.trains.push(Train::new(GraphPos::init(edge_id)));
+        }
+    }
+
+    fn update(&mut self, dt: f32) {
+        let graph = self.tracks.graph();
+        for train in &mut self.trains {
+            train.update(graph, &self.switches, dt);
+        }
     }
 }
 
@@ -166,7 +253,26 @@ impl MapView {
         }
     }
 
-    fn draw(&self, world: &World) {
+    // Screen pixels per meter
+    fn pixels(&self, map: &Map) -> (f32, f32) {
+        let cell_dx = self.scale * (WINDOW_WIDTH / map.width) as f32;
+        let cell_dy = self.scale * (WINDOW_HEIGHT / map.height) as f32;
+        (cell_dx / TILE_SIZE, cell_dy / TILE_SIZE)
+    }
+
+    fn to_screen(&self, map: &Map, point: Node) -> Vec2 {
+        let (kx, ky) = self.pixels(map);
+        vec2(point.x * kx + self.pos_x, point.y * ky + self.pos_y)
+    }
+
+    // Tile under the mouse
+    fn mouse_tile(&self, world: &World) -> Option<(usize, usize)> {
+        let (x, y) = mouse_position();
+        let (kx, ky) = self.pixels(&world.map);
+        world.tracks.tile_at(Node::new((x - self.pos_x) / kx, (y - self.pos_y) / ky))
+    }
+
+    fn draw(&self, world: &World, piece: TrackPiece) {
         let map = &world.map;
         let cell_dx = self.scale * (WINDOW_WIDTH / map.width) as f32;
         let cell_dy = self.scale * (WINDOW_HEIGHT / map.height) as f32;
@@ -178,52 +284,45 @@ impl MapView {
                 let rect_x = x as f32 * cell_dx + self.pos_x;
                 let rect_y = y as f32 * cell_dy + self.pos_y;
                 draw_rectangle(
-                    rect_x, 
-                    rect_y, 
-                    cell_dx, 
+                    rect_x,
+                    rect_y,
+                    cell_dx,
                     cell_dy, color);
-
-                if let Some(track) = world.track_at(x, y) {
-                    self.draw_track(rect_x, rect_y, cell_dx, cell_dy, track)
-                }
             }
         }
+        let graph = world.tracks.graph();
+        for edge in &graph.edges {
+            self.draw_track(map, &edge.curve().points(5.));
+        }
+        for train in &world.trains {
+            self.draw_train(map, graph, train);
+        }
+        draw_text(format!("Track: {:?}", piece), 10., 20., 20., BLACK);
     }
 
-    fn draw_track(&self, x: f32, y: f32, dx: f32, dy: f32, track: &Track) {
-        // let center_x = x + dx /2.;
-        let center_y = y + dy /2.;
-        let end_x = x + dx;
-        // let end_y = y + dy;
+    fn draw_track(&self, map: &Map, points: &[Node]) {
         let thickness = 1.;
         let color = DARKBROWN;
-        match track {
-            Track::Straight => {
-                let up = center_y - 4.;
-                let down = center_y + 4.;
-                draw_line(x, center_y, end_x, center_y, 12., color);
-                draw_line(x, up, end_x, up, thickness, GRAY);
-                draw_line(x, down, end_x, down, thickness, GRAY);
+        for segment in points.windows(2) {
+            let (p1, p2) = (self.to_screen(map, segment[0]), self.to_screen(map, segment[1]));
+            draw_line(p1.x, p1.y, p2.x, p2.y, 12. * self.scale, color);
+        }
+        for segment in points.windows(2) {
+            let (p1, p2) = (self.to_screen(map, segment[0]), self.to_screen(map, segment[1]));
+            let normal = (p2 - p1).normalize_or_zero().perp() * 4. * self.scale;
+            for side in [normal, -normal] {
+                draw_line(p1.x + side.x, p1.y + side.y, p2.x + side.x, p2.y + side.y, thickness, GRAY);
             }
-            // Track::NorthSouth => {
-            //     draw_line(center_x, y, center_x, end_y, thickness, color);
-            // }
-            // Track::NorthWest => {
-            //     draw_line(center_x, end_y, center_x, center_y, thickness, color);
-            //     draw_line(center_x, center_y, x, center_y, thickness, color);
-            // }
-            // Track::NorthEast => {
-            //     draw_line(center_x, end_y, center_x, center_y, thickness, color);
-            //     draw_line(center_x, center_y, end_x, center_y, thickness, color);
-            // }
-            // Track::SouthWest => {
-            //     draw_line(center_x, y, center_x, center_y, thickness, color);
-            //     draw_line(center_x, center_y, x, center_y, thickness, color);
-            // }
-            // Track::SouthEast => {
-            //     draw_line(center_x, y, center_x, center_y, thickness, color);
-            //     draw_line(center_x, center_y, end_x, center_y, thickness, color);
-            // }
+        }
+    }
+
+    fn draw_train(&self, map: &Map, graph: &Graph, train: &Train) {
+        let route = [train.position.edge_id()];
+        for (i, car) in train.consist.positions(graph, &train.position, &route).iter().enumerate() {
+            let front = self.to_screen(map, graph.pos_to_location(&car.front).point);
+            let rear = self.to_screen(map, graph.pos_to_location(&car.rear).point);
+            let color = if i == 0 { MAROON } else { RED };
+            draw_line(front.x, front.y, rear.x, rear.y, 8. * self.scale, color);
         }
     }
 }
@@ -235,15 +334,24 @@ fn init_world() -> World {
     let map = Map::new(width, height, tiles);
     let mut world = World::new(map);
 
-    let id = world.put_track(1, 2, Track::Straight);
-    world.put_track(2, 2, Track::Straight);
-    world.put_track(3, 2, Track::Straight);
-    world.put_track(4, 2, Track::Straight);
-    world.put_track(5, 2, Track::Straight);
-    world.put_track(6, 2, Track::Straight);
+    // Loop with a siding
+    world.put_track(1, 1, TrackPiece::SouthEast);
+    for x in 2..6 {
+        world.put_track(x, 1, TrackPiece::Horizontal);
+        world.put_track(x, 4, TrackPiece::Horizontal);
+    }
+    world.put_track(6, 1, TrackPiece::SouthWest);
+    for y in 2..4 {
+        world.put_track(1, y, TrackPiece::Vertical);
+        world.put_track(6, y, TrackPiece::Vertical);
+    }
+    world.put_track(1, 4, TrackPiece::NorthEast);
+    world.put_track(6, 4, TrackPiece::NorthWest);
+    world.put_track(2, 4, TrackPiece::NorthEast);
+    world.put_track(2, 3, TrackPiece::SouthEast);
+    world.put_track(3, 3, TrackPiece::Horizontal);
 
-    let train = Train::new(TrackPos::new(id));
-    world.add_train(train);
+    world.add_train(3, 1);
     world
 }
 
@@ -261,6 +369,7 @@ fn window_conf() -> Conf {
 async fn main() {
     let mut map_view = MapView::new();
     let mut world = init_world();
+    let mut piece = TrackPiece::Horizontal;
 
     loop {
         let dt = get_frame_time();
@@ -288,11 +397,31 @@ async fn main() {
         if is_key_down(KeyCode::Down) {
             map_view.move_down(dt);
         }
-        // Update world 
+        let keys = [KeyCode::Key1, KeyCode::Key2, KeyCode::Key3, KeyCode::Key4, KeyCode::Key5, KeyCode::Key6];
+        for (key, p) in keys.into_iter().zip(TrackPiece::ALL) {
+            if is_key_pressed(key) {
+                piece = p;
+            }
+        }
+        if let Some((x, y)) = map_view.mouse_tile(&world) {
+            if is_mouse_button_pressed(MouseButton::Left) {
+                world.put_track(x, y, piece);
+            }
+            if is_mouse_button_pressed(MouseButton::Right) {
+                world.clear_tile(x, y);
+            }
+            if is_key_pressed(KeyCode::S) {
+                world.throw_switches(x, y);
+            }
+            if is_key_pressed(KeyCode::T) {
+                world.add_train(x, y);
+            }
+        }
+        // Update world
         world.update(dt);
         // Draw world
-        map_view.draw(&world);
-        
+        map_view.draw(&world, piece);
+
         next_frame().await
     }
-}
\ No newline at end of file
+}
@@ -1,26 +1,29 @@
 // “Escape! Code Your Way Out of a Paper Bag”
 //
+// Usage: rts [--scenario <file>]
+//
+
+use std::path::Path;
 
+use anyhow::Result;
 use macroquad::prelude::*;
 use macroquad_sandbox::rts::*;
-use mapgen::{Map, MapBuilder, filter::*};
+use macroquad_sandbox::rts::scenario::Scenario;
 
 
 const SCREEN_WIDTH: usize = 1200;
 const SCREEN_HEIGHT: usize = 900;
+const DEFAULT_SCENARIO: &str = include_str!("../../params/rts/paper_bag.toml");
 
 
-fn random_map(ncols: usize, nrows: usize) -> Map {
-    MapBuilder::new(ncols, nrows)
-        .with(NoiseGenerator::uniform())
-        .with(CellularAutomata::new())
-        .with(AreaStartingPosition::new(XStart::CENTER, YStart::CENTER))
-        .with(CullUnreachable::new())
-        .with(DistantExit::new())
-        .build()
+fn scenario_arg() -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    args.iter()
+        .position(|a| a == "--scenario")
+        .and_then(|idx| args.get(idx + 1).cloned())
 }
 
-fn draw(universe: &Universe) {
+fn draw(universe: &Universe, winner: Option<usize>) {
     let cell_dx = (SCREEN_WIDTH / universe.map.width) as f32;
     let cell_dy = (SCREEN_HEIGHT / universe.map.height) as f32;
 
@@ -36,15 +39,14 @@ fn draw(universe: &Universe) {
         }
     }
 
-    if universe.path.len() > 1 {
-        let mut last_x = universe.path[0].x * cell_dx;
-        let mut last_y = universe.path[0].y * cell_dy;
-        for i in 1..universe.path.len() {
-            let x = universe.path[i].x * cell_dx;
-            let y = universe.path[i].y * cell_dy;
-            draw_line(last_x, last_y, x, y, 1., GREEN);
-            last_x = x;
-            last_y = y;
+    for unit in &universe.units {
+        for segment in unit.path.windows(2) {
+            draw_line(
+                segment[0].x * cell_dx, 
+                segment[0].y * cell_dy, 
+                segment[1].x * cell_dx, 
+                segment[1].y * cell_dy, 
+                1., GREEN);
         }
     }
 
@@ -52,6 +54,10 @@ fn draw(universe: &Universe) {
         let color = if unit.is_moving { RED } else { BLUE };
         draw_circle(unit.pos.x * cell_dx, unit.pos.y * cell_dy, 5.0, color);
     }
+
+    if let Some(player) = winner.and_then(|idx| universe.players.get(idx)) {
+        draw_text(format!("{} wins!", player.name), 20.0, 40.0, 40.0, DARKGREEN);
+    }
 }
 
 
@@ -66,13 +72,12 @@ fn window_conf() -> Conf {
 }
 
 #[macroquad::main(window_conf)]
-async fn main() {
-    let map = random_map(80, 60);
-    let data: Vec<bool> = map.tiles.iter().map(|&t| t.is_blocked()).collect();
-    let world_map = WorldMap::from_data(map.width, map.height, &data);
-    let mut universe = Universe::from_map(world_map);
-    let sp = map.starting_point.unwrap();
-    universe.add_unit(sp.x, sp.y);
+async fn main() -> Result<()> {
+    let scenario = match scenario_arg() {
+        Some(path) => Scenario::load(path)?,
+        None => Scenario::parse(DEFAULT_SCENARIO, Path::new("params/rts"))?,
+    };
+    let mut universe = scenario.universe();
 
     loop {
         if is_key_down(KeyCode::Q) | is_key_down(KeyCode::Escape) {
@@ -80,15 +85,20 @@ async fn main() {
         }
 
         universe.tick();
+        let winner = scenario.winner(&universe);
 
         // Process mouse
         if is_mouse_button_pressed(MouseButton::Left) {
             let (x, y) = mouse_position();
-            universe.move_to(x as usize * 80 / SCREEN_WIDTH , y as usize * 60 / SCREEN_HEIGHT);
+            universe.move_to(
+                x as usize * universe.map.width / SCREEN_WIDTH,
+                y as usize * universe.map.height / SCREEN_HEIGHT);
         }
 
-        draw(&universe);
+        draw(&universe, winner);
 
         next_frame().await
     }
+
+    Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+// Run RTS simulation without the window and print movement metrics as JSON.
+//
+// Usage: rts_headless [--scenario <file>] [--commands <file> | --ai] [--seed <n>] [--ticks <n>]
+//
+// The seed drives the AI and the map generator (unless the scenario has its own map seed), so
+// the same arguments give the same metrics.
+
+use std::fs;
+
+use anyhow::{bail, Context, Result};
+use macroquad_sandbox::rts::scenario::Scenario;
+use macroquad_sandbox::rts::simulation::{parse_commands, Driver, Simulation};
+
+
+const DEFAULT_SCENARIO: &str = "params/rts/paper_bag.toml";
+const DEFAULT_TICKS: u64 = 1000;
+
+
+struct Args {
+    scenario: String,
+    commands: Option<String>,
+    ai: bool,
+    seed: u64,
+    ticks: u64,
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        scenario: DEFAULT_SCENARIO.to_owned(),
+        commands: None,
+        ai: false,
+        seed: 0,
+        ticks: DEFAULT_TICKS,
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(arg) = it.next() {
+        let mut value = || it.next().with_context(|| format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--scenario" => args.scenario = value()?,
+            "--commands" => args.commands = Some(value()?),
+            "--ai" => args.ai = true,
+            "--seed" => args.seed = value()?.parse().context("Invalid seed")?,
+            "--ticks" => args.ticks = value()?.parse().context("Invalid number of ticks")?,
+            _ => bail!("Unknown argument: {}", arg),
+        }
+    }
+    if args.ai && args.commands.is_some() {
+        bail!("Use either --commands or --ai");
+    }
+    Ok(args)
+}
+
+fn main() -> Result<()> {
+    let args = parse_args()?;
+    let scenario = Scenario::load_seeded(&args.scenario, Some(args.seed))?;
+    let driver = match &args.commands {
+        Some(path) => {
+            let text = fs::read_to_string(path).with_context(|| format!("Can't read {}", path))?;
+            Driver::Script(parse_commands(&text)?)
+        }
+        None if args.ai => Driver::Wander { seed: args.seed },
+        None => Driver::Script(vec![]),
+    };
+
+    let mut simulation = Simulation::new(scenario.universe(), driver);
+    simulation.run(args.ticks);
+    println!("{}", serde_json::to_string_pretty(&simulation.metrics())?);
+    Ok(())
+}
@@ -1,73 +1,25 @@
-// Balls colliding inside the box
+// Animated sprite
 //
+// Bird flaps while the Space is held and dives when released. Dive waits until the flap ends.
+//
+// Keys:
+//   Space       flap
+//   L / P / O   loop / ping-pong / once
+//   F           flip horizontally
+//   Up / Down   scale
 
 use macroquad::prelude::*;
+use macroquad_sandbox::mqx::animation::PlayMode;
+use macroquad_sandbox::mqx::animator::{AnimState, Animator, Condition, FrameEvent, Transition};
+use macroquad_sandbox::mqx::import::load_sprite_sheet;
 
 
 const WINDOW_WIDTH: i32 = 1024;
 const WINDOW_HEIGHT: i32 = 800;
-
-
-struct FrameSeq {
-    pub start: i32,
-    pub end: i32,
-}
-
-struct Frames {
-    texture: Texture2D,
-    num_cols: i32,
-    sequences: Vec<FrameSeq>,
-}
-
-struct Sprite<'a> {
-    frames: &'a Frames,
-    fps: f32,
-    time: f32,
-}
-
-impl FrameSeq {
-    fn new(start: i32, end: i32) -> FrameSeq {
-        FrameSeq { start, end }
-    }
-}
-
-impl Frames {
-    pub fn new(texture: Texture2D, num_cols: i32, sequences: Vec<FrameSeq>) -> Self {
-        Self {
-            texture,
-            num_cols,
-            sequences
-        }
-    }
-
-    fn draw(&self, pos: Vec2, seq_id: usize, frame: i32) {
-        let seq = self.sequences.get(seq_id).unwrap();
-        let frame_id = frame % (seq.end - seq.start) + seq.start;
-        let r = frame_id / self.num_cols;
-        let c = frame_id % self.num_cols;
-        let params = DrawTextureParams {
-                dest_size: None,
-                source: Some(Rect::new(c as f32 * 184., r as f32 * 184., 184., 184.)),
-                rotation: 0.,
-                pivot: None,
-                flip_x: false,
-                flip_y: false,
-            };
-        draw_texture_ex(&self.texture, pos.x, pos.y, WHITE, params);
-    }
-}
-
-impl<'a> Sprite<'a> {
-    fn new(frames: &'a Frames, fps: f32) -> Self {
-        Self { frames, fps, time: 0. }
-    }
-
-    fn draw(&mut self, pos: Vec2, dt: f32) {
-        self.time += dt;
-        let frame_id = (self.time * self.fps) as i32;
-        self.frames.draw(pos, 0, frame_id); 
-    }
-}
+/// Crossfade between flapping and diving (s)
+const FADE: f32 = 0.15;
+/// Frame with the wings down
+const WINGBEAT_FRAME: usize = 15;
 
 
 fn window_conf() -> Conf {
@@ -83,11 +35,23 @@ fn window_conf() -> Conf {
 #[macroquad::main(window_conf)]
 async fn main() {
 
-    let texture = load_texture("assets/flapping_bird.png").await.unwrap();
-    let frames = Frames::new(texture, 5, 
-        vec![FrameSeq::new(11, 20), FrameSeq::new(21, 23)]);
-    let mut sprite = Sprite::new(&frames, 20.);
-    let pos = vec2(((WINDOW_WIDTH - 184) / 2) as f32, ((WINDOW_HEIGHT - 184) / 2) as f32);
+    // Exported from Aseprite with the tags
+    let mut sheet = load_sprite_sheet("assets/flapping_bird.json").await.unwrap();
+    let flap = sheet.clip_id("flap").unwrap();
+    let dive = sheet.clip_id("dive").unwrap();
+    let flapping = || vec![Condition::If("flapping".to_owned())];
+    let not_flapping = || vec![Condition::IfNot("flapping".to_owned())];
+    let mut animator = Animator::new(
+        vec![AnimState::new("dive", dive), AnimState::new("flap", flap)],
+        vec![
+            Transition { fade: FADE, ..Transition::new(Some(0), 1, flapping()) },
+            Transition { fade: FADE, exit_time: Some(1.), ..Transition::new(Some(1), 0, not_flapping()) },
+        ],
+        vec![FrameEvent::new(flap, WINGBEAT_FRAME, "wingbeat")],
+    );
+    animator.player_mut().pivot = vec2(0.5, 0.5);
+    let pos = vec2((WINDOW_WIDTH / 2) as f32, (WINDOW_HEIGHT / 2) as f32);
+    let mut wingbeats = 0;
 
     loop {
         let dt = get_frame_time();
@@ -96,11 +60,36 @@ async fn main() {
         if is_key_down(KeyCode::Q) | is_key_down(KeyCode::Escape) {
             break;
         }
+        animator.set_bool("flapping", is_key_down(KeyCode::Space));
+        for (key, mode) in [(KeyCode::L, PlayMode::Loop), (KeyCode::P, PlayMode::PingPong), (KeyCode::O, PlayMode::Once)] {
+            if is_key_pressed(key) {
+                sheet.clips[animator.player().clip()].mode = mode;
+                animator.player_mut().restart();
+            }
+        }
+        let player = animator.player_mut();
+        if is_key_pressed(KeyCode::F) {
+            player.flip_x = !player.flip_x;
+        }
+        if is_key_down(KeyCode::Up) {
+            player.scale = (player.scale + dt).min(Vec2::splat(3.));
+        }
+        if is_key_down(KeyCode::Down) {
+            player.scale = (player.scale - dt).max(Vec2::splat(0.2));
+        }
+        for event in animator.update(dt, &sheet.clips) {
+            if event == "wingbeat" {
+                wingbeats += 1;
+            }
+        }
 
         // Draw universe
         clear_background(WHITE);
 
-        sprite.draw(pos, dt);
+        animator.draw(&sheet, pos);
+        let clip = &sheet.clips[animator.player().clip()];
+        draw_text(format!("{} ({:?})", animator.state_name(), clip.mode), 10., 30., 30., BLACK);
+        draw_text(format!("Wingbeats: {}", wingbeats), 10., 60., 30., BLACK);
         next_frame().await
     }
-}
\ No newline at end of file
+}
@@ -1,17 +1,48 @@
 // Draw map on the screen
 //
+// Usage: trans [<network file>]
+//
+// Press E to edit the track network (simulation is paused):
+// * Left click - add node, left drag from the node - connect it (to the new node on the empty space)
+// * Right drag - move node
+// * X / Delete - remove node or edge under the mouse
+// * G - snap to the tile grid, Ctrl+Z / Ctrl+Y - undo / redo
+// * Ctrl+S / Ctrl+O - save / load the graph (to the network file, if it was given)
+// Editing the network removes trains, stations and signals, also from the file when it is saved.
+// Click the track to see the edge and the position on it.
+// Press R to show passengers, C to show road congestion. P prints punctuality, ridership and
+// road traffic reports.
+// Press V to show the analysis: strongly connected parts of the network, nodes reachable from the
+// edge under the mouse and problems found in the network.
+//
 // TODO
 // * Lines
-// * Cars 
-// * Circle based lines
-// * Stations
 
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
 use macroquad::prelude::*;
-use macroquad_sandbox::transnet::{Edge, Node, GraphPos, Graph};
+use macroquad_sandbox::mqx::plot::Plot;
+use macroquad_sandbox::transnet::{
+    Clock, Consist, Dynamics, Edge, EdgeHit, Node, GraphPos, Graph, GraphIssue, Light, NetworkFile, PunctualityReport,
+    Ridership, Roads, RoadsDef, Service, Signalling, CAR_LENGTH, LANE_WIDTH, SpatialIndex, SpeedProfile, Station, TrainDef,
+};
 
 const WINDOW_WIDTH: usize = 800;
 const WINDOW_HEIGHT: usize = 600;
 
+// Length of the speed profile shown on the plot
+const PROFILE_LENGTH: f32 = 2000.;
+
+// Size of the tile in pixels. Editor can snap nodes to the tiles
+const GRID: f32 = 10.;
+// How close the mouse has to be to pick the node or the edge
+const PICK_DISTANCE: f32 = 10.;
+const MAX_UNDO: usize = 100;
+// Editor saves here if the network file wasn't given
+const NETWORK_FILE: &str = "params/trans/network.toml";
+const DEFAULT_NETWORK: &str = include_str!("../../params/trans/triangle.toml");
+
 const COLORS: [Color; 10] = [PINK, BLUE, BEIGE, YELLOW, DARKBROWN, ORANGE, PINK, RED, MAROON, DARKPURPLE]; 
 
 
@@ -27,16 +58,48 @@ struct Map {
 }
 
 struct Train {
+    // Position of the head
     pos: GraphPos,
-    speed: f32,
+    consist: Consist,
+    dynamics: Dynamics,
+    // Recent speed profile
+    profile: SpeedProfile,
     schedule_id: usize,
 }
 
 struct World {
     map: Map,
     trans_net: Graph,
+    signals: Signalling,
+    stations: Vec<Station>,
     schedules: Vec<Vec<usize>>,
     trains: Vec<Train>,
+    // Timetable of every train
+    services: Vec<Service>,
+    ridership: Ridership,
+    // Index of the tracks for finding what was clicked
+    index: SpatialIndex,
+    roads: Option<Roads>,
+    clock: Clock,
+}
+
+enum Drag {
+    // New edge from the node
+    Connect(usize),
+    // Node follows the mouse. Graph is remembered for undo when the node moves for the first time
+    Move { node_id: usize, moved: bool },
+}
+
+struct Editor {
+    path: PathBuf,
+    active: bool,
+    snap: bool,
+    drag: Option<Drag>,
+    // Graph before every change
+    undo: Vec<Graph>,
+    redo: Vec<Graph>,
+    // Graph was changed in this update
+    changed: bool,
 }
 
 
@@ -69,48 +132,255 @@ impl Map {
 }
 
 impl Train {
-    fn new(speed: f32, station_id: usize, route_id: usize) -> Self {
+    fn new(def: &TrainDef) -> Self {
         Self { 
-            pos: GraphPos::init(station_id),
-            speed,
-            schedule_id: route_id,
+            pos: def.pos(),
+            consist: def.consist(),
+            dynamics: Dynamics::new(def.spec()),
+            profile: SpeedProfile::default(),
+            schedule_id: def.schedule,
         }
     }
 
     fn update_pos(&mut self, new_pos: GraphPos) {
         self.pos = new_pos;
+        self.profile.record(self.dynamics.odometer, self.dynamics.speed);
+        // Keep only the last 2km
+        let start = self.dynamics.odometer - PROFILE_LENGTH;
+        self.profile.points.retain(|p| p.0 >= start);
     }
 }
 
 impl World {
-    fn new(map: Map, trans_net: Graph, schedules: Vec<Vec<usize>>, trains: Vec<Train>) -> Self {
-        Self { 
-            map, 
-            trans_net,
-            schedules,
-            trains,
-        }
-    }
-    
     fn update(&mut self, dt: f32) {
-        for train in self.trains .iter_mut() {
+        let dt = self.clock.tick(dt);
+        let time = self.clock.time;
+        for (idx, (train, service)) in self.trains.iter_mut().zip(&mut self.services).enumerate() {
             let route = &self.schedules[train.schedule_id];
-            train.update_pos(self.trans_net.update_pos(&train.pos, route, train.speed * dt));
+            let stop = service.max_distance(&self.trans_net, &self.stations, &train.pos, route, time);
+            let lookahead = train.dynamics.lookahead();
+            let signal = self.signals.clear_distance(&self.trans_net, idx, &train.pos, route, lookahead);
+            let distance = train.dynamics.step(&self.trans_net, &train.pos, route, stop.min(signal), dt);
+            let new_pos = self.signals.advance(&self.trans_net, idx, &train.pos, route, distance);
+            train.consist.follow(&self.trans_net, route, &train.pos, &new_pos);
+            self.signals.set_occupied(idx, train.consist.occupied_edges(&self.trans_net, &new_pos, route));
+            service.update(&self.stations, &new_pos, time);
+            train.update_pos(new_pos);
+        }
+        self.ridership.update(&self.services, time, dt);
+        if let Some(roads) = &mut self.roads {
+            roads.update(dt);
+        }
+    }
+
+    fn punctuality(&self) -> PunctualityReport {
+        PunctualityReport::new(&self.services, &self.stations, self.clock.time)
+    }
+
+    // Trains, stations and signals refer to the edges, they aren't valid after the network changed
+    fn network_changed(&mut self) {
+        self.signals = Signalling::new(&self.trans_net);
+        self.stations.clear();
+        self.schedules.clear();
+        self.trains.clear();
+        self.services.clear();
+        self.ridership = Ridership::new(0, vec![], &[], vec![]);
+        self.index = self.trans_net.spatial_index();
+    }
+}
+
+
+impl Editor {
+    fn new(path: PathBuf) -> Self {
+        Self { path, active: false, snap: false, drag: None, undo: vec![], redo: vec![], changed: false }
+    }
+
+    // Process the input. Returns true if the graph was changed. Index is kept up to date with
+    // the edits made here, so the later picks see them
+    fn update(&mut self, graph: &mut Graph, index: &mut SpatialIndex) -> bool {
+        let (x, y) = mouse_position();
+        let mouse = Node::new(x, y);
+        let point = self.snap_point(mouse);
+        let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+        let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+        self.changed = false;
+
+        if is_key_pressed(KeyCode::G) {
+            self.snap = !self.snap;
+        }
+        if ctrl && is_key_pressed(KeyCode::Z) {
+            return if shift { self.redo(graph) } else { self.undo(graph) };
+        }
+        if ctrl && is_key_pressed(KeyCode::Y) {
+            return self.redo(graph);
+        }
+        if ctrl && is_key_pressed(KeyCode::S) {
+            match NetworkFile::save_graph(graph, &self.path) {
+                Ok(()) => println!("Network saved to {}", self.path.display()),
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+        if ctrl && is_key_pressed(KeyCode::O) {
+            match NetworkFile::load(&self.path).map(|file| file.to_graph()) {
+                Ok(loaded) => {
+                    self.snapshot(graph);
+                    *graph = loaded;
+                    *index = graph.spatial_index();
+                }
+                Err(err) => eprintln!("{}", err),
+            }
+        }
+
+        if is_key_pressed(KeyCode::X) || is_key_pressed(KeyCode::Delete) {
+            if let Some(node_id) = node_at(graph, index, mouse) {
+                self.snapshot(graph);
+                graph.remove_node(node_id);
+                *index = graph.spatial_index();
+            } else if let Some(edge_id) = edge_at(graph, index, mouse) {
+                self.snapshot(graph);
+                graph.remove_edge(edge_id);
+                *index = graph.spatial_index();
+            }
+        }
+
+        if is_mouse_button_pressed(MouseButton::Left) {
+            match node_at(graph, index, mouse) {
+                Some(node_id) => self.drag = Some(Drag::Connect(node_id)),
+                None => {
+                    self.snapshot(graph);
+                    graph.add_node(point);
+                    *index = graph.spatial_index();
+                }
+            }
+        }
+        if is_mouse_button_released(MouseButton::Left) {
+            if let Some(Drag::Connect(from)) = self.drag {
+                self.drag = None;
+                let to = node_at(graph, index, mouse);
+                if to != Some(from) {
+                    self.snapshot(graph);
+                    let to = to.unwrap_or_else(|| graph.add_node(point));
+                    graph.add_edge(Edge::new(from, to, &graph.nodes));
+                    *index = graph.spatial_index();
+                }
+            }
+        }
+
+        if is_mouse_button_pressed(MouseButton::Right) {
+            if let Some(node_id) = node_at(graph, index, mouse) {
+                self.drag = Some(Drag::Move { node_id, moved: false });
+            }
+        }
+        if let Some(Drag::Move { node_id, moved }) = self.drag {
+            let moves = graph.nodes[node_id] != point;
+            if moves && !moved {
+                self.snapshot(graph);
+            }
+            self.drag = if is_mouse_button_released(MouseButton::Right) {
+                None
+            } else {
+                Some(Drag::Move { node_id, moved: moved || moves })
+            };
+            if moves {
+                graph.move_node(node_id, point);
+                return true;
+            }
+        }
+
+        self.changed
+    }
+
+    fn snap_point(&self, point: Node) -> Node {
+        if self.snap {
+            Node::new((point.x / GRID).round() * GRID, (point.y / GRID).round() * GRID)
+        } else {
+            point
+        }
+    }
+
+    // Remember the graph before the change
+    fn snapshot(&mut self, graph: &Graph) {
+        self.changed = true;
+        self.undo.push(graph.clone());
+        if self.undo.len() > MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.redo.clear();
+    }
+
+    fn undo(&mut self, graph: &mut Graph) -> bool {
+        let Some(prev) = self.undo.pop() else {
+            return false;
+        };
+        self.redo.push(std::mem::replace(graph, prev));
+        true
+    }
+
+    fn redo(&mut self, graph: &mut Graph) -> bool {
+        let Some(next) = self.redo.pop() else {
+            return false;
+        };
+        self.undo.push(std::mem::replace(graph, next));
+        true
+    }
+
+    fn draw(&self, graph: &Graph, index: &SpatialIndex) {
+        let (x, y) = mouse_position();
+        let mouse = Node::new(x, y);
+        let hover = node_at(graph, index, mouse);
+        for (node_id, node) in graph.nodes.iter().enumerate() {
+            let color = if hover == Some(node_id) { YELLOW } else { WHITE };
+            draw_circle(node.x, node.y, 6., BLACK);
+            draw_circle(node.x, node.y, 4., color);
         }
+        if hover.is_none() {
+            if let Some(edge_id) = edge_at(graph, index, mouse) {
+                for segment in graph.edges[edge_id].curve().points(10.).windows(2) {
+                    draw_line(segment[0].x, segment[0].y, segment[1].x, segment[1].y, 2., YELLOW);
+                }
+            }
+        }
+        if let Some(Drag::Connect(from)) = self.drag {
+            let start = graph.nodes[from];
+            let end = self.snap_point(mouse);
+            draw_line(start.x, start.y, end.x, end.y, 2., WHITE);
+        }
+
+        let snap = if self.snap { "on" } else { "off" };
+        let status = format!("EDIT  snap: {}  undo: {}  redo: {}", snap, self.undo.len(), self.redo.len());
+        draw_text(status, 10., WINDOW_HEIGHT as f32 - 10., 20., WHITE);
     }
 }
 
+// Node closest to the point, if it is close enough
+fn node_at(graph: &Graph, index: &SpatialIndex, point: Node) -> Option<usize> {
+    index.nearest_node(graph, point, PICK_DISTANCE)
+}
+
+// Edge closest to the point, if it is close enough
+fn edge_at(graph: &Graph, index: &SpatialIndex, point: Node) -> Option<usize> {
+    index.nearest_edge(graph, point, PICK_DISTANCE).map(|hit| hit.edge_id)
+}
+
 
 #[derive(Debug)]
 struct WorldView {
     scale: f32,
     pos_x: f32,
     pos_y: f32,
+    // Show the network analysis
+    analysis: bool,
+    // Show passengers
+    ridership: bool,
+    // Show road congestion
+    congestion: bool,
+    // Clicked point on the track
+    selected: Option<EdgeHit>,
 }
 
 impl WorldView {
     pub fn new() -> Self {
-        Self {scale: 1., pos_x: 0.0, pos_y: 0.0}
+        Self {scale: 1., pos_x: 0.0, pos_y: 0.0, analysis: false, ridership: false, congestion: false, selected: None}
     }
 
     pub fn zoom_in(&mut self, dt: f32) {
@@ -167,25 +437,161 @@ impl WorldView {
                     cell_dy, color);
             }
         }
-        self.draw_connections(&world.trans_net);
+        if let Some(roads) = &world.roads {
+            self.draw_roads(roads);
+        }
+        self.draw_connections(&world.trans_net, &world.signals);
+        for station in &world.stations {
+            let pos = world.trans_net.pos_to_location(&station.pos).point;
+            draw_rectangle(pos.x - 12., pos.y - 12., 24., 24., GRAY);
+            draw_text(&station.name, pos.x + 14., pos.y - 14., 20., BLACK);
+        }
+
+        for (idx, train) in world.trains.iter().enumerate() {
+            let location = world.trans_net.pos_to_location(&train.pos);
+            let route = &world.schedules[train.schedule_id];
+            self.draw_train(&world.trans_net, train, route, COLORS[idx % COLORS.len()]);
+            // Red light over the train stopped at the signal
+            if world.signals.waiting_for(idx).is_some() {
+                draw_circle(location.point.x, location.point.y - 15., 4., RED);
+            }
+        }
+        draw_text(world.clock.to_string(), 10., 20., 24., BLACK);
+
+        let series = world.trains.first().map(|train| train.profile.series(10.)).unwrap_or_default();
+        if series.iter().any(|&v| v > 0.) {
+            let plot = Plot::new("Train 1 speed", vec2(560., 440.), vec2(230., 150.));
+            plot.plot(&series, COLORS[0]);
+        }
+        if let Some(hit) = self.selected {
+            draw_circle_lines(hit.point.x, hit.point.y, 8., 2., WHITE);
+            let text = format!("edge {} at {:.0} m", hit.edge_id, hit.pos.distance());
+            draw_text(text, hit.point.x + 10., hit.point.y + 24., 18., WHITE);
+        }
+        if self.analysis {
+            self.draw_analysis(&world.trans_net, &world.index);
+        }
+        if self.ridership {
+            self.draw_ridership(world);
+        }
+    }
+
+    fn draw_roads(&self, roads: &Roads) {
+        for (edge_id, edge) in roads.graph.edges.iter().enumerate() {
+            // Lanes are on the right side of the edge
+            let width = roads.lanes[edge_id] as f32 * LANE_WIDTH;
+            let color = if self.congestion {
+                Color::from_vec(GREEN.to_vec().lerp(RED.to_vec(), roads.congestion(edge_id)))
+            } else {
+                DARKGRAY
+            };
+            for segment in edge.curve().points(10.).windows(2) {
+                let (p1, p2) = (vec2(segment[0].x, segment[0].y), vec2(segment[1].x, segment[1].y));
+                let right = (p2 - p1).normalize_or_zero().perp() * width / 2.;
+                draw_line(p1.x + right.x, p1.y + right.y, p2.x + right.x, p2.y + right.y, width, color);
+            }
+            let node_id = edge.to_node_id;
+            if let Some(light) = roads.light(node_id, edge_id) {
+                let end = edge.curve().at(edge.length() - 2.);
+                let (sin, cos) = end.heading.sin_cos();
+                let color = match light {
+                    Light::Green => GREEN,
+                    Light::Amber => ORANGE,
+                    Light::Red => RED,
+                };
+                draw_circle(end.point.x - sin * (width + 3.), end.point.y + cos * (width + 3.), 2.5, color);
+            }
+        }
+        for vehicle in &roads.vehicles {
+            let location = roads.location(vehicle);
+            let params = DrawRectangleParams { offset: vec2(0.5, 0.5), rotation: location.heading, color: BLUE };
+            draw_rectangle_ex(location.point.x, location.point.y, CAR_LENGTH, 2., params);
+        }
+        if self.congestion {
+            let x = 10.;
+            let y = WINDOW_HEIGHT as f32 - 30. - 20. * roads.graph.edges.len() as f32;
+            draw_rectangle(x - 5., y - 20., 300., 30. + 20. * roads.graph.edges.len() as f32, Color::new(0., 0., 0., 0.6));
+            draw_text("road  cars/h  congestion  queue", x, y - 2., 18., WHITE);
+            for edge_id in 0..roads.graph.edges.len() {
+                let row = format!("{:<4} {:>7.0} {:>10.0}% {:>6}",
+                    edge_id, roads.throughput(edge_id), 100. * roads.congestion(edge_id), roads.queue(edge_id));
+                draw_text(row, x, y + 18. + 20. * edge_id as f32, 18., WHITE);
+            }
+        }
+    }
 
+    fn draw_ridership(&self, world: &World) {
+        let ridership = &world.ridership;
+        for (idx, station) in world.stations.iter().enumerate() {
+            let pos = world.trans_net.pos_to_location(&station.pos).point;
+            draw_text(format!("{} waiting", ridership.waiting_at(idx)), pos.x + 14., pos.y + 4., 18., WHITE);
+        }
         for (idx, train) in world.trains.iter().enumerate() {
-            let pos = world.trans_net.pos_to_location(&train.pos);
-            self.draw_train(&pos, COLORS[idx]);
+            let pos = world.trans_net.pos_to_location(&train.pos).point;
+            let capacity = ridership.capacity.get(idx).copied().unwrap_or(0);
+            draw_text(format!("{}/{}", ridership.onboard(idx), capacity), pos.x + 8., pos.y + 20., 18., WHITE);
+        }
+        // Table of the lines
+        let x = WINDOW_WIDTH as f32 - 330.;
+        draw_rectangle(x - 10., 5., 330., 30. + 20. * ridership.lines.len() as f32, Color::new(0., 0., 0., 0.6));
+        draw_text("line                boarded  delivered  wait", x, 22., 18., WHITE);
+        for (line, stats) in ridership.stats.iter().enumerate() {
+            let mut name = ridership.line_name(line, &world.stations);
+            name.truncate(18);
+            let row = format!("{:<18} {:>8} {:>10} {:>4.0}s", name, stats.boarded, stats.delivered, stats.mean_wait());
+            draw_text(row, x, 42. + 20. * line as f32, 18., COLORS[line % COLORS.len()]);
+        }
+    }
+
+    fn draw_analysis(&self, tracks: &Graph, index: &SpatialIndex) {
+        // Nodes of the same component have the same color
+        for (idx, component) in tracks.strongly_connected_components().iter().enumerate() {
+            for &node_id in component {
+                let node = tracks.nodes[node_id];
+                draw_circle(node.x, node.y, 8., COLORS[idx % COLORS.len()]);
+            }
+        }
+        let (x, y) = mouse_position();
+        if let Some(edge_id) = edge_at(tracks, index, Node::new(x, y)) {
+            for node_id in tracks.reachable_from_edge(edge_id, false) {
+                let node = tracks.nodes[node_id];
+                draw_circle_lines(node.x, node.y, 12., 2., WHITE);
+            }
+        }
+        for (idx, issue) in tracks.validate().iter().enumerate() {
+            if let GraphIssue::Unreachable { node } | GraphIssue::DeadEnd { node, .. } = issue {
+                let node = tracks.nodes[*node];
+                draw_circle_lines(node.x, node.y, 16., 2., RED);
+            }
+            draw_text(issue.to_string(), 10., 44. + 20. * idx as f32, 20., RED);
         }
     }
     
-    fn draw_connections(&self, tracks: &Graph) {
+    fn draw_connections(&self, tracks: &Graph, signals: &Signalling) {
         let thickness = 5.;
-        for track in &tracks.edges {
-            let p1 = &tracks.nodes[track.from_node_id];
-            let p2 = &tracks.nodes[track.to_node_id];
-            draw_line(p1.x, p1.y, p2.x, p2.y, thickness, BROWN);
+        for (edge_id, track) in tracks.edges.iter().enumerate() {
+            // Reserved tracks have the color of the train
+            let color = signals.reserved_by(edge_id).map_or(BROWN, |train| COLORS[train % COLORS.len()]);
+            for segment in track.curve().points(10.).windows(2) {
+                let (p1, p2) = (segment[0], segment[1]);
+                draw_line(p1.x, p1.y, p2.x, p2.y, thickness, color);
+                // Hide gaps between segments
+                draw_circle(p2.x, p2.y, thickness / 2., color);
+            }
         }
     }
     
-    fn draw_train(&self, pos: &Node, color: Color) {
-        draw_circle(pos.x, pos.y, 10., color);
+    fn draw_train(&self, tracks: &Graph, train: &Train, route: &[usize], color: Color) {
+        for car in train.consist.positions(tracks, &train.pos, route) {
+            let front = tracks.pos_to_location(&car.front).point;
+            let rear = tracks.pos_to_location(&car.rear).point;
+            draw_line(rear.x, rear.y, front.x, front.y, 12., color);
+        }
+        // Front of the train
+        let location = tracks.pos_to_location(&train.pos);
+        let pos = location.point;
+        let (dx, dy) = (location.heading.cos(), location.heading.sin());
+        draw_line(pos.x - 6. * dx, pos.y - 6. * dy, pos.x, pos.y, 12., BLACK);
     }
 
 }
@@ -193,7 +599,7 @@ impl WorldView {
 
 fn window_conf() -> Conf {
     Conf {
-        window_title: "Map viewer".to_owned(),
+        window_title: "Transport sandbox".to_owned(),
         fullscreen: false,
         window_width: WINDOW_WIDTH as i32,
         window_height: WINDOW_HEIGHT as i32,
@@ -201,43 +607,50 @@ fn window_conf() -> Conf {
     }
 }
 
-fn init_world() -> World {
-    let nodes = vec![
-        Node::new(100., 100.),
-        Node::new(700., 100.),
-        Node::new(700., 500.),
-        Node::new(100., 500.),
-    ];
-
-    let edges = vec![
-        Edge::new(0, 1, &nodes),
-        Edge::new(1, 3, &nodes),
-        Edge::new(3, 0, &nodes),
-        Edge::new(1, 2, &nodes),
-    ];
-    let graph = Graph::new(nodes, edges);
-
-    let schedules = vec![
-        vec![0, 1, 2],
-        vec![3],
-        vec![0, 1],
-    ];
-
-    let trains = vec![
-            // Train::new(100., 0, 0),
-            Train::new(200., 0, 0),
-            Train::new(140., 3, 1),
-            // Train::new(90., 3, 1),
-            Train::new(150., 0, 2),
-        ];
-
-    World::new(Map::default(), graph, schedules, trains)
+fn network_arg() -> Option<String> {
+    std::env::args().nth(1)
+}
+
+fn init_world(file: &NetworkFile) -> Result<World> {
+    let graph = file.to_graph();
+    let index = graph.spatial_index();
+    let mut signals = file.signalling(&graph);
+    let schedules = file.schedules();
+    let trains: Vec<Train> = file.trains.iter().map(Train::new).collect();
+    for (idx, train) in trains.iter().enumerate() {
+        let edges = train.consist.occupied_edges(&graph, &train.pos, &schedules[train.schedule_id]);
+        signals.place_consist(idx, &edges).context("Trains can't start in the same block")?;
+    }
+    let services: Vec<Service> = file.trains.iter()
+        .map(|def| Service::new(def.timetable.clone().unwrap_or_default()))
+        .collect();
+    let capacity = file.trains.iter().map(|def| def.capacity).collect();
+    let ridership = Ridership::new(file.stations.len(), file.demand.clone(), &services, capacity);
+
+    Ok(World {
+        map: Map::default(),
+        trans_net: graph,
+        signals,
+        stations: file.stations(),
+        schedules,
+        trains,
+        services,
+        ridership,
+        index,
+        roads: file.roads.as_ref().map(RoadsDef::to_roads),
+        clock: Clock::new(1.),
+    })
 }
 
 #[macroquad::main(window_conf)]
-async fn main() {
+async fn main() -> Result<()> {
+    let (file, path) = match network_arg() {
+        Some(path) => (NetworkFile::load(Path::new(&path))?, path),
+        None => (NetworkFile::parse(DEFAULT_NETWORK)?, NETWORK_FILE.to_owned()),
+    };
     let mut map_view = WorldView::new();
-    let mut world = init_world();
+    let mut world = init_world(&file)?;
+    let mut editor = Editor::new(PathBuf::from(path));
 
     loop {
         let dt = get_frame_time();
@@ -265,11 +678,44 @@ async fn main() {
         if is_key_down(KeyCode::Down) {
             map_view.move_down(dt);
         }
-        // Update world (nothing there yet)
-        world.update(dt);
+        if is_key_pressed(KeyCode::P) {
+            println!("{}", world.punctuality());
+            println!("{}", world.ridership);
+            if let Some(roads) = &world.roads {
+                println!("{}", roads);
+            }
+        }
+        if is_key_pressed(KeyCode::E) {
+            editor.active = !editor.active;
+        }
+        if is_key_pressed(KeyCode::V) {
+            map_view.analysis = !map_view.analysis;
+        }
+        if is_key_pressed(KeyCode::R) {
+            map_view.ridership = !map_view.ridership;
+        }
+        if is_key_pressed(KeyCode::C) {
+            map_view.congestion = !map_view.congestion;
+        }
+        if editor.active {
+            if editor.update(&mut world.trans_net, &mut world.index) {
+                world.network_changed();
+                map_view.selected = None;
+            }
+        } else {
+            if is_mouse_button_pressed(MouseButton::Left) {
+                let (x, y) = mouse_position();
+                map_view.selected = world.index.nearest_edge(&world.trans_net, Node::new(x, y), PICK_DISTANCE);
+            }
+            world.update(dt);
+        }
         // Draw world
         map_view.draw(&world);
+        if editor.active {
+            editor.draw(&world.trans_net, &world.index);
+        }
         
         next_frame().await
     }
+    Ok(())
 }
\ No newline at end of file
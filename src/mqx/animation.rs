@@ -0,0 +1,356 @@
+// Sprite animation: named clips of frames and the player which runs them.
+//
+// Clip is a list of frames (indices into `Frames`), each shown for its own time. Clip can loop,
+// go back and forth (ping-pong) or play once and stop at the last frame. Player keeps the time
+// in the current clip and draws the current frame flipped, scaled and placed by its pivot.
+
+use std::ops::Range;
+
+use macroquad::prelude::*;
+
+use crate::mqx::frames::Frames;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PlayMode {
+    #[default]
+    Loop,
+    /// Forward and back again: 0 1 2 1 0 1 2 ...
+    PingPong,
+    /// Stops at the last frame
+    Once,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clip {
+    pub name: String,
+    /// Frame indices
+    pub frames: Vec<usize>,
+    /// Time of every frame in seconds. Frames without the time are skipped
+    pub durations: Vec<f32>,
+    pub mode: PlayMode,
+}
+
+/// Frames with the clips made of them
+pub struct SpriteSheet {
+    pub frames: Frames,
+    pub clips: Vec<Clip>,
+}
+
+#[derive(Clone)]
+pub struct AnimationPlayer {
+    clip: usize,
+    time: f32,
+    /// Multiplies the time
+    pub speed: f32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    /// Point of the frame placed at the drawing position: (0, 0) is top left, (1, 1) bottom right
+    pub pivot: Vec2,
+    pub scale: Vec2,
+    pub color: Color,
+}
+
+
+impl Clip {
+    /// Frames from the range, all shown for the same time
+    pub fn new(name: &str, frames: Range<usize>, fps: f32, mode: PlayMode) -> Self {
+        let frames: Vec<usize> = frames.collect();
+        let durations = vec![1. / fps; frames.len()];
+        Self { name: name.to_owned(), frames, durations, mode }
+    }
+
+    /// Frames in any order, each with its own time.
+    /// Panics if the number of durations differs from the number of frames
+    pub fn with_durations(name: &str, frames: Vec<usize>, durations: Vec<f32>, mode: PlayMode) -> Self {
+        assert_eq!(frames.len(), durations.len(), "Clip '{}' needs one duration per frame", name);
+        Self { name: name.to_owned(), frames, durations, mode }
+    }
+
+    /// Time of playing the clip once (forward only)
+    pub fn duration(&self) -> f32 {
+        (0..self.frames.len()).map(|step| self.frame_time(step)).sum()
+    }
+
+    /// Position in the clip (not the frame index) shown at the given time
+    pub fn step_at(&self, time: f32) -> usize {
+        let count = self.frames.len();
+        let total = self.duration();
+        if count <= 1 || total <= 0. {
+            return 0;
+        }
+        match self.mode {
+            PlayMode::Once if time >= total => count - 1,
+            PlayMode::Once | PlayMode::Loop => self.step_in(time.rem_euclid(total), 0..count),
+            PlayMode::PingPong => {
+                // Way back skips both end frames
+                let back: f32 = (1..count - 1).map(|step| self.frame_time(step)).sum();
+                let time = time.rem_euclid(total + back);
+                if time < total {
+                    self.step_in(time, 0..count)
+                } else {
+                    let steps: Vec<usize> = (1..count - 1).rev().collect();
+                    let mut time = time - total;
+                    steps.iter().copied()
+                        .find(|&step| {
+                            time -= self.frame_time(step);
+                            time < 0.
+                        })
+                        .unwrap_or(1)
+                }
+            }
+        }
+    }
+
+    /// Frame index shown at the given time
+    pub fn frame_at(&self, time: f32) -> Option<usize> {
+        self.frames.get(self.step_at(time)).copied()
+    }
+
+    /// Clip played once has reached its end
+    pub fn is_finished(&self, time: f32) -> bool {
+        self.mode == PlayMode::Once && time >= self.duration()
+    }
+
+    /// Time of one cycle: ping-pong goes there and back
+    pub fn cycle_duration(&self) -> f32 {
+        self.timeline().iter().map(|(_, d)| d).sum()
+    }
+
+    /// Frames which show up after the time `from` until the time `to` (including), in order.
+    /// Frame shown again after the loop is included again
+    pub fn frames_between(&self, from: f32, to: f32) -> Vec<usize> {
+        let timeline = self.timeline();
+        let cycle = self.cycle_duration();
+        if to <= from || cycle <= 0. {
+            return vec![];
+        }
+        let last_cycle = if self.mode == PlayMode::Once { 0. } else { (to / cycle).floor() };
+        let mut frames = vec![];
+        let mut cycle_start = (from / cycle).floor().max(0.) * cycle;
+        while cycle_start <= last_cycle * cycle {
+            let mut start = cycle_start;
+            for &(step, duration) in &timeline {
+                if from < start && start <= to {
+                    frames.push(self.frames[step]);
+                }
+                start += duration;
+            }
+            cycle_start += cycle;
+        }
+        frames
+    }
+
+    // Steps of one cycle with their time. Steps without the time are never shown
+    fn timeline(&self) -> Vec<(usize, f32)> {
+        let count = self.frames.len();
+        let back = match self.mode {
+            PlayMode::PingPong if count > 2 => (1..count - 1).rev().collect(),
+            _ => vec![],
+        };
+        (0..count).chain(back)
+            .map(|step| (step, self.frame_time(step)))
+            .filter(|(_, time)| *time > 0.)
+            .collect()
+    }
+
+    // Durations can be changed directly, so missing ones are taken as zero
+    fn frame_time(&self, step: usize) -> f32 {
+        self.durations.get(step).copied().unwrap_or(0.)
+    }
+
+    fn step_in(&self, mut time: f32, steps: Range<usize>) -> usize {
+        let last = steps.end - 1;
+        steps.into_iter()
+            .find(|&step| {
+                time -= self.frame_time(step);
+                time < 0.
+            })
+            .unwrap_or(last)
+    }
+}
+
+
+impl SpriteSheet {
+    pub fn new(frames: Frames, clips: Vec<Clip>) -> Self {
+        Self { frames, clips }
+    }
+
+    pub fn clip_id(&self, name: &str) -> Option<usize> {
+        self.clips.iter().position(|c| c.name == name)
+    }
+}
+
+
+impl AnimationPlayer {
+    pub fn new(clip: usize) -> Self {
+        Self {
+            clip,
+            time: 0.,
+            speed: 1.,
+            flip_x: false,
+            flip_y: false,
+            pivot: Vec2::ZERO,
+            scale: Vec2::ONE,
+            color: WHITE,
+        }
+    }
+
+    pub fn clip(&self) -> usize {
+        self.clip
+    }
+
+    /// Time in the current clip
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Switch to the clip. Playing the same clip continues
+    pub fn play(&mut self, clip: usize) {
+        if clip != self.clip {
+            self.clip = clip;
+            self.time = 0.;
+        }
+    }
+
+    pub fn restart(&mut self) {
+        self.time = 0.;
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt * self.speed;
+    }
+
+    /// Frame index to show
+    pub fn frame(&self, sheet: &SpriteSheet) -> Option<usize> {
+        sheet.clips.get(self.clip).and_then(|c| c.frame_at(self.time))
+    }
+
+    pub fn is_finished(&self, sheet: &SpriteSheet) -> bool {
+        sheet.clips.get(self.clip).is_some_and(|c| c.is_finished(self.time))
+    }
+
+    /// Draw the current frame with its pivot at the given position
+    pub fn draw(&self, sheet: &SpriteSheet, pos: Vec2) {
+        let Some(rect) = self.frame(sheet).and_then(|f| sheet.frames.rect(f)) else {
+            return;
+        };
+        let size = rect.size() * self.scale;
+        let origin = origin(pos, size, self.pivot, self.flip_x, self.flip_y);
+        let params = DrawTextureParams {
+            dest_size: Some(size),
+            source: Some(rect),
+            flip_x: self.flip_x,
+            flip_y: self.flip_y,
+            ..Default::default()
+        };
+        draw_texture_ex(sheet.frames.texture(), origin.x, origin.y, self.color, params);
+    }
+}
+
+
+// Top left corner of the frame of the given size. Flipped frame is mirrored around the pivot
+fn origin(pos: Vec2, size: Vec2, pivot: Vec2, flip_x: bool, flip_y: bool) -> Vec2 {
+    let x = if flip_x { 1. - pivot.x } else { pivot.x };
+    let y = if flip_y { 1. - pivot.y } else { pivot.y };
+    pos - vec2(x, y) * size
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frames_over(clip: &Clip, step: f32, count: usize) -> Vec<usize> {
+        (0..count).map(|i| clip.frame_at(i as f32 * step + step / 2.).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_modes() {
+        let clip = Clip::new("walk", 4..7, 10., PlayMode::Loop);
+        assert_eq!(frames_over(&clip, 0.1, 7), vec![4, 5, 6, 4, 5, 6, 4]);
+        assert!(!clip.is_finished(10.));
+
+        let clip = Clip { mode: PlayMode::PingPong, ..clip };
+        assert_eq!(frames_over(&clip, 0.1, 9), vec![4, 5, 6, 5, 4, 5, 6, 5, 4]);
+
+        let clip = Clip { mode: PlayMode::Once, ..clip };
+        assert_eq!(frames_over(&clip, 0.1, 5), vec![4, 5, 6, 6, 6]);
+        assert!(!clip.is_finished(0.25));
+        assert!(clip.is_finished(0.35));
+
+        // Single frame and empty clips don't move
+        assert_eq!(Clip::new("idle", 3..4, 10., PlayMode::PingPong).frame_at(1.3), Some(3));
+        assert_eq!(Clip::new("none", 3..3, 10., PlayMode::Loop).frame_at(1.3), None);
+    }
+
+    #[test]
+    fn test_durations() {
+        let clip = Clip::with_durations("attack", vec![2, 0, 1], vec![0.5, 0.1, 0.2], PlayMode::Loop);
+        assert!((clip.duration() - 0.8).abs() < 1e-6);
+        assert_eq!(clip.frame_at(0.45), Some(2));
+        assert_eq!(clip.frame_at(0.55), Some(0));
+        assert_eq!(clip.frame_at(0.65), Some(1));
+        assert_eq!(clip.frame_at(0.85), Some(2));
+
+        let clip = Clip { mode: PlayMode::PingPong, ..clip };
+        // Back: frame 0 again for 0.1
+        assert_eq!(clip.frame_at(0.85), Some(0));
+        assert_eq!(clip.frame_at(0.95), Some(2));
+
+        // Frame without the time is skipped
+        let clip = Clip { durations: vec![0.5, 0.1], mode: PlayMode::Loop, ..clip };
+        assert!((clip.duration() - 0.6).abs() < 1e-6);
+        assert_eq!(clip.frame_at(0.55), Some(0));
+        assert_eq!(clip.frame_at(0.65), Some(2));
+        assert_eq!(clip.frames_between(0., 0.7), vec![0, 2]);
+    }
+
+    #[test]
+    #[should_panic(expected = "needs one duration per frame")]
+    fn test_durations_mismatch() {
+        Clip::with_durations("attack", vec![2, 0, 1], vec![0.5, 0.1], PlayMode::Loop);
+    }
+
+    #[test]
+    fn test_frames_between() {
+        let clip = Clip::new("walk", 4..7, 10., PlayMode::Loop);
+        assert_eq!(clip.frames_between(0.05, 0.15), vec![5]);
+        // Wraps around
+        assert_eq!(clip.frames_between(0.15, 0.45), vec![6, 4, 5]);
+        assert!(clip.frames_between(0.11, 0.19).is_empty());
+
+        let clip = Clip { mode: PlayMode::PingPong, ..clip };
+        assert!((clip.cycle_duration() - 0.4).abs() < 1e-6);
+        assert_eq!(clip.frames_between(0.15, 0.45), vec![6, 5, 4]);
+
+        let clip = Clip { mode: PlayMode::Once, ..clip };
+        assert_eq!(clip.frames_between(0.15, 1.), vec![6]);
+    }
+
+    #[test]
+    fn test_player() {
+        let mut player = AnimationPlayer::new(0);
+        player.speed = 2.;
+        player.update(0.25);
+        assert_eq!(player.time(), 0.5);
+        player.play(0);
+        assert_eq!(player.time(), 0.5);
+        player.play(1);
+        assert_eq!((player.clip(), player.time()), (1, 0.));
+    }
+
+    #[test]
+    fn test_origin() {
+        let size = vec2(20., 40.);
+        // Feet at the position
+        let pivot = vec2(0.25, 1.);
+        assert_eq!(origin(vec2(100., 100.), size, pivot, false, false), vec2(95., 60.));
+        // Mirrored around the pivot
+        assert_eq!(origin(vec2(100., 100.), size, pivot, true, false), vec2(85., 60.));
+        assert_eq!(origin(vec2(100., 100.), size, pivot, false, true), vec2(95., 100.));
+    }
+}
@@ -0,0 +1,427 @@
+// Animation state machine on top of the sprite clips.
+//
+// Every state plays a clip. Transitions lead from one state (or any state) to another when all
+// their conditions on the parameters hold. Transition can wait for the exit time (part of the
+// clip played, 1 is the whole clip) and crossfade the clips for the given time. Looping clip can be
+// left when it passes the exit time in any of its cycles. Triggers are parameters which are reset
+// by the transition using them.
+//
+// Frame events are named frames of the clips: the event fires every time its frame shows up, even
+// if the update skips over it.
+
+use std::collections::HashMap;
+
+use macroquad::prelude::*;
+
+use crate::mqx::animation::{AnimationPlayer, Clip, PlayMode, SpriteSheet};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Param {
+    Bool(bool),
+    Float(f32),
+    /// Set until a transition uses it
+    Trigger(bool),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    /// Bool or trigger is set
+    If(String),
+    IfNot(String),
+    Greater(String, f32),
+    Less(String, f32),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AnimState {
+    pub name: String,
+    pub clip: usize,
+    /// Multiplies the clip time
+    pub speed: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition {
+    /// None is from any other state
+    pub from: Option<usize>,
+    pub to: usize,
+    /// All of them have to hold
+    pub conditions: Vec<Condition>,
+    /// Part of the clip played before leaving the state
+    pub exit_time: Option<f32>,
+    /// Crossfade time in seconds, 0 switches at once
+    pub fade: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FrameEvent {
+    pub clip: usize,
+    /// Frame index (as in `Clip::frames`)
+    pub frame: usize,
+    pub name: String,
+}
+
+pub struct Animator {
+    states: Vec<AnimState>,
+    transitions: Vec<Transition>,
+    events: Vec<FrameEvent>,
+    params: HashMap<String, Param>,
+    state: usize,
+    player: AnimationPlayer,
+    fade: Option<Fade>,
+}
+
+// Previous state fading out
+struct Fade {
+    player: AnimationPlayer,
+    elapsed: f32,
+    duration: f32,
+}
+
+
+impl AnimState {
+    pub fn new(name: &str, clip: usize) -> Self {
+        Self { name: name.to_owned(), clip, speed: 1. }
+    }
+}
+
+
+impl Transition {
+    /// Immediate transition without the exit time
+    pub fn new(from: Option<usize>, to: usize, conditions: Vec<Condition>) -> Self {
+        Self { from, to, conditions, exit_time: None, fade: 0. }
+    }
+}
+
+
+impl FrameEvent {
+    pub fn new(clip: usize, frame: usize, name: &str) -> Self {
+        Self { clip, frame, name: name.to_owned() }
+    }
+}
+
+
+impl Animator {
+    /// Machine starting in the first state
+    pub fn new(states: Vec<AnimState>, transitions: Vec<Transition>, events: Vec<FrameEvent>) -> Self {
+        let mut player = AnimationPlayer::new(states.first().map_or(0, |s| s.clip));
+        player.speed = states.first().map_or(1., |s| s.speed);
+        Self { states, transitions, events, params: HashMap::new(), state: 0, player, fade: None }
+    }
+
+    pub fn state(&self) -> usize {
+        self.state
+    }
+
+    pub fn state_name(&self) -> &str {
+        self.states.get(self.state).map_or("", |s| s.name.as_str())
+    }
+
+    pub fn state_id(&self, name: &str) -> Option<usize> {
+        self.states.iter().position(|s| s.name == name)
+    }
+
+    /// Player of the current state, for flipping, scale and pivot
+    pub fn player(&self) -> &AnimationPlayer {
+        &self.player
+    }
+
+    pub fn player_mut(&mut self) -> &mut AnimationPlayer {
+        &mut self.player
+    }
+
+    /// Weight of the current state while crossfading, 1 otherwise
+    pub fn blend(&self) -> f32 {
+        self.fade.as_ref().map_or(1., |f| f.elapsed / f.duration)
+    }
+
+    pub fn param(&self, name: &str) -> Option<Param> {
+        self.params.get(name).copied()
+    }
+
+    pub fn set_bool(&mut self, name: &str, value: bool) {
+        self.params.insert(name.to_owned(), Param::Bool(value));
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.params.insert(name.to_owned(), Param::Float(value));
+    }
+
+    pub fn trigger(&mut self, name: &str) {
+        self.params.insert(name.to_owned(), Param::Trigger(true));
+    }
+
+    /// Jump to the state without a transition
+    pub fn enter(&mut self, state: usize, clips: &[Clip]) -> Vec<String> {
+        self.fade = None;
+        self.switch(state);
+        self.entry_events(clips)
+    }
+
+    /// Advance the time and take the transition if any. Returns the events fired
+    pub fn update(&mut self, dt: f32, clips: &[Clip]) -> Vec<String> {
+        if let Some(fade) = &mut self.fade {
+            fade.player.update(dt);
+            fade.elapsed += dt;
+            if fade.elapsed >= fade.duration {
+                self.fade = None;
+            }
+        }
+        let before = self.player.time();
+        self.player.update(dt);
+        let mut events: Vec<String> = clips.get(self.player.clip())
+            .map(|clip| clip.frames_between(before, self.player.time()))
+            .unwrap_or_default()
+            .into_iter()
+            .flat_map(|frame| self.frame_events(frame))
+            .collect();
+
+        let Some(idx) = self.transitions.iter().position(|t| self.can_take(t, clips, before)) else {
+            return events;
+        };
+        let transition = self.transitions[idx].clone();
+        for condition in &transition.conditions {
+            if let Condition::If(name) | Condition::IfNot(name) = condition {
+                if let Some(Param::Trigger(set)) = self.params.get_mut(name) {
+                    *set = false;
+                }
+            }
+        }
+        let previous = self.switch(transition.to);
+        if transition.fade > 0. {
+            self.fade = Some(Fade { player: previous, elapsed: 0., duration: transition.fade });
+        }
+        events.extend(self.entry_events(clips));
+        events
+    }
+
+    /// Draw the current state, crossfaded with the previous one
+    pub fn draw(&self, sheet: &SpriteSheet, pos: Vec2) {
+        match &self.fade {
+            Some(fade) => {
+                let blend = self.blend();
+                let mut previous = fade.player.clone();
+                previous.flip_x = self.player.flip_x;
+                previous.flip_y = self.player.flip_y;
+                previous.pivot = self.player.pivot;
+                previous.scale = self.player.scale;
+                previous.color.a = self.player.color.a * (1. - blend);
+                previous.draw(sheet, pos);
+                let mut current = self.player.clone();
+                current.color.a *= blend;
+                current.draw(sheet, pos);
+            }
+            None => self.player.draw(sheet, pos),
+        }
+    }
+
+    // Events of the first frame of the state just entered
+    fn entry_events(&self, clips: &[Clip]) -> Vec<String> {
+        clips.get(self.player.clip())
+            .and_then(|c| c.frame_at(self.player.time()))
+            .map_or(vec![], |frame| self.frame_events(frame))
+    }
+
+    fn frame_events(&self, frame: usize) -> Vec<String> {
+        self.events.iter()
+            .filter(|e| e.clip == self.player.clip() && e.frame == frame)
+            .map(|e| e.name.clone())
+            .collect()
+    }
+
+    // Time `before` is the time of the clip before the update
+    fn can_take(&self, transition: &Transition, clips: &[Clip], before: f32) -> bool {
+        let from = transition.from.map_or(transition.to != self.state, |from| from == self.state);
+        from && transition.to < self.states.len()
+            && transition.conditions.iter().all(|c| self.holds(c))
+            && transition.exit_time.is_none_or(|exit| match clips.get(self.player.clip()) {
+                Some(clip) => passed_exit(clip, exit, before, self.player.time()),
+                None => true,
+            })
+    }
+
+    fn holds(&self, condition: &Condition) -> bool {
+        match condition {
+            Condition::If(name) => matches!(self.param(name), Some(Param::Bool(true) | Param::Trigger(true))),
+            Condition::IfNot(name) => !matches!(self.param(name), Some(Param::Bool(true) | Param::Trigger(true))),
+            Condition::Greater(name, value) => matches!(self.param(name), Some(Param::Float(v)) if v > *value),
+            Condition::Less(name, value) => matches!(self.param(name), Some(Param::Float(v)) if v < *value),
+        }
+    }
+
+    // New player for the state, keeping the look. Returns the old one
+    fn switch(&mut self, state: usize) -> AnimationPlayer {
+        let Some(next) = self.states.get(state) else {
+            return self.player.clone();
+        };
+        let mut player = AnimationPlayer::new(next.clip);
+        player.speed = next.speed;
+        player.flip_x = self.player.flip_x;
+        player.flip_y = self.player.flip_y;
+        player.pivot = self.player.pivot;
+        player.scale = self.player.scale;
+        player.color = self.player.color;
+        self.state = state;
+        std::mem::replace(&mut self.player, player)
+    }
+}
+
+
+// Clip played once stays after the exit time. Repeating clip passes it once in every cycle,
+// it has to happen during this update
+fn passed_exit(clip: &Clip, exit: f32, before: f32, time: f32) -> bool {
+    let exit = exit * clip.duration();
+    let cycle = clip.cycle_duration();
+    if cycle <= 0. || time < exit {
+        return cycle <= 0.;
+    }
+    if clip.mode == PlayMode::Once {
+        return true;
+    }
+    // Last time the exit was passed
+    let passed = exit + ((time - exit) / cycle).floor() * cycle;
+    passed > before || (passed == 0. && before == 0.)
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLE: usize = 0;
+    const RUN: usize = 1;
+    const JUMP: usize = 2;
+
+    fn clips() -> Vec<Clip> {
+        vec![
+            Clip::new("idle", 0..2, 10., PlayMode::Loop),
+            Clip::new("run", 2..6, 10., PlayMode::Loop),
+            Clip::new("jump", 6..9, 10., PlayMode::Once),
+        ]
+    }
+
+    fn runner() -> Animator {
+        let states = vec![AnimState::new("idle", 0), AnimState::new("run", 1), AnimState::new("jump", 2)];
+        let transitions = vec![
+            Transition::new(Some(IDLE), RUN, vec![Condition::Greater("speed".to_owned(), 0.1)]),
+            Transition {
+                fade: 0.2,
+                ..Transition::new(Some(RUN), IDLE, vec![Condition::Less("speed".to_owned(), 0.1)])
+            },
+            Transition::new(None, JUMP, vec![Condition::If("jump".to_owned())]),
+            Transition {
+                exit_time: Some(1.),
+                ..Transition::new(Some(JUMP), IDLE, vec![Condition::If("grounded".to_owned())])
+            },
+        ];
+        let events = vec![FrameEvent::new(1, 3, "footstep"), FrameEvent::new(1, 5, "footstep")];
+        Animator::new(states, transitions, events)
+    }
+
+    #[test]
+    fn test_transitions() {
+        let clips = clips();
+        let mut animator = runner();
+        animator.update(0.05, &clips);
+        assert_eq!(animator.state_name(), "idle");
+
+        animator.set_float("speed", 2.);
+        animator.update(0.05, &clips);
+        assert_eq!((animator.state(), animator.player().time()), (RUN, 0.));
+
+        // Trigger from any state, used once
+        animator.trigger("jump");
+        animator.update(0.05, &clips);
+        assert_eq!(animator.state(), JUMP);
+        assert_eq!(animator.param("jump"), Some(Param::Trigger(false)));
+        animator.update(0.05, &clips);
+        assert_eq!(animator.state(), JUMP);
+
+        // Landing waits for the end of the jump clip
+        animator.set_bool("grounded", true);
+        animator.update(0.1, &clips);
+        assert_eq!(animator.state(), JUMP);
+        animator.update(0.2, &clips);
+        assert_eq!(animator.state(), IDLE);
+    }
+
+    #[test]
+    fn test_exit_time_of_loop() {
+        let clips = clips();
+        // Run (0.4 s loop) stops at the end of the cycle
+        let transitions = vec![Transition {
+            exit_time: Some(1.),
+            ..Transition::new(Some(1), 0, vec![Condition::Less("speed".to_owned(), 0.1)])
+        }];
+        let mut animator = Animator::new(vec![AnimState::new("idle", 0), AnimState::new("run", 1)], transitions, vec![]);
+        animator.enter(1, &clips);
+        for _ in 0..9 {
+            animator.update(0.05, &clips);
+        }
+        // Second cycle has started
+        animator.set_float("speed", 0.);
+        for _ in 0..6 {
+            animator.update(0.05, &clips);
+            assert_eq!(animator.state_name(), "run");
+        }
+        animator.update(0.1, &clips);
+        assert_eq!(animator.state_name(), "idle");
+    }
+
+    #[test]
+    fn test_fade() {
+        let clips = clips();
+        let mut animator = runner();
+        animator.set_float("speed", 2.);
+        animator.update(0.05, &clips);
+        animator.set_float("speed", 0.);
+        animator.update(0.05, &clips);
+        assert_eq!(animator.state(), IDLE);
+        assert_eq!(animator.blend(), 0.);
+        animator.update(0.05, &clips);
+        assert!((animator.blend() - 0.25).abs() < 1e-6);
+        animator.update(0.2, &clips);
+        assert_eq!(animator.blend(), 1.);
+    }
+
+    #[test]
+    fn test_events() {
+        let clips = clips();
+        let mut animator = runner();
+        animator.set_float("speed", 2.);
+        let mut fired = vec![];
+        // Run frames 2 3 4 5 change every 0.1 s
+        for step in 0..20 {
+            let events = animator.update(0.05, &clips);
+            if !events.is_empty() {
+                fired.push((step, events));
+            }
+        }
+        let footsteps: Vec<usize> = fired.iter().map(|(step, _)| *step).collect();
+        assert_eq!(footsteps, vec![2, 6, 10, 14, 18]);
+        assert!(fired.iter().all(|(_, events)| events == &vec!["footstep".to_owned()]));
+
+        // Long update skips over both footsteps
+        let mut animator = runner();
+        animator.set_float("speed", 2.);
+        animator.update(0.01, &clips);
+        assert_eq!(animator.update(0.39, &clips), vec!["footstep".to_owned(), "footstep".to_owned()]);
+
+        // Single frame clip shows its frame again in every loop
+        let clips = vec![Clip::new("blink", 0..1, 10., PlayMode::Loop)];
+        let events = vec![FrameEvent::new(0, 0, "blink")];
+        let mut animator = Animator::new(vec![AnimState::new("blink", 0)], vec![], events);
+        let blinks: usize = (0..10).map(|_| animator.update(0.052, &clips).len()).sum();
+        assert_eq!(blinks, 5);
+        let clips = self::clips();
+
+        // Entering the state at the tagged frame fires too
+        let mut animator = runner();
+        animator.events.push(FrameEvent::new(1, 2, "start"));
+        assert_eq!(animator.enter(RUN, &clips), vec!["start".to_owned()]);
+    }
+}
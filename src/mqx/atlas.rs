@@ -0,0 +1,331 @@
+// Texture atlas: regions of the texture described by the RON file.
+//
+// Two layouts are supported:
+//
+// ```ron
+// #![enable(implicit_some)]
+// Grid((
+//     texture_width: 261,
+//     texture_height: 261,
+//     columns: 20,
+//     rows: 20,
+//     sprite_count: 390,              // optional, all cells by default
+//     cell_size: (13, 13),            // optional, texture size / columns and rows by default
+//     position: (0, 0),               // optional, top left corner of the grid
+// ))
+//
+// List((
+//     texture_width: 48,
+//     texture_height: 16,
+//     sprites: [
+//         (x: 0, y: 0, width: 16, height: 16, name: "grass"),     // name is optional
+//         (x: 16, y: 0, width: 32, height: 16),
+//     ],
+// ))
+// ```
+//
+// Sizes are given in the pixels of the texture described by the file. If the loaded texture has
+// a different size, regions are scaled to it.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use macroquad::prelude::*;
+use ron::extensions::Extensions;
+use serde_derive::Deserialize;
+
+
+#[derive(Debug, PartialEq)]
+pub enum AtlasError {
+    Io(String),
+    Syntax { line: usize, column: usize, message: String },
+    /// Descriptor parsed, but it's not a valid atlas
+    Invalid(String),
+    UnknownRegion(String),
+    OutOfRange { index: usize, count: usize },
+}
+
+/// Regions of the atlas, without the texture
+#[derive(Clone, Debug, PartialEq)]
+pub struct AtlasLayout {
+    pub texture_size: Vec2,
+    regions: Vec<Rect>,
+    names: HashMap<String, usize>,
+}
+
+pub struct Atlas {
+    texture: Texture2D,
+    layout: AtlasLayout,
+}
+
+// Descriptor as written in the file
+#[derive(Deserialize)]
+#[serde(rename = "Atlas")]
+enum AtlasDef {
+    Grid(GridDef),
+    List(ListDef),
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Grid", deny_unknown_fields)]
+struct GridDef {
+    texture_width: u32,
+    texture_height: u32,
+    columns: u32,
+    rows: u32,
+    #[serde(default)]
+    sprite_count: Option<u32>,
+    #[serde(default)]
+    cell_size: Option<(f32, f32)>,
+    #[serde(default)]
+    position: Option<(f32, f32)>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "List", deny_unknown_fields)]
+struct ListDef {
+    texture_width: u32,
+    texture_height: u32,
+    sprites: Vec<SpriteDef>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename = "Sprite", deny_unknown_fields)]
+struct SpriteDef {
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+
+impl fmt::Display for AtlasError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AtlasError::Io(err) => write!(f, "Can't read atlas: {}", err),
+            AtlasError::Syntax { line, column, message } =>
+                write!(f, "Atlas syntax error at line {}, column {}: {}", line, column, message),
+            AtlasError::Invalid(message) => write!(f, "Invalid atlas: {}", message),
+            AtlasError::UnknownRegion(name) => write!(f, "Atlas has no region '{}'", name),
+            AtlasError::OutOfRange { index, count } =>
+                write!(f, "Atlas region {} out of range, there are {} regions", index, count),
+        }
+    }
+}
+
+impl std::error::Error for AtlasError {}
+
+
+impl AtlasLayout {
+    /// Parse the RON descriptor
+    pub fn parse(text: &str) -> Result<Self, AtlasError> {
+        // Optional values can be written without Some(..) even without #![enable(implicit_some)]
+        let options = ron::Options::default().with_default_extension(Extensions::IMPLICIT_SOME);
+        let def: AtlasDef = options.from_str(text)
+            .map_err(|err| AtlasError::Syntax {
+                line: err.position.line,
+                column: err.position.col,
+                message: err.code.to_string(),
+            })?;
+        match def {
+            AtlasDef::Grid(grid) => Self::grid(grid),
+            AtlasDef::List(list) => Self::list(list),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    /// Region in the pixels of the described texture
+    pub fn region(&self, index: usize) -> Result<Rect, AtlasError> {
+        self.regions.get(index).copied().ok_or(AtlasError::OutOfRange { index, count: self.regions.len() })
+    }
+
+    pub fn index_of(&self, name: &str) -> Result<usize, AtlasError> {
+        self.names.get(name).copied().ok_or_else(|| AtlasError::UnknownRegion(name.to_owned()))
+    }
+
+    fn grid(def: GridDef) -> Result<Self, AtlasError> {
+        let texture_size = texture_size(def.texture_width, def.texture_height)?;
+        let columns = positive("columns", def.columns)? as usize;
+        let rows = positive("rows", def.rows)? as usize;
+        let cell_size = match def.cell_size {
+            Some((width, height)) => vec2(width, height),
+            None => vec2(texture_size.x / columns as f32, texture_size.y / rows as f32),
+        };
+        let position = def.position.map(Vec2::from).unwrap_or(Vec2::ZERO);
+        let sprite_count = match def.sprite_count {
+            Some(count) => positive("sprite_count", count)? as usize,
+            None => columns * rows,
+        };
+        if sprite_count > columns * rows {
+            return Err(invalid(&format!("sprite_count {} is more than {} cells", sprite_count, columns * rows)));
+        }
+        let end = position + cell_size * vec2(columns as f32, rows as f32);
+        if end.x > texture_size.x + 0.5 || end.y > texture_size.y + 0.5 {
+            return Err(invalid(&format!("grid ends at ({}, {}) outside of the texture", end.x, end.y)));
+        }
+        let regions = (0..sprite_count)
+            .map(|i| {
+                let corner = position + cell_size * vec2((i % columns) as f32, (i / columns) as f32);
+                Rect::new(corner.x, corner.y, cell_size.x, cell_size.y)
+            })
+            .collect();
+        Ok(Self { texture_size, regions, names: HashMap::new() })
+    }
+
+    fn list(def: ListDef) -> Result<Self, AtlasError> {
+        let texture_size = texture_size(def.texture_width, def.texture_height)?;
+        let mut regions = vec![];
+        let mut names = HashMap::new();
+        for (idx, sprite) in def.sprites.into_iter().enumerate() {
+            let rect = Rect::new(sprite.x, sprite.y, sprite.width, sprite.height);
+            if let Some(name) = sprite.name {
+                if names.insert(name.clone(), idx).is_some() {
+                    return Err(invalid(&format!("region name '{}' is used twice", name)));
+                }
+            }
+            if rect.x < 0. || rect.y < 0. || rect.right() > texture_size.x || rect.bottom() > texture_size.y {
+                return Err(invalid(&format!("sprite {} is outside of the texture", idx)));
+            }
+            regions.push(rect);
+        }
+        Ok(Self { texture_size, regions, names })
+    }
+}
+
+
+impl Atlas {
+    pub fn new(texture: Texture2D, layout: AtlasLayout) -> Self {
+        Self { texture, layout }
+    }
+
+    /// Load the texture and its RON descriptor
+    pub async fn load(texture_path: &str, descriptor_path: &str) -> Result<Self, AtlasError> {
+        let text = load_string(descriptor_path).await
+            .map_err(|err| AtlasError::Io(format!("{}: {}", descriptor_path, err)))?;
+        let layout = AtlasLayout::parse(&text)?;
+        let texture = load_texture(texture_path).await
+            .map_err(|err| AtlasError::Io(format!("{}: {}", texture_path, err)))?;
+        Ok(Self::new(texture, layout))
+    }
+
+    pub fn layout(&self) -> &AtlasLayout {
+        &self.layout
+    }
+
+    pub fn texture(&self) -> &Texture2D {
+        &self.texture
+    }
+
+    /// Region in the pixels of the loaded texture
+    pub fn source(&self, index: usize) -> Result<Rect, AtlasError> {
+        let region = self.layout.region(index)?;
+        let scale = self.texture.size() / self.layout.texture_size;
+        Ok(Rect::new(region.x * scale.x, region.y * scale.y, region.w * scale.x, region.h * scale.y))
+    }
+
+    /// Draw the region. Source rectangle of the params is always set to the region
+    pub fn draw(&self, index: usize, x: f32, y: f32, params: DrawTextureParams) -> Result<(), AtlasError> {
+        let params = DrawTextureParams { source: Some(self.source(index)?), ..params };
+        draw_texture_ex(&self.texture, x, y, WHITE, params);
+        Ok(())
+    }
+
+    pub fn draw_named(&self, name: &str, x: f32, y: f32, params: DrawTextureParams) -> Result<(), AtlasError> {
+        self.draw(self.layout.index_of(name)?, x, y, params)
+    }
+}
+
+
+fn invalid(message: &str) -> AtlasError {
+    AtlasError::Invalid(message.to_owned())
+}
+
+fn positive(field: &str, value: u32) -> Result<u32, AtlasError> {
+    match value {
+        0 => Err(invalid(&format!("field '{}' should be a positive integer", field))),
+        value => Ok(value),
+    }
+}
+
+fn texture_size(width: u32, height: u32) -> Result<Vec2, AtlasError> {
+    Ok(vec2(positive("texture_width", width)? as f32, positive("texture_height", height)? as f32))
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid() {
+        let layout = AtlasLayout::parse(include_str!("../../assets/tiles.ron")).unwrap();
+        assert_eq!(layout.texture_size, vec2(261., 261.));
+        assert_eq!(layout.len(), 400);
+        let cell = 261. / 20.;
+        assert_eq!(layout.region(21).unwrap(), Rect::new(cell, cell, cell, cell));
+        assert_eq!(layout.region(400), Err(AtlasError::OutOfRange { index: 400, count: 400 }));
+
+        let text = "Grid((texture_width: 64, texture_height: 32, columns: 3, rows: 2, sprite_count: Some(5), \
+                    cell_size: (16, 16), position: (8, 0)))";
+        let layout = AtlasLayout::parse(text).unwrap();
+        assert_eq!(layout.len(), 5);
+        assert_eq!(layout.region(4).unwrap(), Rect::new(24., 16., 16., 16.));
+        // Some(..) is optional
+        let layout = AtlasLayout::parse(&text.replace("Some(5)", "5")).unwrap();
+        assert_eq!(layout.len(), 5);
+    }
+
+    #[test]
+    fn test_list() {
+        let text = r#"
+            // Two tiles and the wide one
+            List((
+                texture_width: 48,
+                texture_height: 32,
+                sprites: [
+                    (x: 0, y: 0, width: 16, height: 16, name: "grass"),
+                    (x: 16, y: 0, width: 16, height: 16, name: "water"),
+                    /* no name */ (x: 0, y: 16, width: 48, height: 16),
+                ],
+            ))
+        "#;
+        let layout = AtlasLayout::parse(text).unwrap();
+        assert_eq!(layout.len(), 3);
+        assert_eq!(layout.index_of("water"), Ok(1));
+        assert_eq!(layout.region(2).unwrap(), Rect::new(0., 16., 48., 16.));
+        assert_eq!(layout.index_of("lava"), Err(AtlasError::UnknownRegion("lava".to_owned())));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| AtlasLayout::parse(text).unwrap_err().to_string();
+        assert_eq!(error("Grid((\n  columns: 2,\n  rows 2,\n))"),
+            "Atlas syntax error at line 3, column 8: Expected colon");
+        assert_eq!(error("Grid((texture_width: 10, texture_height: 10, columns: 2))"),
+            "Atlas syntax error at line 1, column 56: Unexpected missing field `rows` in `Grid`");
+        assert_eq!(error("Grid((texture_width: 10, texture_height: 10, columns: 2, rows: 0))"),
+            "Invalid atlas: field 'rows' should be a positive integer");
+        assert!(error("Grid((texture_width: 10, texture_height: 10, columns: 2, rows: 2, colour: 1))")
+            .starts_with("Atlas syntax error at line 1, column 73: Unexpected field named `colour`"));
+        assert!(error("Hex((texture_width: 10))")
+            .starts_with("Atlas syntax error at line 1, column 4: Unexpected variant named `Hex`"));
+        assert_eq!(error("List((texture_width: 10, texture_height: 10, sprites: [(x: 5, y: 0, width: 8, height: 8)]))"),
+            "Invalid atlas: sprite 0 is outside of the texture");
+        assert_eq!(error("List((texture_width: 10, texture_height: 10, sprites: [(x: 0, y: 0, width: 8, height: 8)]"),
+            "Atlas syntax error at line 1, column 90: Unexpected end of RON");
+        assert_eq!(error("Grid((texture_width: \"wide\"))"),
+            "Atlas syntax error at line 1, column 22: Expected integer");
+    }
+}
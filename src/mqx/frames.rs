@@ -1,35 +1,98 @@
 // 2d frames from source image.
 //
+// Frames are rectangles on the texture. Usually they make a grid (rows x columns, numbered row by
+// row), but they can be placed anywhere on the texture (packed sprite sheets).
 
 use macroquad::prelude::*;
 
 
 pub struct Frames {
     texture: Texture2D,
-    num_rows: i32,
+    rects: Vec<Rect>,
+    /// Columns of the grid, 0 if frames don't make a grid
     num_cols: i32,
 }
 
 impl Frames {
+    /// Frames in the grid covering the whole texture
     pub fn new(texture: Texture2D, num_rows: i32, num_cols: i32) -> Frames {
+        let rects = grid(texture.width(), texture.height(), num_rows, num_cols);
         Frames {
             texture,
-            num_rows,
+            rects,
             num_cols,
         }
     }
 
+    pub fn from_rects(texture: Texture2D, rects: Vec<Rect>) -> Frames {
+        Frames { texture, rects, num_cols: 0 }
+    }
+
+    pub fn texture(&self) -> &Texture2D {
+        &self.texture
+    }
+
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+
+    /// Source rectangle of the frame
+    pub fn rect(&self, frame: usize) -> Option<Rect> {
+        self.rects.get(frame).copied()
+    }
+
+    /// Draw the frame from the grid with the top left corner at the given position
     pub fn draw(&self, pos: Vec2, row: i32, col: i32) {
-        let frame_width = self.texture.width() / self.num_rows as f32;
-        let frame_height = self.texture.height() / self.num_cols as f32;
-        let params = DrawTextureParams {
-                dest_size: None,
-                source: Some(Rect::new(row as f32 * frame_width, col as f32 * frame_height, frame_width, frame_height)),
-                rotation: 0.,
-                pivot: None,
-                flip_x: false,
-                flip_y: false,
-            };
+        self.draw_frame((row * self.num_cols + col) as usize, pos);
+    }
+
+    pub fn draw_frame(&self, frame: usize, pos: Vec2) {
+        self.draw_frame_ex(frame, pos, DrawTextureParams::default());
+    }
+
+    /// Draw the frame with the given parameters. Source rectangle is always set to the frame
+    pub fn draw_frame_ex(&self, frame: usize, pos: Vec2, params: DrawTextureParams) {
+        let Some(source) = self.rect(frame) else {
+            return;
+        };
+        let params = DrawTextureParams { source: Some(source), ..params };
         draw_texture_ex(&self.texture, pos.x, pos.y, WHITE, params);
     }
-}
\ No newline at end of file
+}
+
+
+/// Rectangles of the grid cells, row by row
+pub fn grid(width: f32, height: f32, num_rows: i32, num_cols: i32) -> Vec<Rect> {
+    if num_rows <= 0 || num_cols <= 0 {
+        return vec![];
+    }
+    let frame_width = width / num_cols as f32;
+    let frame_height = height / num_rows as f32;
+    (0..num_rows)
+        .flat_map(|row| (0..num_cols).map(move |col| (row, col)))
+        .map(|(row, col)| Rect::new(col as f32 * frame_width, row as f32 * frame_height, frame_width, frame_height))
+        .collect()
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grid() {
+        // Wide texture: 2 rows, 4 columns
+        let rects = grid(400., 100., 2, 4);
+        assert_eq!(rects.len(), 8);
+        assert_eq!(rects[1], Rect::new(100., 0., 100., 50.));
+        assert_eq!(rects[5], Rect::new(100., 50., 100., 50.));
+        assert!(grid(400., 100., 0, 4).is_empty());
+    }
+}
@@ -0,0 +1,402 @@
+// Import of sprite sheets exported by Aseprite and TexturePacker.
+//
+// Both tools write JSON with the `frames` either as an array (each frame has its `filename`) or
+// as a hash keyed by the file name. Frames keep the order of the file.
+//
+// Aseprite adds to the `meta`:
+// * `frameTags`: named ranges of frames with the direction (forward, reverse, pingpong,
+//   pingpong_reverse) and optional repeat count. They become the clips, tag played once
+//   (repeat "1") doesn't loop.
+// * `slices`: named rectangles of the frame with optional pivot (hit boxes, attachment points).
+// Frame `duration` is in milliseconds.
+//
+// TexturePacker has no durations (frames take 100 ms) and clips come from the optional
+// `animations` object: name -> list of frame file names.
+//
+// Rotated frames are not supported. Trimmed frames are drawn without their offset.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+
+use macroquad::prelude::*;
+use serde::de::{Deserializer, MapAccess, Visitor};
+use serde_derive::Deserialize;
+
+use crate::mqx::animation::{Clip, PlayMode, SpriteSheet};
+use crate::mqx::frames::Frames;
+
+
+/// Frame time if the file doesn't give it (ms)
+const DEFAULT_DURATION: f32 = 100.;
+
+
+#[derive(Debug, PartialEq)]
+pub enum ImportError {
+    Io(String),
+    Json(String),
+    /// JSON parsed, but it's not a valid sprite sheet
+    Invalid(String),
+}
+
+/// Named rectangle of the frame (Aseprite slice)
+#[derive(Clone, Debug, PartialEq)]
+pub struct Slice {
+    pub name: String,
+    /// Relative to the frame
+    pub rect: Rect,
+    /// Relative to the rectangle
+    pub pivot: Option<Vec2>,
+}
+
+/// Sprite sheet read from the JSON, without the texture
+#[derive(Clone, Debug, PartialEq)]
+pub struct SheetData {
+    /// Texture file from the meta, relative to the JSON file
+    pub image: Option<String>,
+    pub names: Vec<String>,
+    pub rects: Vec<Rect>,
+    pub clips: Vec<Clip>,
+    pub slices: Vec<Slice>,
+}
+
+
+// ---- JSON layout
+
+#[derive(Deserialize)]
+struct SheetJson {
+    frames: FramesJson,
+    #[serde(default)]
+    meta: MetaJson,
+    /// TexturePacker
+    #[serde(default)]
+    animations: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum FramesJson {
+    Array(Vec<FrameJson>),
+    Hash(Ordered<FrameJson>),
+}
+
+// JSON object with the order of its keys
+struct Ordered<T>(Vec<(String, T)>);
+
+#[derive(Deserialize)]
+struct FrameJson {
+    filename: Option<String>,
+    frame: RectJson,
+    #[serde(default)]
+    rotated: bool,
+    duration: Option<f32>,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+struct RectJson {
+    x: f32,
+    y: f32,
+    w: f32,
+    h: f32,
+}
+
+#[derive(Clone, Copy, Deserialize)]
+struct PointJson {
+    x: f32,
+    y: f32,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MetaJson {
+    image: Option<String>,
+    #[serde(default)]
+    frame_tags: Vec<TagJson>,
+    #[serde(default)]
+    slices: Vec<SliceJson>,
+}
+
+#[derive(Deserialize)]
+struct TagJson {
+    name: String,
+    from: usize,
+    to: usize,
+    #[serde(default = "forward")]
+    direction: String,
+    repeat: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SliceJson {
+    name: String,
+    keys: Vec<SliceKeyJson>,
+}
+
+#[derive(Deserialize)]
+struct SliceKeyJson {
+    bounds: RectJson,
+    pivot: Option<PointJson>,
+}
+
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::Io(err) => write!(f, "Can't read sprite sheet: {}", err),
+            ImportError::Json(err) => write!(f, "Sprite sheet is not valid JSON: {}", err),
+            ImportError::Invalid(message) => write!(f, "Invalid sprite sheet: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+
+impl SheetData {
+    /// Parse the Aseprite or TexturePacker JSON
+    pub fn parse(text: &str) -> Result<Self, ImportError> {
+        let json: SheetJson = serde_json::from_str(text).map_err(|err| ImportError::Json(err.to_string()))?;
+        let frames = match json.frames {
+            FramesJson::Array(frames) => frames.into_iter()
+                .enumerate()
+                .map(|(idx, frame)| (frame.filename.clone().unwrap_or_else(|| idx.to_string()), frame))
+                .collect(),
+            FramesJson::Hash(Ordered(frames)) => frames,
+        };
+        if let Some((name, _)) = frames.iter().find(|(_, frame)| frame.rotated) {
+            return Err(invalid(&format!("frame '{}' is rotated, export without rotation", name)));
+        }
+        let names: Vec<String> = frames.iter().map(|(name, _)| name.clone()).collect();
+        let rects = frames.iter().map(|(_, frame)| frame.frame.rect()).collect();
+        let durations: Vec<f32> = frames.iter()
+            .map(|(_, frame)| frame.duration.unwrap_or(DEFAULT_DURATION) / 1000.)
+            .collect();
+
+        let mut clips = vec![];
+        for tag in &json.meta.frame_tags {
+            clips.push(tag.clip(&durations)?);
+        }
+        for (name, frame_names) in &json.animations {
+            let frames = frame_names.iter()
+                .map(|frame| names.iter().position(|n| n == frame)
+                    .ok_or_else(|| invalid(&format!("animation '{}' has unknown frame '{}'", name, frame))))
+                .collect::<Result<Vec<_>, _>>()?;
+            let times = frames.iter().map(|&f| durations[f]).collect();
+            clips.push(Clip::with_durations(name, frames, times, PlayMode::Loop));
+        }
+        for (idx, clip) in clips.iter().enumerate() {
+            if clips[..idx].iter().any(|c| c.name == clip.name) {
+                return Err(invalid(&format!("clip '{}' is defined twice", clip.name)));
+            }
+        }
+
+        let slices = json.meta.slices.iter()
+            .map(|slice| {
+                let key = slice.keys.first()
+                    .ok_or_else(|| invalid(&format!("slice '{}' has no keys", slice.name)))?;
+                Ok(Slice {
+                    name: slice.name.clone(),
+                    rect: key.bounds.rect(),
+                    pivot: key.pivot.map(|p| vec2(p.x, p.y)),
+                })
+            })
+            .collect::<Result<_, ImportError>>()?;
+
+        Ok(Self { image: json.meta.image, names, rects, clips, slices })
+    }
+
+    /// Read the JSON file. Image path is resolved against the file's directory
+    pub async fn load(path: &str) -> Result<Self, ImportError> {
+        let text = load_string(path).await.map_err(|err| ImportError::Io(format!("{}: {}", path, err)))?;
+        let mut data = Self::parse(&text)?;
+        let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        data.image = data.image.map(|image| dir.join(image).to_string_lossy().into_owned());
+        Ok(data)
+    }
+
+    pub fn slice(&self, name: &str) -> Option<&Slice> {
+        self.slices.iter().find(|s| s.name == name)
+    }
+
+    pub fn sprite_sheet(&self, texture: Texture2D) -> SpriteSheet {
+        SpriteSheet::new(Frames::from_rects(texture, self.rects.clone()), self.clips.clone())
+    }
+}
+
+
+/// Load the JSON and the texture it names
+pub async fn load_sprite_sheet(path: &str) -> Result<SpriteSheet, ImportError> {
+    let data = SheetData::load(path).await?;
+    let image = data.image.as_deref().ok_or_else(|| invalid("meta has no image"))?;
+    let texture = load_texture(image).await.map_err(|err| ImportError::Io(format!("{}: {}", image, err)))?;
+    Ok(data.sprite_sheet(texture))
+}
+
+
+fn invalid(message: &str) -> ImportError {
+    ImportError::Invalid(message.to_owned())
+}
+
+fn forward() -> String {
+    "forward".to_owned()
+}
+
+impl RectJson {
+    fn rect(&self) -> Rect {
+        Rect::new(self.x, self.y, self.w, self.h)
+    }
+}
+
+impl TagJson {
+    fn clip(&self, durations: &[f32]) -> Result<Clip, ImportError> {
+        if self.from > self.to || self.to >= durations.len() {
+            return Err(invalid(&format!(
+                "tag '{}' has frames {}..{}, there are {} frames", self.name, self.from, self.to, durations.len(),
+            )));
+        }
+        let mut frames: Vec<usize> = (self.from..=self.to).collect();
+        let mode = match self.direction.as_str() {
+            "forward" | "reverse" if self.repeat.as_deref() == Some("1") => PlayMode::Once,
+            "forward" | "reverse" => PlayMode::Loop,
+            "pingpong" | "pingpong_reverse" => PlayMode::PingPong,
+            direction => return Err(invalid(&format!("tag '{}' has unknown direction '{}'", self.name, direction))),
+        };
+        if self.direction.ends_with("reverse") {
+            frames.reverse();
+        }
+        let times = frames.iter().map(|&f| durations[f]).collect();
+        Ok(Clip::with_durations(&self.name, frames, times, mode))
+    }
+}
+
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Ordered<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct OrderedVisitor<T>(std::marker::PhantomData<T>);
+
+        impl<'de, T: serde::Deserialize<'de>> Visitor<'de> for OrderedVisitor<T> {
+            type Value = Ordered<T>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("an object")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+                let mut entries = vec![];
+                while let Some(entry) = map.next_entry()? {
+                    entries.push(entry);
+                }
+                Ok(Ordered(entries))
+            }
+        }
+
+        deserializer.deserialize_map(OrderedVisitor(std::marker::PhantomData))
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hash keys which sort differently than the frames go
+    const ASEPRITE: &str = r##"{
+        "frames": {
+            "walk 9.aseprite": { "frame": { "x": 0, "y": 0, "w": 16, "h": 16 }, "duration": 100 },
+            "walk 10.aseprite": { "frame": { "x": 16, "y": 0, "w": 16, "h": 16 }, "duration": 200 },
+            "walk 11.aseprite": { "frame": { "x": 32, "y": 0, "w": 16, "h": 16 }, "duration": 300 },
+            "walk 12.aseprite": { "frame": { "x": 48, "y": 0, "w": 16, "h": 16 }, "duration": 400 }
+        },
+        "meta": {
+            "app": "https://www.aseprite.org/",
+            "image": "walk.png",
+            "frameTags": [
+                { "name": "step", "from": 0, "to": 2, "direction": "forward" },
+                { "name": "back", "from": 1, "to": 3, "direction": "reverse", "repeat": "1" },
+                { "name": "sway", "from": 0, "to": 3, "direction": "pingpong_reverse" }
+            ],
+            "slices": [
+                { "name": "feet", "color": "#0000ffff", "keys": [
+                    { "frame": 0, "bounds": { "x": 4, "y": 12, "w": 8, "h": 4 }, "pivot": { "x": 4, "y": 4 } }
+                ] }
+            ]
+        }
+    }"##;
+
+    #[test]
+    fn test_aseprite() {
+        let data = SheetData::parse(ASEPRITE).unwrap();
+        assert_eq!(data.image.as_deref(), Some("walk.png"));
+        assert_eq!(data.names[1], "walk 10.aseprite");
+        assert_eq!(data.rects[3], Rect::new(48., 0., 16., 16.));
+        assert_eq!(data.clips.len(), 3);
+
+        let step = &data.clips[0];
+        assert_eq!((step.frames.clone(), step.mode), (vec![0, 1, 2], PlayMode::Loop));
+        assert!((step.duration() - 0.6).abs() < 1e-6);
+        let back = &data.clips[1];
+        assert_eq!((back.frames.clone(), back.mode), (vec![3, 2, 1], PlayMode::Once));
+        assert_eq!(back.durations, vec![0.4, 0.3, 0.2]);
+        let sway = &data.clips[2];
+        assert_eq!((sway.frames.clone(), sway.mode), (vec![3, 2, 1, 0], PlayMode::PingPong));
+
+        let feet = data.slice("feet").unwrap();
+        assert_eq!(feet.rect, Rect::new(4., 12., 8., 4.));
+        assert_eq!(feet.pivot, Some(vec2(4., 4.)));
+    }
+
+    #[test]
+    fn test_texture_packer() {
+        let text = r#"{
+            "frames": [
+                { "filename": "jump_01.png", "frame": { "x": 0, "y": 0, "w": 20, "h": 30 }, "rotated": false,
+                  "trimmed": false, "sourceSize": { "w": 20, "h": 30 } },
+                { "filename": "jump_02.png", "frame": { "x": 20, "y": 0, "w": 20, "h": 30 } },
+                { "filename": "idle.png", "frame": { "x": 40, "y": 0, "w": 20, "h": 30 } }
+            ],
+            "animations": { "jump": ["jump_01.png", "jump_02.png"] },
+            "meta": { "app": "https://www.codeandweb.com/texturepacker", "image": "hero.png",
+                      "size": { "w": 60, "h": 30 } }
+        }"#;
+        let data = SheetData::parse(text).unwrap();
+        assert_eq!(data.names, vec!["jump_01.png", "jump_02.png", "idle.png"]);
+        assert_eq!(data.clips.len(), 1);
+        assert_eq!(data.clips[0].frames, vec![0, 1]);
+        assert!((data.clips[0].duration() - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_bird() {
+        let data = SheetData::parse(include_str!("../../assets/flapping_bird.json")).unwrap();
+        assert_eq!(data.rects.len(), 25);
+        assert_eq!(data.rects[7], Rect::new(368., 184., 184., 184.));
+        assert!(data.clips.iter().any(|c| c.name == "flap"));
+        assert!(data.clips.iter().any(|c| c.name == "dive"));
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| SheetData::parse(text).unwrap_err();
+        let frame = r#"{ "frame": { "x": 0, "y": 0, "w": 8, "h": 8 } }"#;
+        assert!(matches!(error("{ \"frames\": "), ImportError::Json(_)));
+        assert_eq!(
+            error(&format!(r#"{{ "frames": [{}], "meta": {{ "frameTags": [{{ "name": "run", "from": 0, "to": 1 }}] }} }}"#, frame)),
+            invalid("tag 'run' has frames 0..1, there are 1 frames"),
+        );
+        assert_eq!(
+            error(&format!(r#"{{ "frames": {{ "a": {} }}, "animations": {{ "run": ["b"] }} }}"#, frame)),
+            invalid("animation 'run' has unknown frame 'b'"),
+        );
+        assert_eq!(
+            error(r#"{ "frames": { "a": { "frame": { "x": 0, "y": 0, "w": 8, "h": 8 }, "rotated": true } } }"#),
+            invalid("frame 'a' is rotated, export without rotation"),
+        );
+        assert_eq!(
+            error(&format!(r#"{{ "frames": [{}], "meta": {{ "frameTags": [{{ "name": "a", "from": 0, "to": 0,
+                "direction": "sideways" }}] }} }}"#, frame)),
+            invalid("tag 'a' has unknown direction 'sideways'"),
+        );
+    }
+}
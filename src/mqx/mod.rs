@@ -1,6 +1,10 @@
 /// Macroquad extensions
 
+pub mod animation;
+pub mod animator;
+pub mod atlas;
 pub mod drawx;
 pub mod frames;
+pub mod import;
 pub mod input;
 pub mod plot;
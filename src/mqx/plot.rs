@@ -57,13 +57,13 @@ impl Plot {
                 .map(|&x| x.into())
                 .reduce(f32::max)
                 .unwrap();
-            let scale_y = chart_area.h / (dy_max - dy_min);
+            let scale_y = if dy_max > dy_min { chart_area.h / (dy_max - dy_min) } else { 0. };
 
             let ps: Vec<Vec2> = xs.iter().enumerate()
                 .map(|(i, x)| 
                     Vec2::new(
                         chart_area.x + i as f32 * dx, 
-                        chart_area.y + chart_area.h - x * scale_y))
+                        chart_area.y + chart_area.h - (x - dy_min) * scale_y))
                 .collect();
 
             for i in 1..ps.len() {
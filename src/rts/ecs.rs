@@ -0,0 +1,228 @@
+//! RTS simulation build on top of ECS (bevy_ecs).
+//!
+//! Units are entities with `Position`, `Velocity`, `Path`, `Owner` and `Health` components.
+//! Every tick runs the following systems:
+//!  * pathfinding: finds path for every unit with `MoveRequest`
+//!  * movement: moves units along their paths (the same way as `Universe::tick`)
+//!  * combat: units with `Weapon` damage the closest enemy in range. Dead units are removed.
+//!
+
+use bevy_ecs::prelude::*;
+use glam::Vec2;
+
+use crate::rts::pathfinding as pf;
+use crate::rts::universe::{step_towards, UNIT_SPEED};
+use crate::rts::WorldMap;
+
+
+#[derive(Resource)]
+pub struct Map(pub WorldMap);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Position(pub Vec2);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Velocity {
+    /// Point which unit is moving to (center of the next cell on the path)
+    pub dest: Vec2,
+    pub speed: f32,
+    pub is_moving: bool,
+}
+
+/// Cells (centers) which unit still needs to visit
+#[derive(Component, Clone, Debug, Default, PartialEq)]
+pub struct Path(pub Vec<Vec2>);
+
+/// Index of the player owning the unit
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Owner(pub usize);
+
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Health(pub f32);
+
+/// Units with weapon attack enemies
+#[derive(Component, Clone, Copy, Debug, PartialEq)]
+pub struct Weapon {
+    pub range: f32,
+    /// Damage dealt in every tick
+    pub damage: f32,
+}
+
+/// Request to find path to the given cell. Removed after the path is found.
+#[derive(Component, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MoveRequest {
+    pub x: usize,
+    pub y: usize,
+}
+
+pub struct EcsUniverse {
+    pub world: World,
+    schedule: Schedule,
+}
+
+
+impl Velocity {
+    pub fn new(dest: Vec2) -> Self {
+        Self { dest, speed: UNIT_SPEED, is_moving: false }
+    }
+}
+
+impl EcsUniverse {
+    pub fn from_map(map: WorldMap) -> Self {
+        let mut world = World::new();
+        world.insert_resource(Map(map));
+
+        let mut schedule = Schedule::default();
+        schedule.add_systems((pathfinding, movement, combat).chain());
+
+        Self { world, schedule }
+    }
+
+    pub fn add_unit(&mut self, owner: usize, pos_x: usize, pos_y: usize) -> Entity {
+        let pos = Vec2::new(pos_x as f32 + 0.5, pos_y as f32 + 0.5);
+        self.world
+            .spawn((Position(pos), Velocity::new(pos), Path::default(), Owner(owner), Health(100.)))
+            .id()
+    }
+
+    /// Ask unit to move to a given cell. Path is found in the next tick
+    pub fn move_unit(&mut self, unit: Entity, x: usize, y: usize) {
+        if let Ok(mut entity) = self.world.get_entity_mut(unit) {
+            entity.insert(MoveRequest { x, y });
+        }
+    }
+
+    // Clock tick. update sim state
+    pub fn tick(&mut self) {
+        self.schedule.run(&mut self.world);
+    }
+
+    pub fn position(&self, unit: Entity) -> Option<Vec2> {
+        self.world.get::<Position>(unit).map(|p| p.0)
+    }
+
+    pub fn num_units(&mut self) -> usize {
+        self.world.query::<&Owner>().iter(&self.world).count()
+    }
+}
+
+
+fn pathfinding(
+    mut commands: Commands,
+    map: Res<Map>,
+    mut query: Query<(Entity, &Velocity, &mut Path, &MoveRequest)>,
+) {
+    for (entity, velocity, mut path, request) in &mut query {
+        let pos = (velocity.dest.x as i32, velocity.dest.y as i32);
+        path.0 = pf::find_path(&map.0, pos, (request.x as i32, request.y as i32))
+            .into_iter().map(|(i, j)| Vec2::new(i as f32 + 0.5, j as f32 + 0.5))
+            .collect();
+        commands.entity(entity).remove::<MoveRequest>();
+    }
+}
+
+fn movement(mut query: Query<(&mut Position, &mut Velocity, &mut Path)>) {
+    for (mut position, mut velocity, mut path) in &mut query {
+        if let Some(&dest) = path.0.first() {
+            if position.0 != dest {
+                velocity.dest = dest;
+            } else {
+                path.0.remove(0);
+            }
+            velocity.is_moving = step_towards(&mut position.0, velocity.dest, velocity.speed);
+        }
+    }
+}
+
+fn combat(
+    mut commands: Commands,
+    attackers: Query<(&Position, &Owner, &Weapon)>,
+    mut targets: Query<(Entity, &Position, &Owner, &mut Health)>,
+) {
+    let mut hits = vec![];
+    for (position, owner, weapon) in &attackers {
+        let target = targets.iter()
+            .filter(|(_, p, o, _)| *o != owner && p.0.distance(position.0) <= weapon.range)
+            .min_by(|(_, a, _, _), (_, b, _, _)|
+                a.0.distance(position.0).total_cmp(&b.0.distance(position.0)))
+            .map(|(entity, _, _, _)| entity);
+        if let Some(target) = target {
+            hits.push((target, weapon.damage));
+        }
+    }
+
+    for (target, damage) in hits {
+        if let Ok((_, _, _, mut health)) = targets.get_mut(target) {
+            health.0 -= damage;
+        }
+    }
+    for (entity, _, _, health) in &targets {
+        if health.0 <= 0. {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rts::Universe;
+
+    fn map() -> WorldMap {
+        WorldMap::from_string("
+        ##########
+        #        #
+        #  ##    #
+        #  #     #
+        ##########
+        ")
+    }
+
+    #[test]
+    fn test_same_as_universe() {
+        let mut universe = Universe::from_map(map());
+        universe.add_unit(1, 1);
+        universe.add_unit(8, 3);
+        let mut ecs = EcsUniverse::from_map(map());
+        let units = [ecs.add_unit(0, 1, 1), ecs.add_unit(0, 8, 3)];
+
+        let orders = [(0, 0, 8, 3), (0, 1, 1, 3), (40, 0, 2, 1), (60, 1, 8, 1)];
+        for tick in 0..200 {
+            for &(_, unit, x, y) in orders.iter().filter(|o| o.0 == tick) {
+                universe.move_unit(unit, x, y);
+                ecs.move_unit(units[unit], x, y);
+            }
+            universe.tick();
+            ecs.tick();
+
+            for (idx, entity) in units.iter().enumerate() {
+                assert_eq!(ecs.position(*entity), Some(universe.units[idx].pos), "tick {}", tick);
+            }
+        }
+    }
+
+    #[test]
+    fn test_combat() {
+        let mut ecs = EcsUniverse::from_map(map());
+        let attacker = ecs.add_unit(0, 1, 1);
+        let friend = ecs.add_unit(0, 2, 1);
+        let enemy = ecs.add_unit(1, 3, 1);
+        let far_enemy = ecs.add_unit(1, 8, 1);
+        ecs.world.entity_mut(attacker).insert(Weapon { range: 3., damage: 30. });
+
+        for _ in 0..3 {
+            ecs.tick();
+        }
+        assert_eq!(ecs.world.get::<Health>(enemy), Some(&Health(10.)));
+        assert_eq!(ecs.world.get::<Health>(friend), Some(&Health(100.)));
+
+        ecs.tick();
+        assert!(ecs.position(enemy).is_none());
+        assert!(ecs.position(far_enemy).is_some());
+        assert_eq!(ecs.num_units(), 3);
+    }
+}
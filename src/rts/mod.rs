@@ -1,4 +1,8 @@
 
+pub mod ecs;
+pub mod scenario;
+pub mod simulation;
+pub mod tiled;
 pub mod universe;
 pub mod world_map;
 mod pathfinding;
@@ -0,0 +1,519 @@
+//! Scenario describes the initial state of the RTS game: map, players, their units and resources
+//! and the conditions for winning the game.
+//!
+//! Scenarios are stored as TOML files:
+//!
+//! ```toml
+//! name = "Paper bag"
+//!
+//! [map]
+//! source = "mapgen"              # or "ascii" (with `rows`) or "file" (with `path`)
+//! width = 80
+//! height = 60
+//! seed = 42                      # optional. Seed given to the loader or random map on every run if missing
+//! filters = ["noise", "cellular_automata", "start_center", "cull_unreachable", "distant_exit"]
+//!
+//! [[players]]
+//! name = "Blue"
+//! resources = { gold = 100 }
+//!
+//! [[units]]
+//! player = "Blue"
+//! at = "start"                   # named map point or `x` and `y` coordinates
+//!
+//! [[victory]]
+//! condition = "reach_point"      # or "eliminate", "collect" (with `resource` and `amount`)
+//! point = "exit"
+//! ```
+//!
+
+use std::{collections::HashMap, fmt, fs, path::Path};
+
+use mapgen::{filter::*, MapBuilder, MapFilter};
+use rand::{rngs::StdRng, SeedableRng};
+use serde_derive::Deserialize;
+use toml::Spanned;
+
+use crate::rts::{tiled, MapPoint, Player, Universe, WorldMap};
+
+
+#[derive(Debug, PartialEq)]
+pub struct Issue {
+    /// Line in the scenario file (if the issue is related to some part of the file)
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+/// All problems found in the scenario file
+#[derive(Debug)]
+pub struct ScenarioError {
+    pub issues: Vec<Issue>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Victory {
+    /// Player wins when any of its units reaches given map point
+    ReachPoint { point: String },
+    /// Last player with units wins
+    Eliminate,
+    /// Player wins when it has given amount of the resource
+    Collect { resource: String, amount: u32 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StartingUnit {
+    pub owner: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Validated scenario, ready to start the game
+pub struct Scenario {
+    pub name: String,
+    pub map: WorldMap,
+    pub players: Vec<Player>,
+    pub units: Vec<StartingUnit>,
+    pub victory: Vec<Victory>,
+}
+
+
+impl Issue {
+    fn new(line: Option<usize>, message: String) -> Self {
+        Self { line, message }
+    }
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "line {}: {}", line, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Invalid scenario:")?;
+        for issue in &self.issues {
+            writeln!(f, "  {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<Issue> for ScenarioError {
+    fn from(issue: Issue) -> Self {
+        ScenarioError { issues: vec![issue] }
+    }
+}
+
+
+impl Scenario {
+    /// Load scenario from the file. Map files are resolved relative to the scenario file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Scenario, ScenarioError> {
+        Scenario::load_seeded(path, None)
+    }
+
+    /// Load scenario. Generated map without its own seed uses the given one
+    pub fn load_seeded<P: AsRef<Path>>(path: P, seed: Option<u64>) -> Result<Scenario, ScenarioError> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|err| Issue::new(None, format!("Can't read {}: {}", path.display(), err)))?;
+        Scenario::parse_seeded(&contents, path.parent().unwrap_or(Path::new(".")), seed)
+    }
+
+    /// Parse scenario from the string.
+    pub fn parse(contents: &str, base_dir: &Path) -> Result<Scenario, ScenarioError> {
+        Scenario::parse_seeded(contents, base_dir, None)
+    }
+
+    /// Parse scenario from the string. Generated map without its own seed uses the given one
+    pub fn parse_seeded(contents: &str, base_dir: &Path, seed: Option<u64>) -> Result<Scenario, ScenarioError> {
+        let lines = LineIndex::new(contents);
+        let raw: RawScenario = toml::from_str(contents)
+            .map_err(|err| Issue::new(err.span().map(|s| lines.line(s.start)), err.message().to_owned()))?;
+
+        let mut issues = vec![];
+        let map = MapSource::build(&raw.map, &lines, base_dir, seed, &mut issues);
+
+        if raw.players.is_empty() {
+            issues.push(Issue::new(None, "Scenario needs at least one player".to_owned()));
+        }
+        let mut players: Vec<Player> = vec![];
+        for def in &raw.players {
+            if players.iter().any(|p| p.name == def.get_ref().name) {
+                issues.push(lines.issue(def, format!("Duplicated player '{}'", def.get_ref().name)));
+            }
+            let mut player = Player::new(&def.get_ref().name);
+            player.resources = def.get_ref().resources.clone();
+            players.push(player);
+        }
+
+        let mut units = vec![];
+        for def in &raw.units {
+            if let Some(unit) = def.get_ref().resolve(&players, map.as_ref(), &lines, def, &mut issues) {
+                units.push(unit);
+            }
+        }
+
+        let mut victory = vec![];
+        for def in &raw.victory {
+            let condition = def.get_ref().clone().into_victory();
+            if let (Victory::ReachPoint { point }, Some(map)) = (&condition, &map) {
+                if map.point(point).is_none() {
+                    issues.push(lines.issue(def, format!("Map has no point named '{}'", point)));
+                }
+            }
+            victory.push(condition);
+        }
+
+        match map {
+            Some(map) if issues.is_empty() => Ok(Scenario { name: raw.name, map, players, units, victory }),
+            _ => Err(ScenarioError { issues }),
+        }
+    }
+
+    /// Create game universe in the initial state of this scenario
+    pub fn universe(&self) -> Universe {
+        let mut universe = Universe::from_map(self.map.clone());
+        for player in &self.players {
+            let mut copy = Player::new(&player.name);
+            copy.resources = player.resources.clone();
+            universe.add_player(copy);
+        }
+        for unit in &self.units {
+            universe.add_player_unit(unit.owner, unit.x, unit.y);
+        }
+        universe
+    }
+
+    /// Check victory conditions. Returns index of the winning player
+    pub fn winner(&self, universe: &Universe) -> Option<usize> {
+        self.victory.iter().find_map(|condition| match condition {
+            Victory::ReachPoint { point } => {
+                let point = universe.map.point(point)?;
+                universe.units.iter()
+                    .find(|u| u.pos.x as usize == point.x && u.pos.y as usize == point.y)
+                    .map(|u| u.owner)
+            }
+            Victory::Eliminate => {
+                let mut alive: Vec<usize> = universe.units.iter().map(|u| u.owner).collect();
+                alive.sort();
+                alive.dedup();
+                if universe.players.len() > 1 && alive.len() == 1 { Some(alive[0]) } else { None }
+            }
+            Victory::Collect { resource, amount } => universe.players.iter()
+                .position(|p| p.resource(resource) >= *amount),
+        })
+    }
+}
+
+
+// ------------------------------------------------------------------------------------------------
+// File format
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawScenario {
+    #[serde(default)]
+    name: String,
+    map: Spanned<MapSource>,
+    #[serde(default)]
+    players: Vec<Spanned<PlayerDef>>,
+    #[serde(default)]
+    units: Vec<Spanned<UnitDef>>,
+    #[serde(default)]
+    victory: Vec<Spanned<VictoryDef>>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case", deny_unknown_fields)]
+enum MapSource {
+    Ascii {
+        rows: String,
+        #[serde(default)]
+        points: Vec<PointDef>,
+    },
+    File {
+        path: String,
+    },
+    Mapgen {
+        width: usize,
+        height: usize,
+        seed: Option<u64>,
+        filters: Vec<String>,
+    },
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PointDef {
+    name: String,
+    x: usize,
+    y: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PlayerDef {
+    name: String,
+    #[serde(default)]
+    resources: HashMap<String, u32>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct UnitDef {
+    player: String,
+    at: Option<String>,
+    x: Option<usize>,
+    y: Option<usize>,
+}
+
+#[derive(Clone, Deserialize)]
+#[serde(tag = "condition", rename_all = "snake_case", deny_unknown_fields)]
+enum VictoryDef {
+    ReachPoint { point: String },
+    Eliminate,
+    Collect { resource: String, amount: u32 },
+}
+
+// Converts byte offsets into line numbers
+struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(text: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { line_starts }
+    }
+
+    fn line(&self, offset: usize) -> usize {
+        self.line_starts.partition_point(|&start| start <= offset)
+    }
+
+    fn issue<T>(&self, item: &Spanned<T>, message: String) -> Issue {
+        Issue::new(Some(self.line(item.span().start)), message)
+    }
+}
+
+impl MapSource {
+    fn build(source: &Spanned<MapSource>, lines: &LineIndex, base_dir: &Path, default_seed: Option<u64>,
+             issues: &mut Vec<Issue>) -> Option<WorldMap> {
+        match source.get_ref() {
+            MapSource::Ascii { rows, points } => {
+                let mut map = WorldMap::from_string(rows);
+                map.points = points.iter().map(|p| MapPoint::new(&p.name, p.x, p.y)).collect();
+                Some(map)
+            }
+            MapSource::File { path } => {
+                let path = base_dir.join(path);
+                let map = match path.extension().and_then(|e| e.to_str()) {
+                    Some("json" | "tmj" | "tmx") => tiled::load(&path).map_err(|err| err.to_string()),
+                    _ => fs::read_to_string(&path)
+                        .map(|text| WorldMap::from_string(&text))
+                        .map_err(|err| format!("Can't read {}: {}", path.display(), err)),
+                };
+                map.map_err(|err| issues.push(lines.issue(source, err))).ok()
+            }
+            MapSource::Mapgen { width, height, seed, filters } => {
+                if *width == 0 || *height == 0 {
+                    issues.push(lines.issue(source, "Map size has to be greater then 0".to_owned()));
+                    return None;
+                }
+                let mut builder = MapBuilder::new(*width, *height);
+                let mut is_valid = true;
+                for name in filters {
+                    match map_filter(name) {
+                        Some(filter) => { builder.with(filter); }
+                        None => {
+                            is_valid = false;
+                            issues.push(lines.issue(source, format!("Unknown map filter '{}'", name)));
+                        }
+                    }
+                }
+                if !is_valid {
+                    return None;
+                }
+                let map = match seed.or(default_seed) {
+                    Some(seed) => builder.build_with_rng(&mut StdRng::seed_from_u64(seed)),
+                    None => builder.build(),
+                };
+                Some(WorldMap::from(&map))
+            }
+        }
+    }
+}
+
+impl UnitDef {
+    fn resolve(&self, players: &[Player], map: Option<&WorldMap>, lines: &LineIndex,
+               span: &Spanned<UnitDef>, issues: &mut Vec<Issue>) -> Option<StartingUnit> {
+        let owner = players.iter().position(|p| p.name == self.player);
+        if owner.is_none() {
+            issues.push(lines.issue(span, format!("Unknown player '{}'", self.player)));
+        }
+        let pos = match (&self.at, self.x, self.y) {
+            (Some(name), None, None) => match map?.point(name) {
+                Some(point) => Some((point.x, point.y)),
+                None => {
+                    issues.push(lines.issue(span, format!("Map has no point named '{}'", name)));
+                    None
+                }
+            },
+            (None, Some(x), Some(y)) => Some((x, y)),
+            _ => {
+                issues.push(lines.issue(span, "Unit needs either `at` or both `x` and `y`".to_owned()));
+                None
+            }
+        };
+        let (x, y) = pos?;
+        let map = map?;
+        if x >= map.width || y >= map.height {
+            issues.push(lines.issue(span, format!("Unit position ({}, {}) is outside the map", x, y)));
+            return None;
+        }
+        if map.at(x, y).is_blocked {
+            issues.push(lines.issue(span, format!("Unit position ({}, {}) is blocked", x, y)));
+            return None;
+        }
+        owner.map(|owner| StartingUnit { owner, x, y })
+    }
+}
+
+impl VictoryDef {
+    fn into_victory(self) -> Victory {
+        match self {
+            VictoryDef::ReachPoint { point } => Victory::ReachPoint { point },
+            VictoryDef::Eliminate => Victory::Eliminate,
+            VictoryDef::Collect { resource, amount } => Victory::Collect { resource, amount },
+        }
+    }
+}
+
+fn map_filter(name: &str) -> Option<Box<dyn MapFilter>> {
+    let filter: Box<dyn MapFilter> = match name {
+        "noise" => NoiseGenerator::uniform(),
+        "cellular_automata" => CellularAutomata::new(),
+        "simple_rooms" => SimpleRooms::new(),
+        "bsp_rooms" => BspRooms::new(),
+        "bsp_interior" => BspInterior::new(),
+        "nearest_corridors" => NearestCorridors::new(),
+        "drunkards_walk" => DrunkardsWalk::open_area(),
+        "maze" => MazeBuilder::new(),
+        "voronoi" => VoronoiHive::new(),
+        "start_center" => AreaStartingPosition::new(XStart::CENTER, YStart::CENTER),
+        "start_top_left" => AreaStartingPosition::new(XStart::LEFT, YStart::TOP),
+        "start_bottom_right" => AreaStartingPosition::new(XStart::RIGHT, YStart::BOTTOM),
+        "cull_unreachable" => CullUnreachable::new(),
+        "distant_exit" => DistantExit::new(),
+        _ => return None,
+    };
+    Some(filter)
+}
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENARIO: &str = r#"
+name = "Corridor"
+
+[map]
+source = "ascii"
+rows = """
+#######
+#     #
+#######
+"""
+points = [{ name = "exit", x = 5, y = 1 }]
+
+[[players]]
+name = "Blue"
+resources = { gold = 100 }
+
+[[players]]
+name = "Red"
+
+[[units]]
+player = "Blue"
+x = 1
+y = 1
+
+[[victory]]
+condition = "reach_point"
+point = "exit"
+"#;
+
+    fn parse(text: &str) -> Result<Scenario, ScenarioError> {
+        Scenario::parse(text, Path::new("."))
+    }
+
+    #[test]
+    fn test_parse() {
+        let scenario = parse(SCENARIO).unwrap();
+        assert_eq!(scenario.name, "Corridor");
+        assert_eq!(scenario.map.width, 7);
+        assert_eq!(scenario.players[0].resource("gold"), 100);
+        assert_eq!(scenario.units, vec![StartingUnit { owner: 0, x: 1, y: 1 }]);
+
+        let mut universe = scenario.universe();
+        assert_eq!(scenario.winner(&universe), None);
+        universe.units[0].pos = glam::Vec2::new(5.5, 1.5);
+        assert_eq!(scenario.winner(&universe), Some(0));
+    }
+
+    #[test]
+    fn test_seeded_mapgen() {
+        let text = r#"
+[map]
+source = "mapgen"
+width = 40
+height = 30
+seed = 7
+filters = ["noise", "cellular_automata", "start_center", "cull_unreachable", "distant_exit"]
+
+[[players]]
+name = "Blue"
+
+[[units]]
+player = "Blue"
+at = "start"
+"#;
+        let a = parse(text).unwrap();
+        let b = parse(text).unwrap();
+        assert_eq!(a.map.tiles, b.map.tiles);
+        assert_eq!(a.map.point("start").map(|p| (p.x, p.y)), Some((a.units[0].x, a.units[0].y)));
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let text = SCENARIO
+            .replace("player = \"Blue\"\nx = 1", "player = \"Green\"\nx = 0")
+            .replace("point = \"exit\"", "point = \"treasure\"");
+        let issues = parse(&text).err().unwrap().issues;
+        let lines: Vec<Option<usize>> = issues.iter().map(|i| i.line).collect();
+
+        assert_eq!(issues.len(), 3);
+        assert!(issues[0].message.contains("Unknown player 'Green'"));
+        assert!(issues[1].message.contains("blocked"));
+        assert!(issues[2].message.contains("treasure"));
+        assert_eq!(lines, vec![Some(20), Some(20), Some(25)]);
+    }
+
+    #[test]
+    fn test_syntax_error_line() {
+        let issues = parse("[map]\nsource = \"mapgen\"\nwidth = \"wide\"\n").err().unwrap().issues;
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].line.is_some());
+    }
+}
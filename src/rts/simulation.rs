@@ -0,0 +1,320 @@
+//! Headless simulation of the universe. Used for measuring unit movement without the window.
+//!
+//! Units are controlled either by the command script:
+//!
+//! ```toml
+//! [[commands]]
+//! tick = 0
+//! unit = 0
+//! x = 10
+//! y = 12
+//! ```
+//!
+//! or by the simple AI which sends idle units to the random reachable tiles.
+//!
+
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::rts::{Components, Universe};
+
+
+/// Unit which didn't move for so many ticks before reaching destination is stuck
+pub const STUCK_TICKS: u64 = 50;
+
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Command {
+    pub tick: u64,
+    pub unit: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+/// Who controls the units
+pub enum Driver {
+    Script(Vec<Command>),
+    Wander { seed: u64 },
+}
+
+/// Single move order and its result
+#[derive(Clone, Debug, Serialize)]
+pub struct Trip {
+    pub unit: usize,
+    pub issued_at: u64,
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub path_found: bool,
+    /// Number of steps in the path
+    pub path_length: usize,
+    pub ticks_to_arrival: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    pub ticks: u64,
+    pub trips: Vec<Trip>,
+    pub trips_arrived: usize,
+    /// Units which stopped before reaching the destination of their last trip
+    pub units_stuck: Vec<usize>,
+    pub mean_path_length: f32,
+    pub mean_ticks_to_arrival: Option<f32>,
+    pub pathfinding_calls: usize,
+    pub pathfinding_time_us: u128,
+}
+
+#[derive(Deserialize)]
+struct CommandScript {
+    #[serde(default)]
+    commands: Vec<Command>,
+}
+
+pub struct Simulation {
+    pub universe: Universe,
+    driver: Driver,
+    rng: StdRng,
+    components: Components,
+    tick: u64,
+    trips: Vec<Trip>,
+    // Index of the last trip of each unit
+    active_trips: Vec<Option<usize>>,
+    // Tick when the unit moved the last time
+    last_moved: Vec<u64>,
+    pathfinding_time: Duration,
+}
+
+
+/// Parse command script
+pub fn parse_commands(text: &str) -> Result<Vec<Command>, toml::de::Error> {
+    let script: CommandScript = toml::from_str(text)?;
+    let mut commands = script.commands;
+    commands.sort_by_key(|c| c.tick);
+    Ok(commands)
+}
+
+
+impl Simulation {
+    pub fn new(universe: Universe, driver: Driver) -> Self {
+        let seed = match driver {
+            Driver::Wander { seed } => seed,
+            Driver::Script(_) => 0,
+        };
+        let components = universe.map.components();
+        let active_trips = vec![None; universe.units.len()];
+        let last_moved = vec![0; universe.units.len()];
+        Self {
+            universe,
+            driver,
+            rng: StdRng::seed_from_u64(seed),
+            components,
+            tick: 0,
+            trips: vec![],
+            active_trips,
+            last_moved,
+            pathfinding_time: Duration::ZERO,
+        }
+    }
+
+    /// Run simulation for a given number of ticks
+    pub fn run(&mut self, ticks: u64) {
+        for _ in 0..ticks {
+            self.step();
+        }
+    }
+
+    /// Issue commands for the current tick and advance simulation by one tick
+    pub fn step(&mut self) {
+        for (unit, x, y) in self.orders() {
+            self.move_unit(unit, x, y);
+        }
+        let positions: Vec<_> = self.universe.units.iter().map(|u| u.pos).collect();
+        self.universe.tick();
+        self.tick += 1;
+
+        for (unit_id, unit) in self.universe.units.iter().enumerate() {
+            if unit.pos != positions[unit_id] {
+                self.last_moved[unit_id] = self.tick;
+            }
+            if let Some(trip) = self.active_trips[unit_id].map(|idx| &mut self.trips[idx]) {
+                if trip.ticks_to_arrival.is_none() && trip.path_found && unit.path.is_empty() {
+                    trip.ticks_to_arrival = Some(self.tick - trip.issued_at);
+                }
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> Metrics {
+        let arrived: Vec<u64> = self.trips.iter().filter_map(|t| t.ticks_to_arrival).collect();
+        let units_stuck = self.active_trips.iter().enumerate()
+            .filter_map(|(unit, trip)| trip.map(|idx| (unit, &self.trips[idx])))
+            .filter(|(unit, trip)| 
+                trip.ticks_to_arrival.is_none() && 
+                self.tick - self.last_moved[*unit].max(trip.issued_at) >= STUCK_TICKS)
+            .map(|(unit, _)| unit)
+            .collect();
+        let mean_path_length = if self.trips.is_empty() {
+            0.
+        } else {
+            self.trips.iter().map(|t| t.path_length).sum::<usize>() as f32 / self.trips.len() as f32
+        };
+        let mean_ticks_to_arrival = if arrived.is_empty() {
+            None
+        } else {
+            Some(arrived.iter().sum::<u64>() as f32 / arrived.len() as f32)
+        };
+
+        Metrics {
+            ticks: self.tick,
+            trips: self.trips.clone(),
+            trips_arrived: arrived.len(),
+            units_stuck,
+            mean_path_length,
+            mean_ticks_to_arrival,
+            pathfinding_calls: self.trips.len(),
+            pathfinding_time_us: self.pathfinding_time.as_micros(),
+        }
+    }
+
+    // Move orders (unit, x, y) for the current tick
+    fn orders(&mut self) -> Vec<(usize, usize, usize)> {
+        match &self.driver {
+            Driver::Script(commands) => commands.iter()
+                .filter(|c| c.tick == self.tick)
+                .map(|c| (c.unit, c.x, c.y))
+                .collect(),
+            Driver::Wander { .. } => {
+                let map = &self.universe.map;
+                let mut orders = vec![];
+                for (unit_id, unit) in self.universe.units.iter().enumerate() {
+                    if !unit.path.is_empty() {
+                        continue;
+                    }
+                    let from = (unit.pos.x as usize, unit.pos.y as usize);
+                    // Pick random tile from the same connected area
+                    for _ in 0..100 {
+                        let to = (self.rng.gen_range(0..map.width), self.rng.gen_range(0..map.height));
+                        if to != from && self.components.is_connected(from, to) {
+                            orders.push((unit_id, to.0, to.1));
+                            break;
+                        }
+                    }
+                }
+                orders
+            }
+        }
+    }
+
+    fn move_unit(&mut self, unit_id: usize, x: usize, y: usize) {
+        let Some(unit) = self.universe.units.get(unit_id) else {
+            return;
+        };
+        let from = (unit.pos.x as usize, unit.pos.y as usize);
+        let start = Instant::now();
+        self.universe.move_unit(unit_id, x, y);
+        self.pathfinding_time += start.elapsed();
+
+        let path = &self.universe.units[unit_id].path;
+        self.trips.push(Trip {
+            unit: unit_id,
+            issued_at: self.tick,
+            from,
+            to: (x, y),
+            path_found: !path.is_empty(),
+            path_length: path.len().saturating_sub(1),
+            ticks_to_arrival: None,
+        });
+        self.active_trips[unit_id] = Some(self.trips.len() - 1);
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rts::WorldMap;
+    use crate::rts::scenario::Scenario;
+
+    fn universe() -> Universe {
+        let map = WorldMap::from_string("
+        ##########
+        #        #
+        #        #
+        ##########
+        ");
+        let mut universe = Universe::from_map(map);
+        universe.add_unit(1, 1);
+        universe
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let commands = parse_commands("
+            [[commands]]
+            tick = 5
+            unit = 0
+            x = 1
+            y = 1
+
+            [[commands]]
+            tick = 2
+            unit = 0
+            x = 8
+            y = 1
+        ").unwrap();
+        assert_eq!(commands[0], Command { tick: 2, unit: 0, x: 8, y: 1 });
+    }
+
+    #[test]
+    fn test_script() {
+        let commands = vec![Command { tick: 0, unit: 0, x: 8, y: 1 }];
+        let mut sim = Simulation::new(universe(), Driver::Script(commands));
+        sim.run(200);
+        let metrics = sim.metrics();
+
+        assert_eq!(metrics.trips.len(), 1);
+        assert_eq!(metrics.trips[0].path_length, 7);
+        assert!(metrics.trips[0].ticks_to_arrival.is_some());
+        assert!(metrics.units_stuck.is_empty());
+    }
+
+    #[test]
+    fn test_unreachable_destination() {
+        let commands = vec![Command { tick: 0, unit: 0, x: 0, y: 0 }];
+        let mut sim = Simulation::new(universe(), Driver::Script(commands));
+        sim.run(STUCK_TICKS + 1);
+        let metrics = sim.metrics();
+
+        assert!(!metrics.trips[0].path_found);
+        assert_eq!(metrics.units_stuck, vec![0]);
+    }
+
+    #[test]
+    fn test_wander() {
+        let mut sim = Simulation::new(universe(), Driver::Wander { seed: 3 });
+        sim.run(500);
+        let metrics = sim.metrics();
+
+        assert!(metrics.trips.len() > 1);
+        assert_eq!(metrics.pathfinding_calls, metrics.trips.len());
+    }
+
+    #[test]
+    fn test_same_seed_same_metrics() {
+        // Paper bag map is generated, the seed makes it the same on every run
+        let run = |seed| {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/params/rts/paper_bag.toml");
+            let scenario = Scenario::load_seeded(path, Some(seed)).unwrap();
+            let mut sim = Simulation::new(scenario.universe(), Driver::Wander { seed });
+            sim.run(600);
+            // Only the time measured can differ
+            let metrics = Metrics { pathfinding_time_us: 0, ..sim.metrics() };
+            serde_json::to_string(&metrics).unwrap()
+        };
+        assert_eq!(run(5), run(5));
+    }
+}
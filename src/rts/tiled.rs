@@ -0,0 +1,823 @@
+//! Import and export of maps in the [Tiled](https://www.mapeditor.org/) formats (JSON and TMX).
+//!
+//! Layers are mapped onto the world map in the following way:
+//!  * Tile layer named `collision`: every non empty cell is blocked
+//!  * Tile layer named `terrain` (or the first other tile layer): terrain type of the cell.
+//!    If the map has no collision layer then cells without terrain are blocked.
+//!  * Object layers: every object becomes a named point (spawn point, exit, ...).
+//!    Objects outside of the map are rejected.
+//!
+//! Exported maps embed a tileset without image which defines every tile id used by the layers
+//! (terrain ids and 1 for the blocked cells), so the map can be opened in Tiled and the images
+//! assigned there.
+//!
+//! Only finite, orthogonal maps with uncompressed layer data are supported.
+//!
+
+use std::{fmt, fs, io, path::Path};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use quick_xml::events::{BytesDecl, BytesText, Event};
+use quick_xml::Writer;
+use serde_derive::Deserialize;
+use serde_json::json;
+
+use crate::rts::{MapPoint, Tile, WorldMap};
+
+
+/// Tile size in pixels used when exporting maps
+pub const TILE_SIZE: u32 = 32;
+
+const COLLISION_LAYER: &str = "collision";
+const TERRAIN_LAYER: &str = "terrain";
+const OBJECT_LAYER: &str = "spawns";
+const TILESET_NAME: &str = "world";
+// Tiled stores flip flags in the highest bits of the tile id
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+
+#[derive(Debug)]
+pub enum TiledError {
+    Io(std::io::Error),
+    Parse(String),
+    UnknownFormat(String),
+    InfiniteMap,
+    Orientation(String),
+    Compression { layer: String, compression: String },
+    Encoding { layer: String, encoding: String },
+    LayerSize { layer: String, expected: usize, found: usize },
+    ObjectOutside { name: String, x: f32, y: f32 },
+}
+
+/// Format independent representation of the Tiled map
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tile_width: u32,
+    tile_height: u32,
+    layers: Vec<Layer>,
+}
+
+enum Layer {
+    Tiles { name: String, data: Vec<u32> },
+    Objects { objects: Vec<Object> },
+}
+
+struct Object {
+    name: String,
+    x: f32,
+    y: f32,
+}
+
+
+impl fmt::Display for TiledError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TiledError::Io(err) => write!(f, "Can't access map file: {}", err),
+            TiledError::Parse(msg) => write!(f, "Can't parse map: {}", msg),
+            TiledError::UnknownFormat(path) =>
+                write!(f, "Unknown map format: {} (expected .json, .tmj or .tmx)", path),
+            TiledError::InfiniteMap => write!(f, "Infinite maps are not supported"),
+            TiledError::Orientation(orientation) =>
+                write!(f, "Map orientation '{}' is not supported (only orthogonal)", orientation),
+            TiledError::Compression { layer, compression } =>
+                write!(f, "Layer '{}' uses {} compression. Save the map without compression", layer, compression),
+            TiledError::Encoding { layer, encoding } =>
+                write!(f, "Layer '{}' uses unsupported encoding '{}'", layer, encoding),
+            TiledError::LayerSize { layer, expected, found } =>
+                write!(f, "Layer '{}' has {} tiles, expected {}", layer, found, expected),
+            TiledError::ObjectOutside { name, x, y } =>
+                write!(f, "Object '{}' at ({}, {}) is outside of the map", name, x, y),
+        }
+    }
+}
+
+impl std::error::Error for TiledError {}
+
+impl From<std::io::Error> for TiledError {
+    fn from(err: std::io::Error) -> Self {
+        TiledError::Io(err)
+    }
+}
+
+
+/// Load map from the file. Format is selected based on file extension
+pub fn load<P: AsRef<Path>>(path: P) -> Result<WorldMap, TiledError> {
+    let path = path.as_ref();
+    let contents = fs::read_to_string(path)?;
+    match extension(path).as_str() {
+        "json" | "tmj" => from_json(&contents),
+        "tmx" => from_tmx(&contents),
+        _ => Err(TiledError::UnknownFormat(path.display().to_string())),
+    }
+}
+
+/// Save map to the file. Format is selected based on file extension
+pub fn save<P: AsRef<Path>>(map: &WorldMap, path: P) -> Result<(), TiledError> {
+    let path = path.as_ref();
+    let contents = match extension(path).as_str() {
+        "json" | "tmj" => to_json(map),
+        "tmx" => to_tmx(map),
+        _ => return Err(TiledError::UnknownFormat(path.display().to_string())),
+    };
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Parse map saved in the Tiled JSON format
+pub fn from_json(contents: &str) -> Result<WorldMap, TiledError> {
+    let map: JsonMap = serde_json::from_str(contents)
+        .map_err(|err| TiledError::Parse(err.to_string()))?;
+    if map.infinite {
+        return Err(TiledError::InfiniteMap);
+    }
+    check_orientation(&map.orientation)?;
+
+    let mut layers = vec![];
+    for layer in map.layers {
+        layer.collect_into(&mut layers)?;
+    }
+    TiledMap {
+        width: map.width,
+        height: map.height,
+        tile_width: map.tilewidth,
+        tile_height: map.tileheight,
+        layers,
+    }.into_world_map()
+}
+
+/// Export map to the Tiled JSON format
+pub fn to_json(map: &WorldMap) -> String {
+    let (terrain, collision) = export_layers(map);
+    let objects: Vec<serde_json::Value> = map.points.iter().enumerate()
+        .map(|(i, p)| {
+            let (x, y) = point_to_pixels(p);
+            json!({
+                "id": i + 1, "name": p.name, "type": "", "point": true,
+                "x": x, "y": y, "width": 0, "height": 0, "rotation": 0, "visible": true,
+            })
+        })
+        .collect();
+    let doc = json!({
+        "type": "map",
+        "version": "1.10",
+        "orientation": "orthogonal",
+        "renderorder": "right-down",
+        "infinite": false,
+        "width": map.width,
+        "height": map.height,
+        "tilewidth": TILE_SIZE,
+        "tileheight": TILE_SIZE,
+        "nextlayerid": 4,
+        "nextobjectid": map.points.len() + 1,
+        "tilesets": [tileset_json(&export_gids(&terrain, &collision))],
+        "layers": [
+            tile_layer_json(1, TERRAIN_LAYER, map, terrain),
+            tile_layer_json(2, COLLISION_LAYER, map, collision),
+            {
+                "id": 3, "name": OBJECT_LAYER, "type": "objectgroup", "draworder": "topdown",
+                "objects": objects, "opacity": 1, "visible": true, "x": 0, "y": 0,
+            },
+        ],
+    });
+    serde_json::to_string_pretty(&doc).expect("Can't serialize map")
+}
+
+/// Parse map saved in the Tiled TMX (XML) format
+pub fn from_tmx(contents: &str) -> Result<WorldMap, TiledError> {
+    let root = xml::parse(contents)?;
+    if root.name != "map" {
+        return Err(TiledError::Parse(format!("Expected <map> element, found <{}>", root.name)));
+    }
+    if root.attr("infinite") == Some("1") {
+        return Err(TiledError::InfiniteMap);
+    }
+    check_orientation(root.attr("orientation").unwrap_or("orthogonal"))?;
+
+    let mut layers = vec![];
+    collect_tmx_layers(&root, &mut layers)?;
+    TiledMap {
+        width: root.parse_attr("width")?,
+        height: root.parse_attr("height")?,
+        tile_width: root.parse_attr("tilewidth")?,
+        tile_height: root.parse_attr("tileheight")?,
+        layers,
+    }.into_world_map()
+}
+
+/// Export map to the Tiled TMX (XML) format
+pub fn to_tmx(map: &WorldMap) -> String {
+    let mut writer = Writer::new_with_indent(Vec::new(), b' ', 1);
+    write_tmx(&mut writer, map).expect("Can't write map to memory");
+    String::from_utf8(writer.into_inner()).expect("XML writer produces UTF-8")
+}
+
+
+impl TiledMap {
+    fn into_world_map(self) -> Result<WorldMap, TiledError> {
+        let size = self.width * self.height;
+        for layer in &self.layers {
+            if let Layer::Tiles { name, data } = layer {
+                if data.len() != size {
+                    return Err(TiledError::LayerSize { layer: name.clone(), expected: size, found: data.len() });
+                }
+            }
+        }
+
+        let tile_layers: Vec<(&String, &Vec<u32>)> = self.layers.iter()
+            .filter_map(|l| match l {
+                Layer::Tiles { name, data } => Some((name, data)),
+                _ => None,
+            })
+            .collect();
+        let collision = tile_layers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(COLLISION_LAYER))
+            .map(|(_, data)| *data);
+        let terrain = tile_layers.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(TERRAIN_LAYER))
+            .or_else(|| tile_layers.iter().find(|(name, _)| !name.eq_ignore_ascii_case(COLLISION_LAYER)))
+            .map(|(_, data)| *data);
+
+        let tiles = (0..size)
+            .map(|i| {
+                let terrain = terrain.map(|data| data[i] & GID_MASK).unwrap_or(0);
+                let is_blocked = match collision {
+                    Some(data) => data[i] & GID_MASK != 0,
+                    None => terrain == 0,
+                };
+                Tile::new(is_blocked).with_terrain(terrain)
+            })
+            .collect();
+
+        let tile_width = self.tile_width.max(1) as f32;
+        let tile_height = self.tile_height.max(1) as f32;
+        let points = self.layers.iter()
+            .flat_map(|l| match l {
+                Layer::Objects { objects } => objects.iter().collect(),
+                _ => vec![],
+            })
+            .map(|o| {
+                let (x, y) = (o.x / tile_width, o.y / tile_height);
+                // Negated check also rejects NaN
+                if !(x >= 0. && y >= 0. && x < self.width as f32 && y < self.height as f32) {
+                    return Err(TiledError::ObjectOutside { name: o.name.clone(), x: o.x, y: o.y });
+                }
+                Ok(MapPoint::new(&o.name, x as usize, y as usize))
+            })
+            .collect::<Result<Vec<MapPoint>, TiledError>>()?;
+
+        Ok(WorldMap {
+            tiles,
+            width: self.width,
+            height: self.height,
+            points,
+            regions: vec![],
+        })
+    }
+}
+
+
+// ------------------------------------------------------------------------------------------------
+// JSON format
+// ------------------------------------------------------------------------------------------------
+
+#[derive(Deserialize)]
+struct JsonMap {
+    width: usize,
+    height: usize,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default = "default_orientation")]
+    orientation: String,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    data: Option<serde_json::Value>,
+    #[serde(default)]
+    encoding: String,
+    #[serde(default)]
+    compression: String,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default, rename = "type")]
+    kind: String,
+    #[serde(default)]
+    class: String,
+    x: f32,
+    y: f32,
+    gid: Option<u32>,
+    #[serde(default)]
+    height: f32,
+}
+
+fn default_orientation() -> String {
+    "orthogonal".to_owned()
+}
+
+impl JsonLayer {
+    // Flatten group layers into the list of layers
+    fn collect_into(self, layers: &mut Vec<Layer>) -> Result<(), TiledError> {
+        match self.kind.as_str() {
+            "tilelayer" => {
+                let data = match self.data {
+                    Some(serde_json::Value::Array(values)) => values.iter()
+                        .map(|v| v.as_u64().map(|v| v as u32)
+                            .ok_or_else(|| TiledError::Parse(format!("Invalid tile id '{}' in layer '{}'", v, self.name))))
+                        .collect::<Result<Vec<u32>, TiledError>>()?,
+                    Some(serde_json::Value::String(text)) =>
+                        parse_layer_data(&self.name, &self.encoding, &self.compression, &text)?,
+                    _ => return Err(TiledError::InfiniteMap),
+                };
+                layers.push(Layer::Tiles { name: self.name, data });
+            }
+            "objectgroup" => {
+                let objects = self.objects.into_iter()
+                    .map(|o| Object {
+                        name: object_name(o.name, o.class, o.kind),
+                        x: o.x,
+                        // Tile objects are aligned to the bottom left corner
+                        y: if o.gid.is_some() { o.y - o.height } else { o.y },
+                    })
+                    .collect();
+                layers.push(Layer::Objects { objects });
+            }
+            "group" => {
+                for layer in self.layers {
+                    layer.collect_into(layers)?;
+                }
+            }
+            // Image layers have no meaning for the world map
+            _ => (),
+        }
+        Ok(())
+    }
+}
+
+fn tile_layer_json(id: usize, name: &str, map: &WorldMap, data: Vec<u32>) -> serde_json::Value {
+    json!({
+        "id": id, "name": name, "type": "tilelayer", "width": map.width, "height": map.height,
+        "data": data, "opacity": 1, "visible": true, "x": 0, "y": 0,
+    })
+}
+
+fn tileset_json(gids: &[u32]) -> serde_json::Value {
+    let tiles: Vec<serde_json::Value> = gids.iter().map(|gid| json!({ "id": gid - 1 })).collect();
+    json!({
+        "firstgid": 1, "name": TILESET_NAME, "tilewidth": TILE_SIZE, "tileheight": TILE_SIZE,
+        "tilecount": tiles.len(), "columns": 0, "margin": 0, "spacing": 0,
+        "grid": { "orientation": "orthogonal", "width": 1, "height": 1 },
+        "tiles": tiles,
+    })
+}
+
+
+// ------------------------------------------------------------------------------------------------
+// TMX format
+// ------------------------------------------------------------------------------------------------
+
+fn collect_tmx_layers(element: &xml::Element, layers: &mut Vec<Layer>) -> Result<(), TiledError> {
+    for child in &element.children {
+        match child.name.as_str() {
+            "layer" => {
+                let name = child.attr("name").unwrap_or_default().to_owned();
+                let data = child.child("data")
+                    .ok_or_else(|| TiledError::Parse(format!("Layer '{}' has no data", name)))?;
+                if data.child("chunk").is_some() {
+                    return Err(TiledError::InfiniteMap);
+                }
+                let tiles = match data.attr("encoding") {
+                    // Plain XML: one <tile> element per cell
+                    None => data.children.iter()
+                        .filter(|c| c.name == "tile")
+                        .map(|c| if c.attr("gid").is_some() { c.parse_attr("gid") } else { Ok(0) })
+                        .collect::<Result<Vec<u32>, TiledError>>()?,
+                    Some(encoding) => parse_layer_data(
+                        &name, encoding, data.attr("compression").unwrap_or_default(), &data.text)?,
+                };
+                layers.push(Layer::Tiles { name, data: tiles });
+            }
+            "objectgroup" => {
+                let objects = child.children.iter()
+                    .filter(|c| c.name == "object")
+                    .map(|c| {
+                        let name = object_name(
+                            c.attr("name").unwrap_or_default().to_owned(),
+                            c.attr("class").unwrap_or_default().to_owned(),
+                            c.attr("type").unwrap_or_default().to_owned());
+                        let x: f32 = c.parse_attr("x")?;
+                        let mut y: f32 = c.parse_attr("y")?;
+                        if c.attr("gid").is_some() {
+                            y -= c.attr("height").and_then(|h| h.parse().ok()).unwrap_or(0.);
+                        }
+                        Ok(Object { name, x, y })
+                    })
+                    .collect::<Result<Vec<Object>, TiledError>>()?;
+                layers.push(Layer::Objects { objects });
+            }
+            "group" => collect_tmx_layers(child, layers)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn write_tmx(writer: &mut Writer<Vec<u8>>, map: &WorldMap) -> io::Result<()> {
+    let (terrain, collision) = export_layers(map);
+    writer.write_event(Event::Decl(BytesDecl::new("1.0", Some("UTF-8"), None)))?;
+    writer.create_element("map")
+        .with_attributes([
+            ("version", "1.10"),
+            ("orientation", "orthogonal"),
+            ("renderorder", "right-down"),
+            ("width", map.width.to_string().as_str()),
+            ("height", map.height.to_string().as_str()),
+            ("tilewidth", TILE_SIZE.to_string().as_str()),
+            ("tileheight", TILE_SIZE.to_string().as_str()),
+            ("infinite", "0"),
+            ("nextlayerid", "4"),
+            ("nextobjectid", (map.points.len() + 1).to_string().as_str()),
+        ])
+        .write_inner_content(|writer| {
+            write_tileset_tmx(writer, &export_gids(&terrain, &collision))?;
+            write_tile_layer_tmx(writer, 1, TERRAIN_LAYER, map, &terrain)?;
+            write_tile_layer_tmx(writer, 2, COLLISION_LAYER, map, &collision)?;
+            writer.create_element("objectgroup")
+                .with_attributes([("id", "3"), ("name", OBJECT_LAYER)])
+                .write_inner_content(|writer| {
+                    for (i, p) in map.points.iter().enumerate() {
+                        let (x, y) = point_to_pixels(p);
+                        writer.create_element("object")
+                            .with_attributes([
+                                ("id", (i + 1).to_string().as_str()),
+                                ("name", p.name.as_str()),
+                                ("x", x.to_string().as_str()),
+                                ("y", y.to_string().as_str()),
+                            ])
+                            .write_inner_content(|writer| writer.create_element("point").write_empty().map(|_| ()))?;
+                    }
+                    Ok(())
+                })?;
+            Ok(())
+        })?;
+    Ok(())
+}
+
+// Tileset without image: it only defines the tiles so the layer data refers to valid gids
+fn write_tileset_tmx(writer: &mut Writer<Vec<u8>>, gids: &[u32]) -> io::Result<()> {
+    writer.create_element("tileset")
+        .with_attributes([
+            ("firstgid", "1"),
+            ("name", TILESET_NAME),
+            ("tilewidth", TILE_SIZE.to_string().as_str()),
+            ("tileheight", TILE_SIZE.to_string().as_str()),
+            ("tilecount", gids.len().to_string().as_str()),
+            ("columns", "0"),
+        ])
+        .write_inner_content(|writer| {
+            writer.create_element("grid")
+                .with_attributes([("orientation", "orthogonal"), ("width", "1"), ("height", "1")])
+                .write_empty()?;
+            for gid in gids {
+                writer.create_element("tile")
+                    .with_attribute(("id", (gid - 1).to_string().as_str()))
+                    .write_empty()?;
+            }
+            Ok(())
+        })?;
+    Ok(())
+}
+
+fn write_tile_layer_tmx(writer: &mut Writer<Vec<u8>>, id: usize, name: &str, map: &WorldMap, data: &[u32]) -> io::Result<()> {
+    let rows: Vec<String> = data.chunks(map.width.max(1))
+        .map(|row| row.iter().map(|v| v.to_string()).collect::<Vec<String>>().join(","))
+        .collect();
+    let csv = format!("\n{}\n", rows.join(",\n"));
+    writer.create_element("layer")
+        .with_attributes([
+            ("id", id.to_string().as_str()),
+            ("name", name),
+            ("width", map.width.to_string().as_str()),
+            ("height", map.height.to_string().as_str()),
+        ])
+        .write_inner_content(|writer| {
+            writer.create_element("data")
+                .with_attribute(("encoding", "csv"))
+                .write_text_content(BytesText::new(&csv))
+                .map(|_| ())
+        })?;
+    Ok(())
+}
+
+
+// ------------------------------------------------------------------------------------------------
+// Helpers shared by both formats
+// ------------------------------------------------------------------------------------------------
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn check_orientation(orientation: &str) -> Result<(), TiledError> {
+    if orientation == "orthogonal" {
+        Ok(())
+    } else {
+        Err(TiledError::Orientation(orientation.to_owned()))
+    }
+}
+
+// Object name with fallback to its class (type in older Tiled versions)
+fn object_name(name: String, class: String, kind: String) -> String {
+    [name, class, kind].into_iter()
+        .find(|s| !s.is_empty())
+        .unwrap_or_default()
+}
+
+// Points are exported at the center of their tile
+fn point_to_pixels(point: &MapPoint) -> (u32, u32) {
+    (point.x as u32 * TILE_SIZE + TILE_SIZE / 2, point.y as u32 * TILE_SIZE + TILE_SIZE / 2)
+}
+
+// Terrain and collision layers for the given map
+fn export_layers(map: &WorldMap) -> (Vec<u32>, Vec<u32>) {
+    let terrain = map.tiles.iter().map(|t| t.terrain).collect();
+    let collision = map.tiles.iter().map(|t| t.is_blocked as u32).collect();
+    (terrain, collision)
+}
+
+// Sorted list of the gids used by the exported layers
+fn export_gids(terrain: &[u32], collision: &[u32]) -> Vec<u32> {
+    let mut gids: Vec<u32> = terrain.iter().chain(collision)
+        .copied()
+        .filter(|gid| *gid != 0)
+        .collect();
+    gids.sort_unstable();
+    gids.dedup();
+    gids
+}
+
+fn parse_layer_data(layer: &str, encoding: &str, compression: &str, text: &str) -> Result<Vec<u32>, TiledError> {
+    if !compression.is_empty() {
+        return Err(TiledError::Compression { layer: layer.to_owned(), compression: compression.to_owned() });
+    }
+    match encoding {
+        "csv" | "" => text.split(',')
+            .map(|v| v.trim())
+            .filter(|v| !v.is_empty())
+            .map(|v| v.parse::<u32>()
+                .map_err(|_| TiledError::Parse(format!("Invalid tile id '{}' in layer '{}'", v, layer))))
+            .collect(),
+        "base64" => {
+            let text: String = text.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+            let bytes = BASE64.decode(text)
+                .map_err(|err| TiledError::Parse(format!("Invalid base64 data in layer '{}': {}", layer, err)))?;
+            if bytes.len() % 4 != 0 {
+                return Err(TiledError::Parse(format!("Invalid data length in layer '{}'", layer)));
+            }
+            Ok(bytes.chunks(4)
+                .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+                .collect())
+        }
+        _ => Err(TiledError::Encoding { layer: layer.to_owned(), encoding: encoding.to_owned() }),
+    }
+}
+
+/// Element tree built from the quick-xml events
+mod xml {
+    use super::TiledError;
+    use quick_xml::events::{BytesStart, Event};
+    use quick_xml::Reader;
+    use std::str::FromStr;
+
+    pub struct Element {
+        pub name: String,
+        pub attrs: Vec<(String, String)>,
+        pub children: Vec<Element>,
+        pub text: String,
+    }
+
+    impl Element {
+        fn new(name: &str) -> Self {
+            Self { name: name.to_owned(), attrs: vec![], children: vec![], text: String::new() }
+        }
+
+        pub fn attr(&self, name: &str) -> Option<&str> {
+            self.attrs.iter()
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.as_str())
+        }
+
+        pub fn parse_attr<T: FromStr>(&self, name: &str) -> Result<T, TiledError> {
+            let value = self.attr(name)
+                .ok_or_else(|| TiledError::Parse(format!("<{}> is missing attribute '{}'", self.name, name)))?;
+            value.trim().parse()
+                .map_err(|_| TiledError::Parse(format!("<{}> has invalid {}=\"{}\"", self.name, name, value)))
+        }
+
+        pub fn child(&self, name: &str) -> Option<&Element> {
+            self.children.iter().find(|c| c.name == name)
+        }
+    }
+
+    /// Parse document and return its root element
+    pub fn parse(src: &str) -> Result<Element, TiledError> {
+        let mut reader = Reader::from_str(src);
+        let error = |reader: &Reader<&[u8]>, err: quick_xml::Error| {
+            TiledError::Parse(format!("{} at byte {}", err, reader.error_position()))
+        };
+        let mut stack = vec![Element::new("")];
+        loop {
+            match reader.read_event().map_err(|err| error(&reader, err))? {
+                Event::Start(tag) => stack.push(element(&tag)?),
+                Event::Empty(tag) => {
+                    let element = element(&tag)?;
+                    top(&mut stack).children.push(element);
+                }
+                Event::End(_) => {
+                    // Reader checks that the names of the start and end tags match
+                    if stack.len() < 2 {
+                        return Err(TiledError::Parse("Unexpected closing tag".to_owned()));
+                    }
+                    let element = stack.pop().unwrap();
+                    top(&mut stack).children.push(element);
+                }
+                Event::Text(text) => {
+                    let text = text.unescape().map_err(|err| error(&reader, err))?;
+                    top(&mut stack).text.push_str(&text);
+                }
+                Event::CData(data) => {
+                    let text = data.decode().map_err(|err| error(&reader, err.into()))?;
+                    top(&mut stack).text.push_str(&text);
+                }
+                Event::Eof => break,
+                // Declaration, comments, processing instructions and doctype
+                _ => (),
+            }
+        }
+
+        if stack.len() > 1 {
+            return Err(TiledError::Parse(format!("Element <{}> is not closed", top(&mut stack).name)));
+        }
+        stack.pop().unwrap().children.into_iter().next()
+            .ok_or_else(|| TiledError::Parse("Empty document".to_owned()))
+    }
+
+    fn top(stack: &mut [Element]) -> &mut Element {
+        stack.last_mut().expect("Document root is always on the stack")
+    }
+
+    fn element(tag: &BytesStart) -> Result<Element, TiledError> {
+        let mut element = Element::new(&String::from_utf8_lossy(tag.name().as_ref()));
+        for attr in tag.attributes() {
+            let attr = attr.map_err(|err| TiledError::Parse(format!("Invalid attribute in <{}>: {}", element.name, err)))?;
+            let key = String::from_utf8_lossy(attr.key.as_ref()).into_owned();
+            let value = attr.unescape_value()
+                .map_err(|err| TiledError::Parse(format!("Invalid attribute '{}' in <{}>: {}", key, element.name, err)))?;
+            element.attrs.push((key, value.into_owned()));
+        }
+        Ok(element)
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_map() -> WorldMap {
+        let mut map = WorldMap::from_string("
+        #####
+        #   #
+        #####
+        ");
+        map.set_tile(2, 1, Tile::walkable().with_terrain(7));
+        map.points.push(MapPoint::new("player", 1, 1));
+        map
+    }
+
+    fn assert_same_map(a: &WorldMap, b: &WorldMap) {
+        assert_eq!(a.width, b.width);
+        assert_eq!(a.height, b.height);
+        assert_eq!(a.tiles, b.tiles);
+        assert_eq!(a.points, b.points);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let map = test_map();
+        let loaded = from_json(&to_json(&map)).unwrap();
+        assert_same_map(&map, &loaded);
+    }
+
+    #[test]
+    fn test_tmx_round_trip() {
+        let map = test_map();
+        let loaded = from_tmx(&to_tmx(&map)).unwrap();
+        assert_same_map(&map, &loaded);
+    }
+
+    #[test]
+    fn test_tmx_without_collision_layer() {
+        let tmx = r#"<?xml version="1.0" encoding="UTF-8"?>
+        <map version="1.10" orientation="orthogonal" width="3" height="1" tilewidth="16" tileheight="16" infinite="0">
+         <tileset firstgid="1" source="tiles.tsx"/>
+         <layer id="1" name="Ground" width="3" height="1">
+          <data encoding="base64">AQAAAAAAAAACAACA</data>
+         </layer>
+         <objectgroup id="2" name="Objects">
+          <object id="1" class="exit" x="40" y="8"/>
+         </objectgroup>
+        </map>"#;
+        let map = from_tmx(tmx).unwrap();
+
+        assert_eq!(map.at(0, 0), Tile::walkable().with_terrain(1));
+        assert!(map.at(1, 0).is_blocked);
+        // Flip flags are ignored
+        assert_eq!(map.at(2, 0).terrain, 2);
+        assert_eq!(map.point("exit"), Some(&MapPoint::new("exit", 2, 0)));
+    }
+
+    #[test]
+    fn test_export_tileset() {
+        let map = test_map();
+        let tmx = to_tmx(&map);
+        let root = xml::parse(&tmx).unwrap();
+        let tileset = root.child("tileset").unwrap();
+        let ids: Vec<&str> = tileset.children.iter()
+            .filter(|c| c.name == "tile")
+            .map(|c| c.attr("id").unwrap())
+            .collect();
+        // Collision uses gid 1 and terrain gid 7
+        assert_eq!(ids, vec!["0", "6"]);
+        assert_eq!(tileset.attr("tilecount"), Some("2"));
+
+        let json: serde_json::Value = serde_json::from_str(&to_json(&map)).unwrap();
+        let tiles = &json["tilesets"][0]["tiles"];
+        assert_eq!(tiles, &json!([{ "id": 0 }, { "id": 6 }]));
+    }
+
+    #[test]
+    fn test_tmx_entities() {
+        let tmx = r#"<map width="1" height="1" tilewidth="16" tileheight="16">
+         <layer name="terrain"><data encoding="csv">1</data></layer>
+         <objectgroup><object name="&#70;ort &amp; &#x42;ay" x="8" y="8"/></objectgroup>
+        </map>"#;
+        let map = from_tmx(tmx).unwrap();
+        assert_eq!(map.points[0].name, "Fort & Bay");
+
+        let mut map = test_map();
+        map.points.push(MapPoint::new("<\"quoted\">", 3, 1));
+        let loaded = from_tmx(&to_tmx(&map)).unwrap();
+        assert_same_map(&map, &loaded);
+    }
+
+    #[test]
+    fn test_object_outside() {
+        let object = |x: f32, y: f32| format!(
+            r#"{{"width": 2, "height": 1, "tilewidth": 32, "tileheight": 32, "layers": [
+                {{"type": "tilelayer", "name": "ground", "data": [1, 1]}},
+                {{"type": "objectgroup", "objects": [{{"name": "exit", "x": {}, "y": {}}}]}}
+            ]}}"#, x, y);
+        assert!(from_json(&object(63., 31.)).is_ok());
+        assert!(matches!(from_json(&object(-1., 8.)), Err(TiledError::ObjectOutside { .. })));
+        assert!(matches!(from_json(&object(64., 8.)), Err(TiledError::ObjectOutside { .. })));
+        assert!(matches!(from_json(&object(8., 32.)), Err(TiledError::ObjectOutside { .. })));
+    }
+
+    #[test]
+    fn test_unsupported_features() {
+        let infinite = r#"{"width": 2, "height": 1, "tilewidth": 32, "tileheight": 32, "infinite": true, "layers": []}"#;
+        assert!(matches!(from_json(infinite), Err(TiledError::InfiniteMap)));
+
+        let compressed = r#"{"width": 2, "height": 1, "tilewidth": 32, "tileheight": 32, "layers": [
+            {"type": "tilelayer", "name": "ground", "encoding": "base64", "compression": "zlib", "data": "eJxjZGBgAAAACAAC"}
+        ]}"#;
+        assert!(matches!(from_json(compressed), Err(TiledError::Compression { .. })));
+
+        let wrong_size = r#"{"width": 2, "height": 2, "tilewidth": 32, "tileheight": 32, "layers": [
+            {"type": "tilelayer", "name": "ground", "data": [1, 1, 1]}
+        ]}"#;
+        assert!(matches!(from_json(wrong_size), Err(TiledError::LayerSize { expected: 4, found: 3, .. })));
+    }
+}
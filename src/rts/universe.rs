@@ -1,37 +1,76 @@
 
+use std::collections::HashMap;
 use glam::Vec2;
 use crate::rts::WorldMap;
 use crate::rts::pathfinding as pf;
 
 
+/// Distance traveled by unit in a single tick
+pub const UNIT_SPEED: f32 = 0.1;
+
+
+pub struct Player {
+    pub name: String,
+    pub resources: HashMap<String, u32>,
+}
+
 pub struct Unit {
     pub pos: Vec2,
     dest: Vec2,
     pub is_moving: bool,
+    /// Index of the player owning this unit
+    pub owner: usize,
+    /// Cells (centers) which unit still needs to visit
+    pub path: Vec<Vec2>,
 }
 
 pub struct Universe {
     pub map: WorldMap,
+    pub players: Vec<Player>,
     pub units: Vec<Unit>,
-    pub path: Vec<Vec2>,
+}
+
+impl Player {
+    pub fn new(name: &str) -> Player {
+        Player { name: name.to_owned(), resources: HashMap::new() }
+    }
+
+    pub fn resource(&self, name: &str) -> u32 {
+        self.resources.get(name).copied().unwrap_or(0)
+    }
 }
 
 impl Universe {
     pub fn from_map(map: WorldMap) -> Universe {
-        Universe { map, units: vec![], path: vec![] }
+        Universe { map, players: vec![], units: vec![] }
+    }
+
+    /// Add player and return its index
+    pub fn add_player(&mut self, player: Player) -> usize {
+        self.players.push(player);
+        self.players.len() - 1
     }
 
     pub fn add_unit(&mut self, pos_x: usize, pos_y: usize) {
-        let unit =  Unit::new(pos_x as f32 + 0.5, pos_y as f32 + 0.5);
+        self.add_player_unit(0, pos_x, pos_y);
+    }
+
+    pub fn add_player_unit(&mut self, owner: usize, pos_x: usize, pos_y: usize) {
+        let mut unit =  Unit::new(pos_x as f32 + 0.5, pos_y as f32 + 0.5);
+        unit.owner = owner;
         self.units.push(unit);
     }
 
-    // Move unit to a given cell on the map
+    // Move first unit to a given cell on the map
     pub fn move_to(&mut self, x: usize, y: usize) {
-        if self.units.len() > 0 {
-            let unit = &self.units[0];
+        self.move_unit(0, x, y);
+    }
+
+    // Move given unit to a given cell on the map
+    pub fn move_unit(&mut self, unit_id: usize, x: usize, y: usize) {
+        if let Some(unit) = self.units.get_mut(unit_id) {
             let pos = (unit.dest.x as i32, unit.dest.y as i32);
-            self.path = pf::find_path(&self.map, pos, (x as i32, y as i32))
+            unit.path = pf::find_path(&self.map, pos, (x as i32, y as i32))
                 .into_iter().map(|(i, j)| Vec2::new(i as f32 + 0.5, j as f32 + 0.5))
                 .collect();
         }
@@ -41,13 +80,13 @@ impl Universe {
     pub fn tick(&mut self) {
         for unit in &mut self.units {
 
-            if let Some(&dest) = self.path.get(0) {
+            if let Some(&dest) = unit.path.first() {
                 if unit.pos != dest {
                     unit.dest = dest;
                 } else {
-                    self.path.remove(0);
+                    unit.path.remove(0);
                 }
-                unit.update_pos(0.1);
+                unit.update_pos(UNIT_SPEED);
             }
         }
     }
@@ -55,13 +94,23 @@ impl Universe {
 
 impl Unit {
     pub fn new(x: f32, y: f32) -> Unit {
-        Unit {pos: Vec2::new(x, y), dest: Vec2::new(x, y), is_moving: false}
+        Unit {pos: Vec2::new(x, y), dest: Vec2::new(x, y), is_moving: false, owner: 0, path: vec![]}
     }
 
     pub fn update_pos(&mut self, amount: f32) {
-        let dir = (self.dest - self.pos).normalize_or_zero();
-        self.pos.x += dir.x * amount;
-        self.pos.y += dir.y * amount;
-        self.is_moving = dir.length() > 0.
+        self.is_moving = step_towards(&mut self.pos, self.dest, amount);
+    }
+}
+
+/// Move position towards destination by a given amount. Returns true if the position was moving
+pub(crate) fn step_towards(pos: &mut Vec2, dest: Vec2, amount: f32) -> bool {
+    let dir = (dest - *pos).normalize_or_zero();
+    // Don't overshoot destination, otherwise unit will never reach it
+    if pos.distance(dest) <= amount {
+        *pos = dest;
+    } else {
+        pos.x += dir.x * amount;
+        pos.y += dir.y * amount;
     }
+    dir.length() > 0.
 }
\ No newline at end of file
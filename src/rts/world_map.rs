@@ -1,12 +1,44 @@
 //! World map is grid based. Each grid contains information is unit can move over it
 //! 
 
+use std::collections::VecDeque;
 use std::fmt;
 
 
 #[derive(PartialEq, Copy, Clone, Debug, Eq, Hash)]
 pub struct Tile {
     pub is_blocked: bool,
+    /// Terrain type (tile id from the map editor). 0 means no terrain.
+    pub terrain: u32,
+}
+
+/// Named location on the map, like spawn point or exit
+#[derive(PartialEq, Clone, Debug)]
+pub struct MapPoint {
+    pub name: String,
+    pub x: usize,
+    pub y: usize,
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum RegionShape {
+    /// Rectangle. Right and bottom edges are not part of the region
+    Rect { x1: usize, y1: usize, x2: usize, y2: usize },
+    Tiles(Vec<(usize, usize)>),
+}
+
+/// Named area on the map, like room or corridor
+#[derive(PartialEq, Clone, Debug)]
+pub struct MapRegion {
+    pub name: String,
+    pub shape: RegionShape,
+}
+
+/// Groups of walkable tiles reachable from each other
+pub struct Components {
+    labels: Vec<Option<usize>>,
+    width: usize,
+    pub count: usize,
 }
 
 /// Map data
@@ -15,19 +47,80 @@ pub struct WorldMap {
     pub tiles : Vec<Tile>,
     pub width : usize,
     pub height : usize,
+    pub points: Vec<MapPoint>,
+    pub regions: Vec<MapRegion>,
 }
 
 impl Tile {
     pub fn new(is_blocked: bool) -> Tile {
-        Tile { is_blocked }
+        Tile { is_blocked, terrain: 0 }
     }
 
     pub fn walkable() -> Tile {
-        Tile { is_blocked: false }
+        Tile::new(false)
     }
 
     pub fn blocked() -> Tile {
-        Tile { is_blocked: true }
+        Tile::new(true)
+    }
+
+    pub fn with_terrain(self, terrain: u32) -> Tile {
+        Tile { terrain, ..self }
+    }
+}
+
+impl MapPoint {
+    pub fn new(name: &str, x: usize, y: usize) -> MapPoint {
+        MapPoint { name: name.to_owned(), x, y }
+    }
+}
+
+impl MapRegion {
+    pub fn new(name: &str, shape: RegionShape) -> MapRegion {
+        MapRegion { name: name.to_owned(), shape }
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        match &self.shape {
+            RegionShape::Rect { x1, y1, x2, y2 } => x >= *x1 && x < *x2 && y >= *y1 && y < *y2,
+            RegionShape::Tiles(tiles) => tiles.contains(&(x, y)),
+        }
+    }
+
+    /// All tiles inside this region
+    pub fn tiles(&self) -> Vec<(usize, usize)> {
+        match &self.shape {
+            RegionShape::Rect { x1, y1, x2, y2 } =>
+                (*y1..*y2).flat_map(|y| (*x1..*x2).map(move |x| (x, y))).collect(),
+            RegionShape::Tiles(tiles) => tiles.clone(),
+        }
+    }
+}
+
+impl Components {
+    /// Component id of the given tile. None for blocked tiles
+    pub fn at(&self, x: usize, y: usize) -> Option<usize> {
+        if x >= self.width {
+            None
+        } else {
+            self.labels.get(y * self.width + x).copied().flatten()
+        }
+    }
+
+    pub fn is_connected(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        match (self.at(from.0, from.1), self.at(to.0, to.1)) {
+            (Some(a), Some(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Number of tiles in each component
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut sizes = vec![0; self.count];
+        for id in self.labels.iter().flatten() {
+            sizes[*id] += 1;
+        }
+        sizes
     }
 }
 
@@ -40,6 +133,8 @@ impl WorldMap {
             tiles : vec![Tile::new(true); map_tile_count],
             width,
             height,
+            points: vec![],
+            regions: vec![],
         }
     }
 
@@ -51,6 +146,8 @@ impl WorldMap {
             tiles,
             width,
             height,
+            points: vec![],
+            regions: vec![],
         }
     }
 
@@ -120,8 +217,77 @@ impl WorldMap {
     pub fn xy_idx(&self, x: usize, y: usize) -> usize {
         y * self.width + x        
     }
+
+    /// Find named point
+    pub fn point(&self, name: &str) -> Option<&MapPoint> {
+        self.points.iter().find(|p| p.name == name)
+    }
+
+    /// Find named region
+    pub fn region(&self, name: &str) -> Option<&MapRegion> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    /// First region (room, corridor) which contains given tile
+    pub fn region_at(&self, x: usize, y: usize) -> Option<&MapRegion> {
+        self.regions.iter().find(|r| r.contains(x, y))
+    }
+
+    /// Find groups of connected walkable tiles. 
+    /// Tiles are connected the same way as units move (including diagonals)
+    pub fn components(&self) -> Components {
+        let mut labels = vec![None; self.tiles.len()];
+        let mut count = 0;
+        for start in 0..self.tiles.len() {
+            if labels[start].is_some() || self.tiles[start].is_blocked {
+                continue;
+            }
+            labels[start] = Some(count);
+            let mut queue = VecDeque::from([(start % self.width, start / self.width)]);
+            while let Some((x, y)) = queue.pop_front() {
+                for (nx, ny, _) in self.get_available_exits(x, y) {
+                    let idx = self.xy_idx(nx, ny);
+                    if labels[idx].is_none() {
+                        labels[idx] = Some(count);
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+            count += 1;
+        }
+        Components { labels, width: self.width, count }
+    }
 }
     
+/// Convert generated map. Starting point and exit become points "start" and "exit",
+/// rooms and corridors become regions "room_<n>" and "corridor_<n>".
+impl From<&mapgen::Map> for WorldMap {
+    fn from(map: &mapgen::Map) -> Self {
+        let tiles = map.tiles.iter()
+            .map(|t| Tile::new(t.is_blocked()).with_terrain(t.index() as u32))
+            .collect();
+        let points = [("start", map.starting_point), ("exit", map.exit_point)].into_iter()
+            .filter_map(|(name, p)| p.map(|p| MapPoint::new(name, p.x, p.y)))
+            .collect();
+        let rooms = map.rooms.iter().enumerate()
+            .map(|(i, r)| MapRegion::new(
+                &format!("room_{}", i), 
+                RegionShape::Rect { x1: r.x1, y1: r.y1, x2: r.x2, y2: r.y2 }));
+        let corridors = map.corridors.iter().enumerate()
+            .map(|(i, c)| MapRegion::new(
+                &format!("corridor_{}", i), 
+                RegionShape::Tiles(c.iter().map(|p| (p.x, p.y)).collect())));
+
+        WorldMap {
+            tiles,
+            width: map.width,
+            height: map.height,
+            points,
+            regions: rooms.chain(corridors).collect(),
+        }
+    }
+}
+
 impl fmt::Display for WorldMap {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         for y in 0..self.height {
@@ -173,4 +339,38 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn test_from_mapgen() {
+        let mut map = mapgen::Map::new(10, 6);
+        map.add_room(mapgen::geometry::Rect::new(1, 1, 3, 3));
+        map.add_room(mapgen::geometry::Rect::new(6, 1, 3, 3));
+        map.corridors.push(vec![mapgen::geometry::Point::new(4, 2), mapgen::geometry::Point::new(5, 2)]);
+        map.starting_point = Some(mapgen::geometry::Point::new(2, 2));
+        let world_map = WorldMap::from(&map);
+
+        assert!(!world_map.at(2, 2).is_blocked);
+        assert_eq!(world_map.point("start"), Some(&MapPoint::new("start", 2, 2)));
+        assert_eq!(world_map.region_at(3, 3).map(|r| r.name.as_str()), Some("room_0"));
+        assert_eq!(world_map.region_at(5, 2).map(|r| r.name.as_str()), Some("corridor_0"));
+        assert_eq!(world_map.region_at(4, 4), None);
+        assert_eq!(world_map.region("room_1").unwrap().tiles().len(), 9);
+    }
+
+    #[test]
+    fn test_components() {
+        let map = WorldMap::from_string("
+        #########
+        #  #  # #
+        #  #  # #
+        #########
+        ");
+        let components = map.components();
+
+        assert_eq!(components.count, 3);
+        assert_eq!(components.sizes(), vec![4, 4, 2]);
+        assert!(components.is_connected((1, 1), (2, 2)));
+        assert!(!components.is_connected((1, 1), (4, 1)));
+        assert_eq!(components.at(0, 0), None);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,291 @@
+// Graph validation and connectivity analysis.
+//
+// `Graph::new` accepts any edges, so the graph may refer to nodes which don't exist or have
+// places where trains get stuck. Validation lists all such problems.
+// Connectivity is analysed on the nodes: edge connects its nodes in the direction of travel
+// (both ways if it is bidirectional). Reachability from an edge also respects junction rules,
+// the same way as routing does.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::transnet::{Direction, Graph};
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum GraphIssue {
+    /// Edge refers to the node which doesn't exist
+    DanglingNode { edge: usize, node: usize },
+    ZeroLength { edge: usize },
+    /// No edge leads to the node
+    Unreachable { node: usize },
+    /// Train arriving at the node on the edge can't continue
+    DeadEnd { node: usize, edge: usize },
+    /// Graph is split into parts which aren't connected. Nodes of every part
+    Disconnected { components: Vec<Vec<usize>> },
+}
+
+
+impl fmt::Display for GraphIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphIssue::DanglingNode { edge, node } =>
+                write!(f, "Edge {} refers to node {} which doesn't exist", edge, node),
+            GraphIssue::ZeroLength { edge } => write!(f, "Edge {} has zero length", edge),
+            GraphIssue::Unreachable { node } => write!(f, "Node {} can't be reached", node),
+            GraphIssue::DeadEnd { node, edge } =>
+                write!(f, "Train arriving at node {} on edge {} can't continue", node, edge),
+            GraphIssue::Disconnected { components } =>
+                write!(f, "Graph has {} disconnected parts", components.len()),
+        }
+    }
+}
+
+
+impl Graph {
+    /// All problems found in the graph
+    pub fn validate(&self) -> Vec<GraphIssue> {
+        let mut issues = vec![];
+        for (edge_id, edge) in self.edges.iter().enumerate() {
+            for node in [edge.from_node_id, edge.to_node_id].into_iter().filter(|&n| n >= self.nodes.len()) {
+                issues.push(GraphIssue::DanglingNode { edge: edge_id, node });
+            }
+            if edge.length().is_nan() || edge.length() <= 0. {
+                issues.push(GraphIssue::ZeroLength { edge: edge_id });
+            }
+        }
+
+        let mut reachable = vec![false; self.nodes.len()];
+        for (edge_id, reversed) in self.valid_states() {
+            let node_id = self.edges[edge_id].end_node(reversed);
+            reachable[node_id] = true;
+            let mut next = (0..self.edges.len()).filter(|&e| self.is_valid(e));
+            if !next.any(|next_id| self.turn(node_id, edge_id, next_id).is_some()) {
+                issues.push(GraphIssue::DeadEnd { node: node_id, edge: edge_id });
+            }
+        }
+        for (node, _) in reachable.iter().enumerate().filter(|(_, r)| !**r) {
+            issues.push(GraphIssue::Unreachable { node });
+        }
+
+        let components = self.components();
+        if components.len() > 1 {
+            issues.push(GraphIssue::Disconnected { components });
+        }
+        issues
+    }
+
+    /// Parts of the graph connected by edges (in any direction). Sorted node ids of every part
+    pub fn components(&self) -> Vec<Vec<usize>> {
+        let mut neighbours = vec![vec![]; self.nodes.len()];
+        for edge in self.edges.iter().filter(|e| self.has_nodes(e.from_node_id, e.to_node_id)) {
+            neighbours[edge.from_node_id].push(edge.to_node_id);
+            neighbours[edge.to_node_id].push(edge.from_node_id);
+        }
+        let mut component = vec![None; self.nodes.len()];
+        let mut components = vec![];
+        for start in 0..self.nodes.len() {
+            if component[start].is_some() {
+                continue;
+            }
+            let mut nodes = vec![];
+            let mut queue = VecDeque::from([start]);
+            component[start] = Some(components.len());
+            while let Some(node_id) = queue.pop_front() {
+                nodes.push(node_id);
+                for &next in &neighbours[node_id] {
+                    if component[next].is_none() {
+                        component[next] = Some(components.len());
+                        queue.push_back(next);
+                    }
+                }
+            }
+            nodes.sort();
+            components.push(nodes);
+        }
+        components
+    }
+
+    /// Strongly connected components (Tarjan): train can get from any node of the component to
+    /// any other one. Sorted node ids of every component, ordered by the smallest node id.
+    pub fn strongly_connected_components(&self) -> Vec<Vec<usize>> {
+        let mut successors = vec![vec![]; self.nodes.len()];
+        for edge in self.edges.iter().filter(|e| self.has_nodes(e.from_node_id, e.to_node_id)) {
+            successors[edge.from_node_id].push(edge.to_node_id);
+            if edge.direction == Direction::Both {
+                successors[edge.to_node_id].push(edge.from_node_id);
+            }
+        }
+
+        let count = self.nodes.len();
+        let mut index = vec![usize::MAX; count];
+        let mut low_link = vec![0; count];
+        let mut on_stack = vec![false; count];
+        let mut stack = vec![];
+        let mut next_index = 0;
+        let mut components = vec![];
+        for root in 0..count {
+            if index[root] != usize::MAX {
+                continue;
+            }
+            // Explicit call stack: node and the next successor to visit
+            let mut calls = vec![(root, 0)];
+            while let Some(&mut (node_id, ref mut child)) = calls.last_mut() {
+                if *child == 0 {
+                    index[node_id] = next_index;
+                    low_link[node_id] = next_index;
+                    next_index += 1;
+                    stack.push(node_id);
+                    on_stack[node_id] = true;
+                }
+                if let Some(&next) = successors[node_id].get(*child) {
+                    *child += 1;
+                    if index[next] == usize::MAX {
+                        calls.push((next, 0));
+                    } else if on_stack[next] {
+                        low_link[node_id] = low_link[node_id].min(index[next]);
+                    }
+                    continue;
+                }
+                calls.pop();
+                if let Some(&(parent, _)) = calls.last() {
+                    low_link[parent] = low_link[parent].min(low_link[node_id]);
+                }
+                if low_link[node_id] == index[node_id] {
+                    let mut component = vec![];
+                    while let Some(member) = stack.pop() {
+                        on_stack[member] = false;
+                        component.push(member);
+                        if member == node_id {
+                            break;
+                        }
+                    }
+                    component.sort();
+                    components.push(component);
+                }
+            }
+        }
+        components.sort();
+        components
+    }
+
+    /// Nodes which train can reach when traveling on the edge in the given direction.
+    /// Respects junction rules. Sorted node ids
+    pub fn reachable_from_edge(&self, edge_id: usize, reversed: bool) -> Vec<usize> {
+        if !self.is_valid(edge_id) {
+            return vec![];
+        }
+        // Search state is edge traveled in the given direction: 2 * edge_id + reversed
+        let mut visited = vec![false; 2 * self.edges.len()];
+        let mut reached = vec![false; self.nodes.len()];
+        let start = 2 * edge_id + reversed as usize;
+        visited[start] = true;
+        let mut queue = VecDeque::from([start]);
+        while let Some(state) = queue.pop_front() {
+            let edge_id = state / 2;
+            let node_id = self.edges[edge_id].end_node(state % 2 == 1);
+            reached[node_id] = true;
+            for next_id in (0..self.edges.len()).filter(|&e| self.is_valid(e)) {
+                if let Some(reversed) = self.turn(node_id, edge_id, next_id) {
+                    let next = 2 * next_id + reversed as usize;
+                    if !visited[next] {
+                        visited[next] = true;
+                        queue.push_back(next);
+                    }
+                }
+            }
+        }
+        reached.iter().enumerate().filter(|(_, r)| **r).map(|(id, _)| id).collect()
+    }
+
+    fn has_nodes(&self, from: usize, to: usize) -> bool {
+        from < self.nodes.len() && to < self.nodes.len()
+    }
+
+    // Edge with existing nodes
+    fn is_valid(&self, edge_id: usize) -> bool {
+        self.edges.get(edge_id).is_some_and(|e| self.has_nodes(e.from_node_id, e.to_node_id))
+    }
+
+    // Valid edges with the directions in which they can be traveled
+    fn valid_states(&self) -> Vec<(usize, bool)> {
+        let mut states = vec![];
+        for (edge_id, edge) in self.edges.iter().enumerate().filter(|(id, _)| self.is_valid(*id)) {
+            states.push((edge_id, false));
+            if edge.direction == Direction::Both {
+                states.push((edge_id, true));
+            }
+        }
+        states
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Junction, Node};
+
+    //  0 --e0--> 1 --e1--> 2 <-e2-> 3      4
+    //  ^         |
+    //  +---e3----+
+    fn network() -> Graph {
+        let nodes = vec![
+            Node::new(0., 0.), Node::new(100., 0.), Node::new(200., 0.), Node::new(300., 0.), Node::new(400., 0.),
+        ];
+        let edges = vec![
+            Edge::new(0, 1, &nodes),
+            Edge::new(1, 2, &nodes),
+            Edge::new(2, 3, &nodes).bidirectional(),
+            Edge::new(1, 0, &nodes),
+        ];
+        Graph::new(nodes, edges)
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut graph = network();
+        // Trains can't turn back at the ends of the spur
+        assert_eq!(graph.validate(), vec![
+            GraphIssue::DeadEnd { node: 3, edge: 2 },
+            GraphIssue::DeadEnd { node: 2, edge: 2 },
+            GraphIssue::Unreachable { node: 4 },
+            GraphIssue::Disconnected { components: vec![vec![0, 1, 2, 3], vec![4]] },
+        ]);
+
+        graph.set_junction(1, Junction::Forbidden(vec![(0, 1), (0, 3)]));
+        graph.edges[3].to_node_id = 7;
+        let issues = graph.validate();
+        assert!(issues.contains(&GraphIssue::DanglingNode { edge: 3, node: 7 }));
+        assert!(issues.contains(&GraphIssue::DeadEnd { node: 1, edge: 0 }));
+        // Nothing leads back to the node 0
+        assert!(issues.contains(&GraphIssue::Unreachable { node: 0 }));
+
+        let nodes = vec![Node::new(10., 10.), Node::new(10., 10.)];
+        let graph = Graph::new(nodes.clone(), vec![Edge::new(0, 1, &nodes)]);
+        assert!(graph.validate().contains(&GraphIssue::ZeroLength { edge: 0 }));
+    }
+
+    #[test]
+    fn test_strongly_connected_components() {
+        let graph = network();
+        assert_eq!(graph.strongly_connected_components(), vec![vec![0, 1], vec![2, 3], vec![4]]);
+    }
+
+    #[test]
+    fn test_reachable_from_edge() {
+        let mut graph = network();
+        assert_eq!(graph.reachable_from_edge(1, false), vec![2, 3]);
+        assert_eq!(graph.reachable_from_edge(0, false), vec![0, 1, 2, 3]);
+        // Reversed on the spur
+        assert_eq!(graph.reachable_from_edge(2, true), vec![2]);
+
+        // Train from the node 0 can only go to the node 2
+        graph.set_junction(1, Junction::Allowed(vec![(0, 1)]));
+        assert_eq!(graph.reachable_from_edge(0, false), vec![1, 2, 3]);
+        assert_eq!(graph.reachable_from_edge(9, false), Vec::<usize>::new());
+    }
+}
@@ -0,0 +1,270 @@
+// Train made of cars.
+//
+// Only the head of the train has a position. Cars are placed by walking back from the head along
+// the edges which the train traversed. If the train didn't travel far enough yet, the walk
+// continues backward along the route.
+
+use crate::transnet::{Graph, GraphPos};
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Car {
+    pub length: f32,
+}
+
+/// Front and rear of the car
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CarPos {
+    pub front: GraphPos,
+    pub rear: GraphPos,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Consist {
+    pub cars: Vec<Car>,
+    /// Space between the cars
+    pub gap: f32,
+    /// Edges (and directions) traversed before the edge of the head. The most recent is the last one
+    trail: Vec<(usize, bool)>,
+}
+
+
+impl Car {
+    pub fn new(length: f32) -> Self {
+        Self { length }
+    }
+}
+
+impl Consist {
+    pub fn new(cars: Vec<Car>, gap: f32) -> Self {
+        Self { cars, gap, trail: vec![] }
+    }
+
+    /// Consist of the same cars
+    pub fn uniform(count: usize, car_length: f32, gap: f32) -> Self {
+        Self::new(vec![Car::new(car_length); count], gap)
+    }
+
+    /// Length from the front of the first car to the rear of the last one
+    pub fn length(&self) -> f32 {
+        let cars: f32 = self.cars.iter().map(|c| c.length).sum();
+        cars + self.gap * self.cars.len().saturating_sub(1) as f32
+    }
+
+    /// Remember the edges passed by the head while it moved from `old_head` to `new_head`
+    pub fn follow(&mut self, graph: &Graph, route: &[usize], old_head: &GraphPos, new_head: &GraphPos) {
+        let mut pos = *old_head;
+        // Each edge of the route is passed at most once in a single move
+        for _ in 0..=route.len() {
+            if pos.edge_id() == new_head.edge_id() {
+                break;
+            }
+            self.trail.push((pos.edge_id(), pos.is_reversed()));
+            let Some((edge_id, reversed)) = graph.next_edge(&pos, route) else {
+                break;
+            };
+            let length = graph.edges[edge_id].length();
+            pos = GraphPos::with_progress(edge_id, reversed, length, length);
+        }
+
+        // Forget edges behind the last car
+        let length = graph.edges[new_head.edge_id()].length();
+        let mut covered = new_head.progress(length);
+        let mut keep = 0;
+        for &(edge_id, _) in self.trail.iter().rev() {
+            if covered >= self.length() {
+                break;
+            }
+            covered += graph.edges[edge_id].length();
+            keep += 1;
+        }
+        self.trail.drain(..self.trail.len() - keep);
+    }
+
+    /// Position which is the given distance behind the head
+    pub fn behind(&self, graph: &Graph, head: &GraphPos, route: &[usize], distance: f32) -> GraphPos {
+        self.walk_back(graph, head, route, distance).0
+    }
+
+    /// Front and rear position of every car
+    pub fn positions(&self, graph: &Graph, head: &GraphPos, route: &[usize]) -> Vec<CarPos> {
+        let mut front = 0.;
+        self.cars.iter()
+            .map(|car| {
+                let pos = CarPos {
+                    front: self.behind(graph, head, route, front),
+                    rear: self.behind(graph, head, route, front + car.length),
+                };
+                front += car.length + self.gap;
+                pos
+            })
+            .collect()
+    }
+
+    /// Edges covered by the train, starting with the edge of the head
+    pub fn occupied_edges(&self, graph: &Graph, head: &GraphPos, route: &[usize]) -> Vec<usize> {
+        self.walk_back(graph, head, route, self.length()).1.into_iter()
+            .map(|(edge_id, _)| edge_id)
+            .collect()
+    }
+
+    /// Turn the train back: the rear of the last car becomes the head. Returns the new head.
+    /// Edges covered by the train become the trail, so the cars stay where they were
+    pub fn reverse(&mut self, graph: &Graph, head: &GraphPos, route: &[usize]) -> GraphPos {
+        let (tail, mut edges) = self.walk_back(graph, head, route, self.length());
+        // Edge of the new head is not the part of the trail
+        edges.pop();
+        self.trail = edges.into_iter()
+            .map(|(edge_id, reversed)| (edge_id, !reversed))
+            .collect();
+        self.cars.reverse();
+        tail.flipped()
+    }
+
+    /// Move the trail to the new edge ids after the graph was built again. Edge without the new
+    /// id is forgotten with all the edges before it
+    pub fn map_trail(&mut self, new_edge: impl Fn(usize) -> Option<usize>) {
+        let mut trail: Vec<(usize, bool)> = self.trail.iter().rev()
+            .map_while(|&(edge_id, reversed)| new_edge(edge_id).map(|e| (e, reversed)))
+            .collect();
+        trail.reverse();
+        self.trail = trail;
+    }
+
+    // Walk back from the head. Returns the position and all the edges (with direction) on the way
+    fn walk_back(&self, graph: &Graph, head: &GraphPos, route: &[usize], distance: f32) -> (GraphPos, Vec<(usize, bool)>) {
+        let mut pos = *head;
+        let mut edges = vec![(pos.edge_id(), pos.is_reversed())];
+        let mut remaining = distance;
+        let mut trail = self.trail.iter().rev();
+        loop {
+            let length = graph.edges[pos.edge_id()].length();
+            let progress = pos.progress(length);
+            if remaining <= progress {
+                let pos = GraphPos::with_progress(pos.edge_id(), pos.is_reversed(), progress - remaining, length);
+                return (pos, edges);
+            }
+            let prev = trail.next().copied().or_else(|| graph.prev_edge(&pos, route));
+            // Avoid walking around the loop shorter than the train
+            let Some((edge_id, reversed)) = prev.filter(|(e, _)| !edges.iter().any(|(edge_id, _)| edge_id == e)) else {
+                let pos = GraphPos::with_progress(pos.edge_id(), pos.is_reversed(), 0., length);
+                return (pos, edges);
+            };
+            remaining -= progress;
+            edges.push((edge_id, reversed));
+            let length = graph.edges[edge_id].length();
+            pos = GraphPos::with_progress(edge_id, reversed, length, length);
+        }
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Node, SignalError, Signalling};
+
+    //  0 --e0--> 1 --e1--> 2
+    //            |
+    //            e2
+    //            v
+    //            3
+    fn junction() -> Graph {
+        let nodes = vec![
+            Node::new(0., 0.), Node::new(100., 0.), Node::new(200., 0.), Node::new(100., 100.),
+        ];
+        let edges = vec![
+            Edge::new(0, 1, &nodes),
+            Edge::new(1, 2, &nodes),
+            Edge::new(1, 3, &nodes),
+        ];
+        Graph::new(nodes, edges)
+    }
+
+    #[test]
+    fn test_car_positions() {
+        let graph = junction();
+        // Cars 30 long with gap 10: 30 + 10 + 30 + 10 + 30
+        let consist = Consist::uniform(3, 30., 10.);
+        assert_eq!(consist.length(), 110.);
+
+        let cars = consist.positions(&graph, &GraphPos::new(2, 50.), &[0, 2]);
+        assert_eq!(cars[0], CarPos { front: GraphPos::new(2, 50.), rear: GraphPos::new(2, 20.) });
+        // Second car spans the junction
+        assert_eq!(cars[1], CarPos { front: GraphPos::new(2, 10.), rear: GraphPos::new(0, 80.) });
+        assert_eq!(cars[2].rear, GraphPos::new(0, 40.));
+        assert_eq!(consist.occupied_edges(&graph, &GraphPos::new(2, 50.), &[0, 2]), vec![2, 0]);
+    }
+
+    #[test]
+    fn test_trail() {
+        let graph = junction();
+        let mut consist = Consist::uniform(2, 30., 10.);
+        // Head moved from e0 to e1 (off the route [0, 2] the consist would follow back e0 anyway)
+        let old = GraphPos::new(0, 90.);
+        let new = GraphPos::new(1, 20.);
+        consist.follow(&graph, &[0, 1], &old, &new);
+        assert_eq!(consist.trail, vec![(0, false)]);
+        // Walk back uses the trail, not the route
+        assert_eq!(consist.behind(&graph, &new, &[1], 70.), GraphPos::new(0, 50.));
+
+        // Trail is forgotten when the train moves on
+        consist.follow(&graph, &[0, 1], &new, &GraphPos::new(1, 80.));
+        assert_eq!(consist.trail, vec![]);
+        // Train can't go beyond the beginning of the track
+        assert_eq!(consist.behind(&graph, &GraphPos::new(0, 20.), &[0], 70.), GraphPos::new(0, 0.));
+    }
+
+    #[test]
+    fn test_reverse() {
+        let graph = junction();
+        let mut consist = Consist::new(vec![Car::new(30.), Car::new(20.)], 10.);
+        let head = GraphPos::new(1, 20.);
+        consist.follow(&graph, &[0, 1], &GraphPos::new(0, 90.), &head);
+        let cars = consist.positions(&graph, &head, &[1]);
+
+        // Rear of the last car on e0 is the new head, the old head is the end of the train
+        let head = consist.reverse(&graph, &head, &[1]);
+        assert_eq!(head, GraphPos::new_reversed(0, 60.));
+        assert_eq!(consist.trail, vec![(1, true)]);
+        assert_eq!(consist.cars, vec![Car::new(20.), Car::new(30.)]);
+        // Cars stay in place, even without the route behind the new head
+        let reversed = consist.positions(&graph, &head, &[0]);
+        assert_eq!(reversed[0], CarPos { front: cars[1].rear.flipped(), rear: cars[1].front.flipped() });
+        assert_eq!(reversed[1], CarPos { front: cars[0].rear.flipped(), rear: cars[0].front.flipped() });
+    }
+
+    #[test]
+    fn test_map_trail() {
+        let mut consist = Consist::uniform(3, 30., 10.);
+        consist.trail = vec![(0, false), (1, true), (2, false)];
+        // Edge 1 is gone, edge 0 can't be reached anymore
+        consist.map_trail(|e| [Some(5), None, Some(3)][e]);
+        assert_eq!(consist.trail, vec![(3, false)]);
+        consist.map_trail(|e| Some(e + 1));
+        assert_eq!(consist.trail, vec![(4, false)]);
+    }
+
+    #[test]
+    fn test_occupancy() {
+        let graph = junction();
+        let consist = Consist::uniform(2, 30., 10.);
+        let mut signals = Signalling::new(&graph);
+        let head = GraphPos::new(2, 50.);
+        signals.place_consist(0, &consist.occupied_edges(&graph, &head, &[0, 2])).unwrap();
+        assert_eq!(signals.reserved_by(0), Some(0));
+        assert_eq!(signals.occupied_by(0), Some(0));
+
+        // Junction node is covered by the train, nobody can go through it
+        assert_eq!(signals.place_train(1, &GraphPos::init(1)), Err(SignalError::Occupied { edge: 1, train: 0 }));
+
+        // Rear cleared e0
+        let head = GraphPos::new(2, 80.);
+        signals.set_occupied(0, consist.occupied_edges(&graph, &head, &[0, 2]));
+        assert_eq!(signals.reserved_by(0), None);
+        assert_eq!(signals.reserved_by(2), Some(0));
+    }
+}
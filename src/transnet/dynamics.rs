@@ -0,0 +1,255 @@
+// Train dynamics: acceleration, braking curves, speed limits and grades.
+//
+// Units are meters, seconds and kilograms (graph units are treated as meters).
+// Train accelerates with the maximum traction up to the allowed speed. Allowed speed comes from
+// the braking curves of everything ahead: lower speed limits and the point where the train has
+// to stop (station, red signal, end of the route).
+
+use crate::transnet::{Graph, GraphPos};
+
+
+pub const GRAVITY: f32 = 9.81;
+
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrainSpec {
+    /// Mass in kg
+    pub mass: f32,
+    /// Maximum traction force in N
+    pub max_traction: f32,
+    /// Maximum braking force in N
+    pub max_braking: f32,
+    /// Maximum speed in m/s
+    pub max_speed: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dynamics {
+    pub spec: TrainSpec,
+    /// Current speed in m/s
+    pub speed: f32,
+    /// Total distance traveled
+    pub odometer: f32,
+}
+
+/// Speed of the train at the distance traveled
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SpeedProfile {
+    pub points: Vec<(f32, f32)>,
+}
+
+
+impl TrainSpec {
+    pub fn new(mass: f32, max_traction: f32, max_braking: f32, max_speed: f32) -> Self {
+        Self { mass, max_traction, max_braking, max_speed }
+    }
+
+    /// Deceleration with full brakes on the flat track
+    pub fn deceleration(&self) -> f32 {
+        self.max_braking / self.mass
+    }
+
+    /// Distance needed to stop from the given speed on the flat track
+    pub fn stopping_distance(&self, speed: f32) -> f32 {
+        speed.powi(2) / (2. * self.deceleration())
+    }
+}
+
+impl Dynamics {
+    pub fn new(spec: TrainSpec) -> Self {
+        Self { spec, speed: 0., odometer: 0. }
+    }
+
+    /// Advance the train by the time step. `stop` is the distance to the point where the train
+    /// has to stop (infinity if there is none). Returns the distance traveled.
+    pub fn step(&mut self, graph: &Graph, pos: &GraphPos, route: &[usize], stop: f32, dt: f32) -> f32 {
+        let edge = &graph.edges[pos.edge_id()];
+        let grade = edge.grade(pos.is_reversed());
+        // Downhill makes the brakes weaker
+        let braking = (self.spec.deceleration() + GRAVITY * grade.min(0.)).max(0.1);
+
+        let lookahead = self.lookahead() + self.speed * dt;
+        let mut target = self.spec.max_speed;
+        for (distance, limit) in speed_limits(graph, pos, route, lookahead) {
+            // Speed has to be under the limit before the train gets there in this step
+            let distance = (distance - self.speed * dt).max(0.);
+            target = target.min((limit.powi(2) + 2. * braking * distance).sqrt());
+        }
+        if stop.is_finite() {
+            target = target.min((2. * braking * stop.max(0.)).sqrt());
+        }
+
+        let speed = self.speed;
+        let new_speed = if speed < target {
+            let acceleration = self.spec.max_traction / self.spec.mass - GRAVITY * grade;
+            (speed + acceleration * dt).clamp(0., target)
+        } else {
+            let deceleration = self.spec.deceleration() + GRAVITY * grade;
+            (speed - deceleration * dt).max(target)
+        };
+
+        let mut distance = 0.5 * (speed + new_speed) * dt;
+        self.speed = new_speed;
+        if distance >= stop {
+            distance = stop.max(0.);
+            self.speed = 0.;
+        }
+        self.odometer += distance;
+        distance
+    }
+
+    /// How far ahead the train has to look to brake in time
+    pub fn lookahead(&self) -> f32 {
+        self.spec.stopping_distance(self.spec.max_speed.max(self.speed)) + 1.
+    }
+}
+
+impl SpeedProfile {
+    pub fn record(&mut self, distance: f32, speed: f32) {
+        self.points.push((distance, speed));
+    }
+
+    /// Speed sampled at equal distance steps, starting at the first recorded point.
+    /// Can be drawn with `mqx::plot::Plot`.
+    pub fn series(&self, step: f32) -> Vec<f32> {
+        let (Some(first), Some(last)) = (self.points.first(), self.points.last()) else {
+            return vec![];
+        };
+        let mut series = vec![];
+        let mut idx = 0;
+        let mut distance = first.0;
+        while distance <= last.0 {
+            while idx + 1 < self.points.len() && self.points[idx + 1].0 < distance {
+                idx += 1;
+            }
+            let (d1, v1) = self.points[idx];
+            let (d2, v2) = self.points.get(idx + 1).copied().unwrap_or((d1, v1));
+            let speed = if d2 > d1 { v1 + (v2 - v1) * (distance - d1) / (d2 - d1) } else { v1 };
+            series.push(speed);
+            distance += step;
+        }
+        series
+    }
+}
+
+
+// Distance to the beginning of every edge ahead which has speed limit, with the limit.
+// Limit of the current edge is at distance 0.
+fn speed_limits(graph: &Graph, pos: &GraphPos, route: &[usize], lookahead: f32) -> Vec<(f32, f32)> {
+    let edge = &graph.edges[pos.edge_id()];
+    let mut limits: Vec<(f32, f32)> = edge.speed_limit.map(|limit| (0., limit)).into_iter().collect();
+    let mut distance = edge.length() - pos.progress(edge.length());
+    let mut pos = *pos;
+    for _ in 0..route.len() {
+        if distance >= lookahead {
+            break;
+        }
+        let Some((edge_id, reversed)) = graph.next_edge(&pos, route) else {
+            break;
+        };
+        let edge = &graph.edges[edge_id];
+        if let Some(limit) = edge.speed_limit {
+            limits.push((distance, limit));
+        }
+        distance += edge.length();
+        pos = GraphPos::with_progress(edge_id, reversed, edge.length(), edge.length());
+    }
+    limits
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Node};
+
+    // 100t train, 1 m/s2 acceleration, 2 m/s2 braking, 30 m/s max
+    fn spec() -> TrainSpec {
+        TrainSpec::new(100_000., 100_000., 200_000., 30.)
+    }
+
+    // Straight line made of 1km edges
+    fn line(count: usize) -> Graph {
+        let nodes: Vec<Node> = (0..=count).map(|i| Node::new(1000. * i as f32, 0.)).collect();
+        let edges = (0..count).map(|i| Edge::new(i, i + 1, &nodes)).collect();
+        Graph::new(nodes, edges)
+    }
+
+    // Run until the train stops or reaches the end of the route. Returns final position and profile
+    fn run(graph: &Graph, dynamics: &mut Dynamics, stop: f32) -> (GraphPos, SpeedProfile) {
+        let route: Vec<usize> = (0..graph.edges.len()).collect();
+        let total: f32 = graph.edges.iter().map(|e| e.length()).sum();
+        let mut pos = GraphPos::init(0);
+        let mut profile = SpeedProfile::default();
+        for _ in 0..10_000 {
+            let remaining = stop.min(total) - dynamics.odometer;
+            let distance = dynamics.step(graph, &pos, &route, remaining, 0.1);
+            pos = graph.update_pos(&pos, &route, distance);
+            profile.record(dynamics.odometer, dynamics.speed);
+            if dynamics.speed == 0. && dynamics.odometer > 0. {
+                break;
+            }
+        }
+        (pos, profile)
+    }
+
+    #[test]
+    fn test_acceleration() {
+        let graph = line(1);
+        let mut dynamics = Dynamics::new(spec());
+        dynamics.step(&graph, &GraphPos::init(0), &[0], f32::INFINITY, 1.);
+        assert_eq!(dynamics.speed, 1.);
+        assert_eq!(dynamics.odometer, 0.5);
+
+        // Uphill the train accelerates slower
+        let mut graph = line(1);
+        graph.edges[0] = Edge::new(0, 1, &graph.nodes).with_grade(0.05);
+        let mut dynamics = Dynamics::new(spec());
+        dynamics.step(&graph, &GraphPos::init(0), &[0], f32::INFINITY, 1.);
+        assert!((dynamics.speed - (1. - 0.05 * GRAVITY)).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_stops_at_the_station() {
+        let graph = line(3);
+        let mut dynamics = Dynamics::new(spec());
+        let (pos, profile) = run(&graph, &mut dynamics, 1500.);
+
+        assert_eq!(pos.edge_id(), 1);
+        assert!((pos.distance() - 500.).abs() < 0.01);
+        assert!(profile.points.iter().all(|&(d, _)| d <= 1500.));
+        let max_speed = profile.points.iter().map(|p| p.1).fold(0., f32::max);
+        assert_eq!(max_speed, 30.);
+        // Full brakes from the max speed
+        let braking_start = profile.points.iter().rev().find(|p| p.1 == 30.).unwrap().0;
+        assert!(1500. - braking_start - spec().stopping_distance(30.) < 10.);
+    }
+
+    #[test]
+    fn test_speed_limit() {
+        let mut graph = line(3);
+        graph.edges[1] = Edge::new(1, 2, &graph.nodes).with_speed_limit(10.);
+        let mut dynamics = Dynamics::new(spec());
+        let (pos, profile) = run(&graph, &mut dynamics, f32::INFINITY);
+
+        assert_eq!(pos.edge_id(), 2);
+        assert!((pos.distance() - 1000.).abs() < 0.01);
+        for &(distance, speed) in &profile.points {
+            if (1000. ..=2000.).contains(&distance) {
+                assert!(speed <= 10.01, "{} at {}", speed, distance);
+            }
+        }
+        // Speeds up after the limit
+        assert_eq!(profile.points.iter().map(|p| p.1).fold(0., f32::max), 30.);
+    }
+
+    #[test]
+    fn test_profile_series() {
+        let profile = SpeedProfile { points: vec![(0., 0.), (10., 10.), (30., 10.)] };
+        assert_eq!(profile.series(5.), vec![0., 5., 10., 10., 10., 10., 10.]);
+        assert!(SpeedProfile::default().series(1.).is_empty());
+    }
+}
@@ -0,0 +1,583 @@
+// Track network file (TOML): the graph, stations, schedules, trains, passenger demand and roads.
+//
+// ```toml
+// [[nodes]]
+// x = 100.0
+// y = 100.0
+//
+// [[edges]]
+// from = 0
+// to = 1
+// direction = "both"                          # optional, "one_way" by default
+// geometry = { type = "arc", center = { x = 400.0, y = 100.0 }, clockwise = true }
+// speed_limit = 60.0                          # optional
+// grade = 0.01                                # optional, rising from `from` to `to`
+//
+// [[junctions]]
+// node = 1
+// allowed = [[0, 1], [0, 3]]                  # or forbidden = [...]
+//
+// [[signals]]
+// node = 1
+// signal = "path"                             # or "block" (default at every node)
+//
+// [[stations]]
+// name = "Harbour"
+// edge = 0
+// distance = 300.0                            # from the start of the edge
+// dwell = 2.0
+//
+// [[schedules]]
+// edges = [0, 1, 2]                           # connected edges. Last one may connect to the first one
+//
+// [[trains]]
+// schedule = 0
+// edge = 0                                    # position of the head
+// distance = 150.0
+// max_speed = 200.0
+// cars = 3                                    # optional: cars, car_length, gap, mass, max_traction, max_braking
+// capacity = 100                              # optional, passengers
+// timetable = { stops = [{ station = 0, departure = 3.0 }], period = 14.0 }
+//
+// [[demand]]
+// from = 0                                    # stations
+// to = 1
+// rate = 600.0                                # passengers per hour
+//
+// [roads]                                      # optional road network, has its own nodes
+// nodes = [{ x = 150.0, y = 250.0 }, { x = 250.0, y = 250.0 }]
+// edges = [{ from = 0, to = 1, lanes = 2, speed_limit = 14.0 }]  # one-way, geometry as above
+// intersections = [{ node = 1, control = { type = "lights", phases = [{ green = [0], duration = 20.0 }] } }]
+//                                              # or { type = "priority", major = [0] }
+// flows = [{ route = [0], rate = 600.0 }]      # cars per hour
+// ```
+//
+// Loaded file is validated, every problem found is reported.
+
+use std::fs;
+use std::path::Path;
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::transnet::{
+    Consist, Curve, Demand, Direction, Edge, Geometry, Graph, GraphPos, Junction, Node, RoadsDef, Signal, Signalling,
+    Station, Timetable, TrainSpec,
+};
+
+
+#[derive(Debug)]
+pub enum NetworkError {
+    Io(std::io::Error),
+    Parse(String),
+    /// All problems found in the file
+    Invalid(Vec<String>),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct EdgeDef {
+    pub from: usize,
+    pub to: usize,
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub geometry: Geometry,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_limit: Option<f32>,
+    #[serde(default)]
+    pub grade: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct JunctionDef {
+    pub node: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub allowed: Option<Vec<(usize, usize)>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forbidden: Option<Vec<(usize, usize)>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct SignalDef {
+    pub node: usize,
+    #[serde(default)]
+    pub signal: Signal,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct StationDef {
+    pub name: String,
+    pub edge: usize,
+    pub distance: f32,
+    pub dwell: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct ScheduleDef {
+    pub edges: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct TrainDef {
+    pub schedule: usize,
+    /// Edge of the head
+    pub edge: usize,
+    #[serde(default)]
+    pub distance: f32,
+    #[serde(default)]
+    pub reversed: bool,
+    pub max_speed: f32,
+    #[serde(default = "default_cars")]
+    pub cars: usize,
+    #[serde(default = "default_car_length")]
+    pub car_length: f32,
+    #[serde(default = "default_gap")]
+    pub gap: f32,
+    #[serde(default = "default_mass")]
+    pub mass: f32,
+    #[serde(default = "default_max_traction")]
+    pub max_traction: f32,
+    #[serde(default = "default_max_braking")]
+    pub max_braking: f32,
+    #[serde(default = "default_capacity")]
+    pub capacity: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timetable: Option<Timetable>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct NetworkFile {
+    #[serde(default)]
+    pub nodes: Vec<Node>,
+    #[serde(default)]
+    pub edges: Vec<EdgeDef>,
+    #[serde(default)]
+    pub junctions: Vec<JunctionDef>,
+    #[serde(default)]
+    pub signals: Vec<SignalDef>,
+    #[serde(default)]
+    pub stations: Vec<StationDef>,
+    #[serde(default)]
+    pub schedules: Vec<ScheduleDef>,
+    #[serde(default)]
+    pub trains: Vec<TrainDef>,
+    #[serde(default)]
+    pub demand: Vec<Demand>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub roads: Option<RoadsDef>,
+}
+
+
+fn default_cars() -> usize { 3 }
+fn default_car_length() -> f32 { 40. }
+fn default_gap() -> f32 { 5. }
+fn default_mass() -> f32 { 100_000. }
+fn default_max_traction() -> f32 { 5_000_000. }
+fn default_max_braking() -> f32 { 10_000_000. }
+fn default_capacity() -> usize { 100 }
+
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Io(err) => write!(f, "Can't read network file: {}", err),
+            NetworkError::Parse(err) => write!(f, "Can't parse network file: {}", err),
+            NetworkError::Invalid(issues) => {
+                writeln!(f, "Invalid network file:")?;
+                for issue in issues {
+                    writeln!(f, "  {}", issue)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
+
+impl From<std::io::Error> for NetworkError {
+    fn from(err: std::io::Error) -> Self {
+        NetworkError::Io(err)
+    }
+}
+
+
+impl TrainDef {
+    pub fn pos(&self) -> GraphPos {
+        if self.reversed {
+            GraphPos::new_reversed(self.edge, self.distance)
+        } else {
+            GraphPos::new(self.edge, self.distance)
+        }
+    }
+
+    pub fn spec(&self) -> TrainSpec {
+        TrainSpec::new(self.mass, self.max_traction, self.max_braking, self.max_speed)
+    }
+
+    pub fn consist(&self) -> Consist {
+        Consist::uniform(self.cars, self.car_length, self.gap)
+    }
+}
+
+impl NetworkFile {
+    /// File with the graph only
+    pub fn from_graph(graph: &Graph) -> Self {
+        let edges = graph.edges.iter()
+            .map(|edge| EdgeDef {
+                from: edge.from_node_id,
+                to: edge.to_node_id,
+                direction: edge.direction,
+                geometry: edge.curve().geometry(),
+                speed_limit: edge.speed_limit,
+                grade: edge.grade,
+            })
+            .collect();
+        let junctions = graph.junctions.iter().enumerate()
+            .filter_map(|(node, junction)| match junction {
+                Junction::Free => None,
+                Junction::Allowed(turns) => Some(JunctionDef { node, allowed: Some(turns.clone()), forbidden: None }),
+                Junction::Forbidden(turns) => Some(JunctionDef { node, allowed: None, forbidden: Some(turns.clone()) }),
+            })
+            .collect();
+        Self { nodes: graph.nodes.clone(), edges, junctions, ..Self::default() }
+    }
+
+    /// The file with the graph replaced. Signals, stations, schedules, trains and demand refer to
+    /// the node and edge ids, so they are kept only if the graph is the same. Roads are kept
+    pub fn with_graph(&self, graph: &Graph) -> Self {
+        let Self { nodes, edges, junctions, .. } = Self::from_graph(graph);
+        if nodes == self.nodes && edges == self.edges && junctions == self.junctions {
+            return self.clone();
+        }
+        Self { nodes, edges, junctions, roads: self.roads.clone(), ..Self::default() }
+    }
+
+    /// Save the graph to the file at the path, keeping the other sections of the file which are
+    /// still valid (see `with_graph`)
+    pub fn save_graph(graph: &Graph, path: &Path) -> Result<(), NetworkError> {
+        let file = match Self::load(path) {
+            Ok(file) => file,
+            Err(NetworkError::Io(err)) if err.kind() == std::io::ErrorKind::NotFound => Self::default(),
+            Err(err) => return Err(err),
+        };
+        let file = file.with_graph(graph);
+        let issues = file.validate();
+        if !issues.is_empty() {
+            return Err(NetworkError::Invalid(issues));
+        }
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        file.save(path)
+    }
+
+    /// Parse and validate the file
+    pub fn parse(text: &str) -> Result<Self, NetworkError> {
+        let file: NetworkFile = toml::from_str(text).map_err(|err| NetworkError::Parse(err.to_string()))?;
+        let issues = file.validate();
+        if issues.is_empty() {
+            Ok(file)
+        } else {
+            Err(NetworkError::Invalid(issues))
+        }
+    }
+
+    pub fn load(path: &Path) -> Result<Self, NetworkError> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), NetworkError> {
+        let text = toml::to_string(self).map_err(|err| NetworkError::Parse(err.to_string()))?;
+        fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// Graph of the validated file
+    pub fn to_graph(&self) -> Graph {
+        let edges = self.edges.iter()
+            .map(|def| {
+                let mut edge = Edge::with_geometry(def.from, def.to, def.geometry, &self.nodes).with_grade(def.grade);
+                edge.direction = def.direction;
+                edge.speed_limit = def.speed_limit;
+                edge
+            })
+            .collect();
+        let mut graph = Graph::new(self.nodes.clone(), edges);
+        for def in &self.junctions {
+            if let Some(turns) = &def.allowed {
+                graph.set_junction(def.node, Junction::Allowed(turns.clone()));
+            } else if let Some(turns) = &def.forbidden {
+                graph.set_junction(def.node, Junction::Forbidden(turns.clone()));
+            }
+        }
+        graph
+    }
+
+    /// Signalling with the signals from the file. Trains are not placed yet
+    pub fn signalling(&self, graph: &Graph) -> Signalling {
+        let mut signals = Signalling::new(graph);
+        for def in &self.signals {
+            signals.set_signal(def.node, def.signal);
+        }
+        signals
+    }
+
+    pub fn stations(&self) -> Vec<Station> {
+        self.stations.iter()
+            .map(|def| Station::new(&def.name, GraphPos::new(def.edge, def.distance), def.dwell))
+            .collect()
+    }
+
+    pub fn schedules(&self) -> Vec<Vec<usize>> {
+        self.schedules.iter().map(|s| s.edges.clone()).collect()
+    }
+
+    /// Every problem found in the file
+    pub fn validate(&self) -> Vec<String> {
+        let mut issues = vec![];
+        let node_ok = |node: usize| node < self.nodes.len();
+        let edge_ok = |edge: usize| edge < self.edges.len();
+
+        let mut lengths = vec![];
+        for (edge_id, def) in self.edges.iter().enumerate() {
+            for node in [def.from, def.to].into_iter().filter(|&n| !node_ok(n)) {
+                issues.push(format!("Edge {} refers to node {} which doesn't exist", edge_id, node));
+            }
+            if node_ok(def.from) && node_ok(def.to) {
+                let length = Curve::new(self.nodes[def.from], self.nodes[def.to], def.geometry).length();
+                if !is_positive(length) {
+                    issues.push(format!("Edge {} has zero length", edge_id));
+                }
+                lengths.push(length);
+            }
+        }
+        let edges_ok = issues.is_empty();
+
+        for def in &self.junctions {
+            if !node_ok(def.node) {
+                issues.push(format!("Junction refers to node {} which doesn't exist", def.node));
+            }
+            let turns = def.allowed.iter().chain(&def.forbidden).flatten();
+            for edge in turns.flat_map(|&(a, b)| [a, b]).filter(|&e| !edge_ok(e)) {
+                issues.push(format!("Junction at node {} refers to edge {} which doesn't exist", def.node, edge));
+            }
+        }
+        for def in self.signals.iter().filter(|s| !node_ok(s.node)) {
+            issues.push(format!("Signal refers to node {} which doesn't exist", def.node));
+        }
+
+        for def in &self.stations {
+            if !edge_ok(def.edge) {
+                issues.push(format!("Station '{}' is on edge {} which doesn't exist", def.name, def.edge));
+            } else if edges_ok && !(0. ..=lengths[def.edge]).contains(&def.distance) {
+                issues.push(format!("Station '{}' is outside of edge {}", def.name, def.edge));
+            }
+        }
+
+        // Connections are checked only on the valid graph
+        let graph = if edges_ok && issues.is_empty() { Some(self.to_graph()) } else { None };
+        for (schedule_id, def) in self.schedules.iter().enumerate() {
+            if def.edges.is_empty() {
+                issues.push(format!("Schedule {} is empty", schedule_id));
+            }
+            let invalid: Vec<usize> = def.edges.iter().copied().filter(|&e| !edge_ok(e)).collect();
+            for edge in &invalid {
+                issues.push(format!("Schedule {} refers to edge {} which doesn't exist", schedule_id, edge));
+            }
+            if let (Some(graph), true) = (&graph, invalid.is_empty()) {
+                for (from, to) in disconnected(graph, &def.edges) {
+                    issues.push(format!("Schedule {}: edge {} doesn't connect to edge {}", schedule_id, from, to));
+                }
+            }
+        }
+
+        for (train_id, def) in self.trains.iter().enumerate() {
+            if def.schedule >= self.schedules.len() {
+                issues.push(format!("Train {} has schedule {} which doesn't exist", train_id, def.schedule));
+            }
+            if !edge_ok(def.edge) {
+                issues.push(format!("Train {} is on edge {} which doesn't exist", train_id, def.edge));
+            } else if edges_ok && !(0. ..=lengths[def.edge]).contains(&def.distance) {
+                issues.push(format!("Train {} is outside of edge {}", train_id, def.edge));
+            }
+            if def.cars == 0 {
+                issues.push(format!("Train {} has no cars", train_id));
+            }
+            if !is_positive(def.max_speed) || !is_positive(def.mass) {
+                issues.push(format!("Train {} needs positive max speed and mass", train_id));
+            }
+            let stops = def.timetable.iter().flat_map(|t| &t.stops);
+            for stop in stops.filter(|s| s.station >= self.stations.len()) {
+                issues.push(format!("Train {} stops at station {} which doesn't exist", train_id, stop.station));
+            }
+        }
+
+        for (idx, def) in self.demand.iter().enumerate() {
+            for station in [def.from, def.to].into_iter().filter(|&s| s >= self.stations.len()) {
+                issues.push(format!("Demand {} refers to station {} which doesn't exist", idx, station));
+            }
+            if def.rate < 0. {
+                issues.push(format!("Demand {} has negative rate", idx));
+            }
+        }
+        if let Some(roads) = &self.roads {
+            issues.extend(roads.validate());
+        }
+        issues
+    }
+}
+
+
+// False for NaN too
+fn is_positive(value: f32) -> bool {
+    value > 0.
+}
+
+// Consecutive edges of the route which can't be traveled one after another
+fn disconnected(graph: &Graph, route: &[usize]) -> Vec<(usize, usize)> {
+    let directions = |edge_id: usize| match graph.edges[edge_id].direction {
+        Direction::OneWay => vec![false],
+        Direction::Both => vec![false, true],
+    };
+    let mut result = vec![];
+    let mut reversed = route.first().map(|&e| directions(e)).unwrap_or_default();
+    for pair in route.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let next: Vec<bool> = reversed.iter()
+            .filter_map(|&r| graph.turn(graph.edges[from].end_node(r), from, to))
+            .collect();
+        if next.is_empty() {
+            result.push((from, to));
+            // Check the rest of the route as if this connection was fine
+            reversed = directions(to);
+        } else {
+            reversed = next;
+        }
+    }
+    result
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let nodes = vec![Node::new(100., 100.), Node::new(700., 100.), Node::new(700., 500.)];
+        let edges = vec![
+            Edge::new(0, 1, &nodes).with_speed_limit(50.),
+            Edge::with_geometry(1, 2, Geometry::Arc { center: Node::new(700., 300.), clockwise: true }, &nodes)
+                .bidirectional(),
+        ];
+        let mut graph = Graph::new(nodes, edges);
+        graph.set_junction(1, Junction::Forbidden(vec![(1, 1)]));
+
+        let text = toml::to_string(&NetworkFile::from_graph(&graph)).unwrap();
+        let loaded = NetworkFile::parse(&text).unwrap().to_graph();
+        assert_eq!(loaded, graph);
+    }
+
+    #[test]
+    fn test_save_graph() {
+        let path = std::env::temp_dir().join(format!("transnet_save_graph_{}.toml", std::process::id()));
+        fs::write(&path, include_str!("../../params/trans/triangle.toml")).unwrap();
+        let original = NetworkFile::load(&path).unwrap();
+        // The same graph keeps everything
+        let mut graph = original.to_graph();
+        NetworkFile::save_graph(&graph, &path).unwrap();
+        assert_eq!(NetworkFile::load(&path).unwrap(), original);
+
+        // Station "Hill" is on the edge 2, edge 3 becomes edge 2
+        assert_eq!(original.stations[1].edge, 2);
+        graph.remove_edge(2);
+        NetworkFile::save_graph(&graph, &path).unwrap();
+        let saved = NetworkFile::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved.to_graph(), graph);
+        assert_eq!(saved, NetworkFile { roads: original.roads.clone(), ..NetworkFile::from_graph(&graph) });
+    }
+
+    #[test]
+    fn test_parse() {
+        let file = NetworkFile::parse(r#"
+            [[nodes]]
+            x = 0.0
+            y = 0.0
+
+            [[nodes]]
+            x = 30.0
+            y = 40.0
+
+            [[edges]]
+            from = 0
+            to = 1
+            direction = "both"
+            geometry = { type = "bezier", control1 = { x = 0.0, y = 40.0 }, control2 = { x = 30.0, y = 0.0 } }
+
+            [[stations]]
+            name = "End"
+            edge = 0
+            distance = 50.0
+            dwell = 10.0
+
+            [[schedules]]
+            edges = [0]
+
+            [[trains]]
+            schedule = 0
+            edge = 0
+            reversed = true
+            max_speed = 20.0
+            timetable = { stops = [{ station = 0, departure = 30.0 }], period = 60.0 }
+        "#).unwrap();
+        let graph = file.to_graph();
+        assert_eq!(graph.edges[0].direction, Direction::Both);
+        assert!(graph.edges[0].length() > 50.);
+        assert_eq!(file.stations()[0].pos, GraphPos::new(0, 50.));
+        let train = &file.trains[0];
+        assert_eq!(train.pos(), GraphPos::new_reversed(0, 0.));
+        assert_eq!(train.consist().cars.len(), 3);
+        assert_eq!(train.timetable.as_ref().unwrap().period, Some(60.));
+
+        assert!(matches!(NetworkFile::parse("[[nodes]]\nx = 1"), Err(NetworkError::Parse(_))));
+        // Network of the trans example
+        let file = NetworkFile::parse(include_str!("../../params/trans/triangle.toml")).unwrap();
+        assert_eq!((file.edges.len(), file.stations.len(), file.trains.len()), (4, 3, 3));
+        assert_eq!(file.roads.as_ref().map(|r| r.edges.len()), Some(8));
+    }
+
+    #[test]
+    fn test_validation() {
+        let text = r#"
+            nodes = [{ x = 0.0, y = 0.0 }, { x = 100.0, y = 0.0 }, { x = 100.0, y = 0.0 }]
+            edges = [{ from = 0, to = 1 }, { from = 1, to = 2 }, { from = 1, to = 0 }, { from = 2, to = 5 }]
+            stations = [{ name = "Nowhere", edge = 7, distance = 0.0, dwell = 1.0 }]
+            trains = [{ schedule = 1, edge = 0, max_speed = 10.0 }]
+        "#;
+        let Err(NetworkError::Invalid(issues)) = NetworkFile::parse(text) else {
+            panic!("Invalid file accepted");
+        };
+        assert_eq!(issues, vec![
+            "Edge 1 has zero length",
+            "Edge 3 refers to node 5 which doesn't exist",
+            "Station 'Nowhere' is on edge 7 which doesn't exist",
+            "Train 0 has schedule 1 which doesn't exist",
+        ]);
+
+        // Edges 0 -> 1 can't be followed by 0 -> 1 again
+        let text = r#"
+            nodes = [{ x = 0.0, y = 0.0 }, { x = 100.0, y = 0.0 }]
+            edges = [{ from = 0, to = 1 }, { from = 1, to = 0 }]
+            schedules = [{ edges = [0, 1, 0, 0] }, { edges = [1, 0, 1] }]
+        "#;
+        let Err(NetworkError::Invalid(issues)) = NetworkFile::parse(text) else {
+            panic!("Invalid file accepted");
+        };
+        assert_eq!(issues, vec!["Schedule 0: edge 0 doesn't connect to edge 0"]);
+    }
+}
@@ -0,0 +1,251 @@
+// Shape of the track between two nodes.
+//
+// Every curve is parameterised by the arc length, so distance on the edge is the real
+// distance traveled by the train.
+
+use std::f32::consts::{PI, TAU};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::transnet::Node;
+
+
+// Number of segments used to approximate Bezier curve length
+const BEZIER_SEGMENTS: usize = 64;
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Geometry {
+    #[default]
+    Straight,
+    /// Circular arc around the center. The end node should lie on the same circle as the start node.
+    /// Clockwise is as seen on the screen (y axis pointing down).
+    Arc { center: Node, clockwise: bool },
+    /// Cubic Bezier curve with two control points
+    Bezier { control1: Node, control2: Node },
+}
+
+/// Point on the track and heading (angle in radians, as `atan2(dy, dx)`) of the direction of travel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Location {
+    pub point: Node,
+    pub heading: f32,
+}
+
+/// Geometry resolved for the given end points
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve {
+    start: Node,
+    end: Node,
+    geometry: Geometry,
+    length: f32,
+    /// Arc length at the end of every segment (Bezier only)
+    segments: Vec<f32>,
+}
+
+
+impl Location {
+    /// The same point, looking the other way
+    pub fn reversed(&self) -> Self {
+        let heading = self.heading + PI;
+        Self { point: self.point, heading: heading.sin().atan2(heading.cos()) }
+    }
+}
+
+
+impl Curve {
+    pub fn new(start: Node, end: Node, geometry: Geometry) -> Self {
+        let mut segments = vec![];
+        let length = match geometry {
+            Geometry::Straight => distance(start, end),
+            Geometry::Arc { center, clockwise } => {
+                let (radius, _, sweep) = arc_params(start, end, center, clockwise);
+                radius * sweep.abs()
+            }
+            Geometry::Bezier { control1, control2 } => {
+                let mut prev = start;
+                let mut length = 0.;
+                for i in 1..=BEZIER_SEGMENTS {
+                    let t = i as f32 / BEZIER_SEGMENTS as f32;
+                    let point = bezier_point(start, control1, control2, end, t);
+                    length += distance(prev, point);
+                    segments.push(length);
+                    prev = point;
+                }
+                length
+            }
+        };
+        Self { start, end, geometry, length, segments }
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    pub fn length(&self) -> f32 {
+        self.length
+    }
+
+    /// Location at the given distance from the start, heading towards the end
+    pub fn at(&self, distance: f32) -> Location {
+        let distance = distance.clamp(0., self.length);
+        let frac = if self.length > 0. { distance / self.length } else { 0. };
+        match self.geometry {
+            Geometry::Straight => {
+                let dx = self.end.x - self.start.x;
+                let dy = self.end.y - self.start.y;
+                let point = Node::new(self.start.x + frac * dx, self.start.y + frac * dy);
+                Location { point, heading: dy.atan2(dx) }
+            }
+            Geometry::Arc { center, clockwise } => {
+                let (radius, angle, sweep) = arc_params(self.start, self.end, center, clockwise);
+                let angle = angle + frac * sweep;
+                let point = Node::new(center.x + radius * angle.cos(), center.y + radius * angle.sin());
+                let heading = if clockwise { angle + PI / 2. } else { angle - PI / 2. };
+                Location { point, heading: heading.sin().atan2(heading.cos()) }
+            }
+            Geometry::Bezier { control1, control2 } => {
+                let t = self.bezier_param(distance);
+                let point = bezier_point(self.start, control1, control2, self.end, t);
+                let (dx, dy) = bezier_tangent(self.start, control1, control2, self.end, t);
+                let heading = if dx == 0. && dy == 0. {
+                    // Control point at the end node. Use direction of the chord instead
+                    (self.end.y - self.start.y).atan2(self.end.x - self.start.x)
+                } else {
+                    dy.atan2(dx)
+                };
+                Location { point, heading }
+            }
+        }
+    }
+
+    /// Points along the curve, no more than `step` apart. Used for drawing
+    pub fn points(&self, step: f32) -> Vec<Node> {
+        let count = match self.geometry {
+            Geometry::Straight => 1,
+            _ => (self.length / step).ceil().max(1.) as usize,
+        };
+        (0..=count)
+            .map(|i| self.at(self.length * i as f32 / count as f32).point)
+            .collect()
+    }
+
+    // Bezier parameter t for the given arc length
+    fn bezier_param(&self, distance: f32) -> f32 {
+        let idx = self.segments.partition_point(|&s| s < distance).min(BEZIER_SEGMENTS - 1);
+        let seg_start = if idx == 0 { 0. } else { self.segments[idx - 1] };
+        let seg_length = self.segments[idx] - seg_start;
+        let frac = if seg_length > 0. { (distance - seg_start) / seg_length } else { 0. };
+        (idx as f32 + frac) / BEZIER_SEGMENTS as f32
+    }
+}
+
+
+fn distance(a: Node, b: Node) -> f32 {
+    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt()
+}
+
+// Radius, start angle and signed sweep of the arc
+fn arc_params(start: Node, end: Node, center: Node, clockwise: bool) -> (f32, f32, f32) {
+    let radius = distance(center, start);
+    let start_angle = (start.y - center.y).atan2(start.x - center.x);
+    let end_angle = (end.y - center.y).atan2(end.x - center.x);
+    let mut sweep = (end_angle - start_angle).rem_euclid(TAU);
+    if sweep == 0. {
+        sweep = TAU;
+    }
+    if clockwise {
+        (radius, start_angle, sweep)
+    } else {
+        (radius, start_angle, sweep - TAU)
+    }
+}
+
+fn bezier_point(p0: Node, p1: Node, p2: Node, p3: Node, t: f32) -> Node {
+    let u = 1. - t;
+    let (a, b, c, d) = (u * u * u, 3. * u * u * t, 3. * u * t * t, t * t * t);
+    Node::new(
+        a * p0.x + b * p1.x + c * p2.x + d * p3.x,
+        a * p0.y + b * p1.y + c * p2.y + d * p3.y,
+    )
+}
+
+fn bezier_tangent(p0: Node, p1: Node, p2: Node, p3: Node, t: f32) -> (f32, f32) {
+    let u = 1. - t;
+    let (a, b, c) = (3. * u * u, 6. * u * t, 3. * t * t);
+    (
+        a * (p1.x - p0.x) + b * (p2.x - p1.x) + c * (p3.x - p2.x),
+        a * (p1.y - p0.y) + b * (p2.y - p1.y) + c * (p3.y - p2.y),
+    )
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Node, b: Node) {
+        assert!(distance(a, b) < 0.01, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_straight() {
+        let curve = Curve::new(Node::new(0., 0.), Node::new(0., 100.), Geometry::Straight);
+        assert_eq!(curve.length(), 100.);
+        let loc = curve.at(25.);
+        assert_eq!(loc.point, Node::new(0., 25.));
+        assert_eq!(loc.heading, PI / 2.);
+        assert_eq!(curve.points(10.).len(), 2);
+    }
+
+    #[test]
+    fn test_arc() {
+        // Quarter circle from the top of the circle to the right side
+        let center = Node::new(100., 100.);
+        let curve = Curve::new(Node::new(100., 0.), Node::new(200., 100.), Geometry::Arc { center, clockwise: true });
+        assert!((curve.length() - 50. * PI).abs() < 0.01);
+
+        let loc = curve.at(curve.length() / 2.);
+        let d = 100. / 2f32.sqrt();
+        assert_near(loc.point, Node::new(100. + d, 100. - d));
+        assert!((loc.heading - PI / 4.).abs() < 1e-3);
+        assert_near(curve.at(curve.length()).point, Node::new(200., 100.));
+
+        // The other way round is three quarters
+        let curve = Curve::new(Node::new(100., 0.), Node::new(200., 100.), Geometry::Arc { center, clockwise: false });
+        assert!((curve.length() - 150. * PI).abs() < 0.01);
+        assert!((curve.at(0.).heading.abs() - PI).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_bezier_arc_length() {
+        // Control points on the line give straight track with uneven t
+        let geometry = Geometry::Bezier { control1: Node::new(10., 0.), control2: Node::new(20., 0.) };
+        let curve = Curve::new(Node::new(0., 0.), Node::new(100., 0.), geometry);
+        assert!((curve.length() - 100.).abs() < 0.01);
+        for d in [0., 10., 33., 50., 99., 100.] {
+            assert!((curve.at(d).point.x - d).abs() < 0.05);
+        }
+
+        // S curve. Equal steps along the curve are (almost) equal chords
+        let geometry = Geometry::Bezier { control1: Node::new(100., 0.), control2: Node::new(0., 100.) };
+        let curve = Curve::new(Node::new(0., 0.), Node::new(100., 100.), geometry);
+        let points = curve.points(5.);
+        let step = curve.length() / (points.len() - 1) as f32;
+        for p in points.windows(2) {
+            assert!((distance(p[0], p[1]) - step).abs() < 0.05);
+        }
+        assert_eq!(curve.at(0.).heading, 0.);
+        assert_near(curve.at(curve.length()).point, Node::new(100., 100.));
+    }
+
+    #[test]
+    fn test_reversed_location() {
+        let loc = Location { point: Node::new(0., 0.), heading: PI / 2. };
+        assert!((loc.reversed().heading + PI / 2.).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,663 @@
+// Track graph: nodes, edges and positions on the edges
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::transnet::{Curve, Geometry, Location};
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Node {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Edge can only be traveled from `from_node_id` to `to_node_id`
+    #[default]
+    OneWay,
+    Both,
+}
+
+/// Rules for passing through the node. Turn is a pair (from edge, to edge).
+/// Reversing back onto the same edge is never allowed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Junction {
+    #[default]
+    Free,
+    /// Only listed turns are allowed
+    Allowed(Vec<(usize, usize)>),
+    /// Listed turns are forbidden
+    Forbidden(Vec<(usize, usize)>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub from_node_id: usize,
+    pub to_node_id: usize,
+    pub direction: Direction,
+    /// Maximum speed on the edge
+    pub speed_limit: Option<f32>,
+    /// Rise over run when traveling from `from_node_id` to `to_node_id`. Positive is uphill
+    pub grade: f32,
+    curve: Curve,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Graph {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+    /// Rules for every node
+    pub junctions: Vec<Junction>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GraphPos {
+    edge_id: usize,
+    /// Distance from the from node of the edge
+    distance: f32,
+    /// Train travels the edge against its direction (from `to_node_id` to `from_node_id`)
+    reversed: bool,
+}
+
+/// What to do when there is no edge to continue to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RouteEnd {
+    #[default]
+    Stop,
+    /// Turn back (if the edge can be traveled the other way) and follow the route backward
+    Reverse,
+}
+
+/// Something passed while moving along the route
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MoveEvent {
+    /// Moved from one edge to another at the node
+    PassedNode { node_id: usize, from_edge: usize, to_edge: usize },
+    /// Stopped at the node, the route doesn't continue
+    EndOfRoute { node_id: usize },
+    /// Turned back at the node. The rest of the move (and the following ones) goes along the route
+    /// in the reverse order
+    Reversal { node_id: usize, edge_id: usize },
+}
+
+/// Result of moving along the route
+#[derive(Clone, Debug, PartialEq)]
+pub struct Advance {
+    pub pos: GraphPos,
+    /// Distance actually moved, with the sign of the requested distance
+    pub distance: f32,
+    /// Events in the order they happened
+    pub events: Vec<MoveEvent>,
+}
+
+
+impl Node {
+    pub fn new(x: f32, y: f32) -> Self {
+        Self{ x, y }
+    }
+}
+
+
+impl Edge {
+    pub fn new(from_node_id: usize, to_node_id: usize, locations: &[Node]) -> Self {
+        Self::with_geometry(from_node_id, to_node_id, Geometry::Straight, locations)
+    }
+
+    pub fn with_geometry(from_node_id: usize, to_node_id: usize, geometry: Geometry, locations: &[Node]) -> Self {
+        let curve = Curve::new(locations[from_node_id], locations[to_node_id], geometry);
+        Self {
+            from_node_id,
+            to_node_id,
+            direction: Direction::OneWay,
+            speed_limit: None,
+            grade: 0.,
+            curve,
+        }
+    }
+
+    /// Allow traveling this edge in both directions
+    pub fn bidirectional(self) -> Self {
+        Self { direction: Direction::Both, ..self }
+    }
+
+    pub fn with_speed_limit(self, speed_limit: f32) -> Self {
+        Self { speed_limit: Some(speed_limit), ..self }
+    }
+
+    pub fn with_grade(self, grade: f32) -> Self {
+        Self { grade, ..self }
+    }
+
+    /// Grade in the direction of travel
+    pub fn grade(&self, reversed: bool) -> f32 {
+        if reversed { -self.grade } else { self.grade }
+    }
+
+    pub fn length(&self) -> f32 {
+        self.curve.length()
+    }
+
+    /// Recompute the curve (and length) after the nodes moved
+    pub fn update_curve(&mut self, locations: &[Node]) {
+        let geometry = self.curve.geometry();
+        self.curve = Curve::new(locations[self.from_node_id], locations[self.to_node_id], geometry);
+    }
+
+    pub fn curve(&self) -> &Curve {
+        &self.curve
+    }
+
+    /// Direction (reversed flag) of traveling this edge when starting at the given node
+    pub fn leaving(&self, node_id: usize) -> Option<bool> {
+        if self.from_node_id == node_id {
+            Some(false)
+        } else if self.to_node_id == node_id && self.direction == Direction::Both {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Direction (reversed flag) of traveling this edge when ending at the given node
+    pub fn arriving(&self, node_id: usize) -> Option<bool> {
+        if self.to_node_id == node_id {
+            Some(false)
+        } else if self.from_node_id == node_id && self.direction == Direction::Both {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    /// Node at the end of the edge for the given direction
+    pub fn end_node(&self, reversed: bool) -> usize {
+        if reversed { self.from_node_id } else { self.to_node_id }
+    }
+
+    /// Node at the beginning of the edge for the given direction
+    pub fn start_node(&self, reversed: bool) -> usize {
+        self.end_node(!reversed)
+    }
+}
+
+impl Junction {
+    pub fn is_allowed(&self, from_edge: usize, to_edge: usize) -> bool {
+        from_edge != to_edge && match self {
+            Junction::Free => true,
+            Junction::Allowed(turns) => turns.contains(&(from_edge, to_edge)),
+            Junction::Forbidden(turns) => !turns.contains(&(from_edge, to_edge)),
+        }
+    }
+}
+
+impl GraphPos {
+    pub fn init(edge_id: usize) -> Self {
+        Self::new(edge_id, 0.)
+    }
+
+    pub fn new(edge_id: usize, distance: f32) -> Self {
+        Self { edge_id, distance, reversed: false }
+    }
+
+    /// Position of the train traveling against edge direction
+    pub fn new_reversed(edge_id: usize, distance: f32) -> Self {
+        Self { edge_id, distance, reversed: true }
+    }
+
+    pub fn edge_id(&self) -> usize {
+        self.edge_id
+    }
+
+    /// Distance from the from node of the edge
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    pub fn is_reversed(&self) -> bool {
+        self.reversed
+    }
+
+    /// Distance traveled along the edge in the direction of travel
+    pub fn progress(&self, edge_length: f32) -> f32 {
+        if self.reversed { edge_length - self.distance } else { self.distance }
+    }
+
+    /// The same point, traveling the other way
+    pub fn flipped(&self) -> Self {
+        Self { reversed: !self.reversed, ..*self }
+    }
+
+    pub(crate) fn with_progress(edge_id: usize, reversed: bool, progress: f32, edge_length: f32) -> Self {
+        let distance = if reversed { edge_length - progress } else { progress };
+        Self { edge_id, distance, reversed }
+    }
+}
+
+
+impl Graph {
+    pub fn new(nodes: Vec<Node>, edges: Vec<Edge>) -> Self {
+        let junctions = vec![Junction::Free; nodes.len()];
+        Self { nodes, edges, junctions }
+    }
+
+    pub fn add_node(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.junctions.push(Junction::Free);
+        self.nodes.len() - 1
+    }
+
+    /// Move the node. Edges connected to it are updated
+    pub fn move_node(&mut self, node_id: usize, node: Node) {
+        self.nodes[node_id] = node;
+        for edge in self.edges.iter_mut().filter(|e| e.from_node_id == node_id || e.to_node_id == node_id) {
+            edge.update_curve(&self.nodes);
+        }
+    }
+
+    /// Remove the node and all its edges. Ids of the following nodes (and edges) are shifted
+    pub fn remove_node(&mut self, node_id: usize) {
+        while let Some(edge_id) = self.edges.iter()
+            .position(|e| e.from_node_id == node_id || e.to_node_id == node_id) {
+            self.remove_edge(edge_id);
+        }
+        self.nodes.remove(node_id);
+        self.junctions.remove(node_id);
+        for edge in &mut self.edges {
+            if edge.from_node_id > node_id {
+                edge.from_node_id -= 1;
+            }
+            if edge.to_node_id > node_id {
+                edge.to_node_id -= 1;
+            }
+        }
+    }
+
+    pub fn add_edge(&mut self, edge: Edge) -> usize {
+        self.edges.push(edge);
+        self.edges.len() - 1
+    }
+
+    /// Remove the edge. Ids of the following edges are shifted, junction rules are updated
+    pub fn remove_edge(&mut self, edge_id: usize) {
+        self.edges.remove(edge_id);
+        let shift = |id: usize| if id > edge_id { id - 1 } else { id };
+        for junction in &mut self.junctions {
+            if let Junction::Allowed(turns) | Junction::Forbidden(turns) = junction {
+                turns.retain(|&(a, b)| a != edge_id && b != edge_id);
+                for turn in turns.iter_mut() {
+                    *turn = (shift(turn.0), shift(turn.1));
+                }
+            }
+        }
+    }
+
+    /// Set rules for passing through the node
+    pub fn set_junction(&mut self, node_id: usize, junction: Junction) {
+        self.junctions[node_id] = junction;
+    }
+
+    /// Check if train can go from one edge to another at the given node.
+    /// Returns direction (reversed flag) on the new edge.
+    pub fn turn(&self, node_id: usize, from_edge: usize, to_edge: usize) -> Option<bool> {
+        let allowed = self.junctions.get(node_id)
+            .is_none_or(|j| j.is_allowed(from_edge, to_edge));
+        if allowed && self.edges[from_edge].arriving(node_id).is_some() {
+            self.edges[to_edge].leaving(node_id)
+        } else {
+            None
+        }
+    }
+
+    /// Point on the track and heading in the direction of travel
+    pub fn pos_to_location(&self, graph_pos: &GraphPos) -> Location {
+        let location = self.edges[graph_pos.edge_id].curve.at(graph_pos.distance);
+        if graph_pos.reversed { location.reversed() } else { location }
+    }
+
+    // route is a list of edges. Negative distance moves train backward
+    pub fn update_pos(&self, pos: &GraphPos, route: &[usize], distance: f32) -> GraphPos {
+        self.advance(pos, route, distance, RouteEnd::Stop).pos
+    }
+
+    /// Move along the route through any number of edges. Negative distance moves train backward.
+    /// At the end of the route the train stops or turns back.
+    pub fn advance(&self, pos: &GraphPos, route: &[usize], distance: f32, at_end: RouteEnd) -> Advance {
+        let forward = distance >= 0.;
+        let mut route = route.to_vec();
+        let mut pos = *pos;
+        let mut remaining = distance.abs();
+        let mut events = vec![];
+        // Edges passed without moving. Stops the loop of zero length edges
+        let mut idle = 0;
+        // Turned back without moving since. Stops turning back and forth on zero length edge
+        let mut turned = false;
+        loop {
+            let length = self.edges[pos.edge_id].length();
+            let progress = pos.progress(length);
+            let room = if forward { length - progress } else { progress };
+            if remaining <= room {
+                let progress = if forward { progress + remaining } else { progress - remaining };
+                pos = GraphPos::with_progress(pos.edge_id, pos.reversed, progress, length);
+                remaining = 0.;
+                break;
+            }
+            remaining -= room;
+            if room > 0. {
+                idle = 0;
+                turned = false;
+            } else {
+                idle += 1;
+            }
+
+            let end = if forward { length } else { 0. };
+            pos = GraphPos::with_progress(pos.edge_id, pos.reversed, end, length);
+            let edge = &self.edges[pos.edge_id];
+            let node_id = if forward { edge.end_node(pos.reversed) } else { edge.start_node(pos.reversed) };
+            let next = if forward { self.next_edge(&pos, &route) } else { self.prev_edge(&pos, &route) };
+            match next {
+                Some((edge_id, reversed)) if idle <= self.edges.len() => {
+                    events.push(MoveEvent::PassedNode { node_id, from_edge: pos.edge_id, to_edge: edge_id });
+                    let length = self.edges[edge_id].length();
+                    pos = GraphPos::with_progress(edge_id, reversed, if forward { 0. } else { length }, length);
+                }
+                None if at_end == RouteEnd::Reverse && edge.direction == Direction::Both && !turned => {
+                    turned = true;
+                    events.push(MoveEvent::Reversal { node_id, edge_id: pos.edge_id });
+                    pos = pos.flipped();
+                    route.reverse();
+                }
+                _ => {
+                    events.push(MoveEvent::EndOfRoute { node_id });
+                    break;
+                }
+            }
+        }
+        let moved = distance.abs() - remaining;
+        Advance { pos, distance: if forward { moved } else { -moved }, events }
+    }
+
+    /// Edge (and direction) which follows the current edge on the route.
+    /// Routes are followed in order. If the last edge connects to the first one then route is a loop.
+    /// Train which is not on the route yet joins it on the first connected edge.
+    pub fn next_edge(&self, pos: &GraphPos, route: &[usize]) -> Option<(usize, bool)> {
+        let node_id = self.edges[pos.edge_id].end_node(pos.reversed);
+        let turn = |next: &usize| self.turn(node_id, pos.edge_id, *next).map(|r| (*next, r));
+        match route.iter().position(|&e| e == pos.edge_id) {
+            Some(idx) => route.get(idx + 1).or(route.first()).and_then(turn),
+            None => route.iter().find_map(turn),
+        }
+    }
+
+    /// Edge (and direction) which precedes the current edge on the route. Used when moving backward
+    pub fn prev_edge(&self, pos: &GraphPos, route: &[usize]) -> Option<(usize, bool)> {
+        let node_id = self.edges[pos.edge_id].start_node(pos.reversed);
+        let turn = |prev: &usize| {
+            let reversed = self.edges[*prev].arriving(node_id)?;
+            self.turn(node_id, *prev, pos.edge_id).map(|_| (*prev, reversed))
+        };
+        match route.iter().position(|&e| e == pos.edge_id) {
+            Some(idx) => idx.checked_sub(1).and_then(|i| route.get(i)).or(route.last()).and_then(turn),
+            None => route.iter().find_map(turn),
+        }
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    // Triangle network from the trans example:
+    //
+    //  0 --e0--> 1
+    //  ^       / |
+    //  e2   e1  e3
+    //  |  /      |
+    //  3         2
+    fn triangle() -> Graph {
+        let nodes = vec![
+            Node::new(100., 100.),
+            Node::new(700., 100.),
+            Node::new(700., 500.),
+            Node::new(100., 500.),
+        ];
+        let edges = vec![
+            Edge::new(0, 1, &nodes),
+            Edge::new(1, 3, &nodes),
+            Edge::new(3, 0, &nodes),
+            Edge::new(1, 2, &nodes).bidirectional(),
+        ];
+        Graph::new(nodes, edges)
+    }
+
+    #[test]
+    fn test_loop() {
+        let graph = triangle();
+        let route = vec![0, 1, 2];
+        let pos = graph.update_pos(&GraphPos::new(2, 390.), &route, 20.);
+        assert_eq!(pos, GraphPos::new(0, 10.));
+    }
+
+    #[test]
+    fn test_one_way() {
+        let graph = triangle();
+        // Edge 1 can't be traveled from node 3 to node 1
+        let pos = graph.update_pos(&GraphPos::new(2, 390.), &[2, 1], 20.);
+        assert_eq!(pos, GraphPos::new(2, 400.));
+    }
+
+    #[test]
+    fn test_bidirectional() {
+        let graph = triangle();
+        // Back from the spur to the triangle
+        let pos = GraphPos::new_reversed(3, 10.);
+        let pos = graph.update_pos(&pos, &[3, 1], 20.);
+        assert_eq!(pos.edge_id(), 1);
+        assert!(!pos.is_reversed());
+        assert!((pos.distance() - 10.).abs() < 1e-3);
+
+        let pos = graph.update_pos(&GraphPos::new(0, 590.), &[0, 3], 20.);
+        assert_eq!(pos, GraphPos::new(3, 10.));
+    }
+
+    #[test]
+    fn test_editing() {
+        let mut graph = triangle();
+        graph.set_junction(1, Junction::Allowed(vec![(0, 1), (0, 3)]));
+        let node_id = graph.add_node(Node::new(700., 700.));
+        graph.add_edge(Edge::new(2, node_id, &graph.nodes));
+
+        graph.move_node(2, Node::new(700., 400.));
+        assert_eq!(graph.edges[3].length(), 300.);
+        assert_eq!(graph.edges[4].length(), 300.);
+
+        // Removing node 0 removes edges 0 and 2
+        graph.remove_node(0);
+        assert_eq!(graph.nodes.len(), 4);
+        assert_eq!(graph.edges.len(), 3);
+        assert_eq!((graph.edges[0].from_node_id, graph.edges[0].to_node_id), (0, 2));
+        assert_eq!((graph.edges[2].from_node_id, graph.edges[2].to_node_id), (1, 3));
+        assert_eq!(graph.junctions[0], Junction::Allowed(vec![]));
+    }
+
+    #[test]
+    fn test_location() {
+        let mut graph = triangle();
+        let center = Node::new(700., 300.);
+        graph.edges[3] = Edge::with_geometry(1, 2, Geometry::Arc { center, clockwise: false }, &graph.nodes);
+        assert!((graph.edges[3].length() - 200. * PI).abs() < 0.1);
+
+        // Half way on the spur is the left most point of the arc, heading down
+        let loc = graph.pos_to_location(&GraphPos::new(3, 100. * PI));
+        assert!((loc.point.x - 500.).abs() < 0.01 && (loc.point.y - 300.).abs() < 0.01);
+        assert!((loc.heading - PI / 2.).abs() < 1e-3);
+        let loc = graph.pos_to_location(&GraphPos::new_reversed(3, 100. * PI));
+        assert!((loc.heading + PI / 2.).abs() < 1e-3);
+
+        let loc = graph.pos_to_location(&GraphPos::new(0, 100.));
+        assert_eq!(loc.point, Node::new(200., 100.));
+        assert_eq!(loc.heading, 0.);
+    }
+
+    #[test]
+    fn test_junction_rules() {
+        let mut graph = triangle();
+        graph.set_junction(1, Junction::Allowed(vec![(0, 1), (3, 1)]));
+        assert_eq!(graph.turn(1, 0, 1), Some(false));
+        assert_eq!(graph.turn(1, 0, 3), None);
+        assert_eq!(graph.turn(1, 3, 1), Some(false));
+        // Never back onto the same edge
+        assert_eq!(graph.turn(1, 3, 3), None);
+
+        let pos = graph.update_pos(&GraphPos::new(0, 590.), &[0, 3], 20.);
+        assert_eq!(pos, GraphPos::new(0, 600.));
+        // Moving backward also respects the rules
+        let pos = graph.update_pos(&GraphPos::new(3, 5.), &[0, 3], -10.);
+        assert_eq!(pos, GraphPos::new(3, 0.));
+        let pos = graph.update_pos(&GraphPos::new(1, 5.), &[0, 1], -10.);
+        assert_eq!(pos, GraphPos::new(0, 595.));
+    }
+
+    //  0 -e0-> 1 -e1-> 2 -e2-> 3 <-e3-> 4
+    // Short edges: the train passes several of them in one move
+    fn line() -> Graph {
+        let nodes = (0..5).map(|i| Node::new(10. * i as f32, 0.)).collect::<Vec<_>>();
+        let edges = vec![
+            Edge::new(0, 1, &nodes),
+            Edge::new(1, 2, &nodes),
+            Edge::new(2, 3, &nodes),
+            Edge::new(3, 4, &nodes).bidirectional(),
+        ];
+        Graph::new(nodes, edges)
+    }
+
+    #[test]
+    fn test_advance() {
+        let graph = line();
+        let route = [0, 1, 2, 3];
+        let advance = graph.advance(&GraphPos::new(0, 5.), &route, 30., RouteEnd::Stop);
+        assert_eq!(advance.pos, GraphPos::new(3, 5.));
+        assert_eq!(advance.distance, 30.);
+        assert_eq!(advance.events, vec![
+            MoveEvent::PassedNode { node_id: 1, from_edge: 0, to_edge: 1 },
+            MoveEvent::PassedNode { node_id: 2, from_edge: 1, to_edge: 2 },
+            MoveEvent::PassedNode { node_id: 3, from_edge: 2, to_edge: 3 },
+        ]);
+        // The old single step API used to stop past the end of the second edge
+        assert_eq!(graph.update_pos(&GraphPos::new(0, 5.), &route, 30.), GraphPos::new(3, 5.));
+
+        let advance = graph.advance(&GraphPos::new(1, 5.), &route, 100., RouteEnd::Stop);
+        assert_eq!(advance.pos, GraphPos::new(3, 10.));
+        assert_eq!(advance.distance, 25.);
+        assert_eq!(advance.events.last(), Some(&MoveEvent::EndOfRoute { node_id: 4 }));
+
+        let advance = graph.advance(&GraphPos::new(2, 5.), &route, -100., RouteEnd::Stop);
+        assert_eq!(advance.pos, GraphPos::new(0, 0.));
+        assert_eq!(advance.distance, -25.);
+        assert_eq!(advance.events.last(), Some(&MoveEvent::EndOfRoute { node_id: 0 }));
+    }
+
+    #[test]
+    fn test_advance_reversal() {
+        let graph = line();
+        // Turns back at the end of the spur. One way edge 2 can't be traveled back, so the train
+        // shuttles on the spur
+        let advance = graph.advance(&GraphPos::new(2, 5.), &[2, 3], 40., RouteEnd::Reverse);
+        assert_eq!(advance.events, vec![
+            MoveEvent::PassedNode { node_id: 3, from_edge: 2, to_edge: 3 },
+            MoveEvent::Reversal { node_id: 4, edge_id: 3 },
+            MoveEvent::Reversal { node_id: 3, edge_id: 3 },
+            MoveEvent::Reversal { node_id: 4, edge_id: 3 },
+        ]);
+        assert_eq!(advance.pos, GraphPos::new_reversed(3, 5.));
+        assert_eq!(advance.distance, 40.);
+        assert!((graph.pos_to_location(&advance.pos).heading.abs() - PI).abs() < 1e-5);
+
+        // Already at the end of the spur (after the stop)
+        let length = graph.edges[3].length();
+        let advance = graph.advance(&GraphPos::new(3, length), &[2, 3], 4., RouteEnd::Reverse);
+        assert_eq!(advance.events, vec![MoveEvent::Reversal { node_id: 4, edge_id: 3 }]);
+        assert_eq!(advance.pos, GraphPos::new_reversed(3, length - 4.));
+        assert_eq!(advance.distance, 4.);
+
+        // Zero length spur turns back once and stops
+        let mut graph = graph;
+        let end = graph.add_node(graph.nodes[4]);
+        let stub = graph.add_edge(Edge::new(4, end, &graph.nodes).bidirectional());
+        let advance = graph.advance(&GraphPos::new(stub, 0.), &[stub], 4., RouteEnd::Reverse);
+        assert_eq!(advance.distance, 0.);
+        assert!(matches!(advance.events.last(), Some(MoveEvent::EndOfRoute { .. })));
+
+        // Without the way back it just stops
+        let graph = line();
+        let advance = graph.advance(&GraphPos::new(1, 5.), &[1, 2], 40., RouteEnd::Reverse);
+        assert_eq!(advance.events.last(), Some(&MoveEvent::EndOfRoute { node_id: 3 }));
+        assert_eq!(advance.distance, 15.);
+    }
+
+    // Distance along the route from the start of its first edge
+    fn route_distance(graph: &Graph, route: &[usize], pos: &GraphPos) -> f32 {
+        let idx = route.iter().position(|&e| e == pos.edge_id()).unwrap();
+        let before: f32 = route[..idx].iter().map(|&e| graph.edges[e].length()).sum();
+        before + pos.progress(graph.edges[pos.edge_id()].length())
+    }
+
+    #[test]
+    fn test_advance_conserves_distance_on_loop() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut graph = triangle();
+        // Mix of geometries
+        graph.edges[1] = Edge::with_geometry(1, 3, Geometry::Bezier {
+            control1: Node::new(400., 100.), control2: Node::new(400., 500.),
+        }, &graph.nodes);
+        let route = [0, 1, 2];
+        let total: f32 = route.iter().map(|&e| graph.edges[e].length()).sum();
+        for _ in 0..1000 {
+            let edge_id = route[rng.gen_range(0..route.len())];
+            let pos = GraphPos::new(edge_id, rng.gen_range(0. ..graph.edges[edge_id].length()));
+            // Up to 3 times round the loop in any direction
+            let distance = rng.gen_range(-3. * total..3. * total);
+            let advance = graph.advance(&pos, &route, distance, RouteEnd::Stop);
+            assert_eq!(advance.distance, distance);
+            assert!(!advance.events.iter().any(|e| matches!(e, MoveEvent::EndOfRoute { .. })));
+            let expected = (route_distance(&graph, &route, &pos) + distance).rem_euclid(total);
+            let actual = route_distance(&graph, &route, &advance.pos);
+            let error = (expected - actual).abs();
+            assert!(error.min(total - error) < 0.1, "{:?} + {} -> {:?}", pos, distance, advance.pos);
+        }
+    }
+
+    #[test]
+    fn test_advance_conserves_distance_with_reversals() {
+        let mut rng = StdRng::seed_from_u64(11);
+        let nodes = (0..4).map(|i| Node::new(30. * i as f32, 0.)).collect::<Vec<_>>();
+        let edges = (0..3).map(|i| Edge::new(i, i + 1, &nodes).bidirectional()).collect();
+        let graph = Graph::new(nodes, edges);
+        let length = 90.;
+        let mut route = vec![0, 1, 2];
+        let mut pos = GraphPos::new(0, 0.);
+        // Train shuttles between the ends: unfolded path is the triangle wave
+        let mut traveled = 0.;
+        for _ in 0..1000 {
+            let distance = rng.gen_range(0. ..500.);
+            let advance = graph.advance(&pos, &route, distance, RouteEnd::Reverse);
+            assert!((advance.distance - distance).abs() < 1e-3);
+            for event in &advance.events {
+                if let MoveEvent::Reversal { .. } = event {
+                    route.reverse();
+                }
+            }
+            traveled = (traveled + distance) % (2. * length);
+            pos = advance.pos;
+            let x = graph.pos_to_location(&pos).point.x;
+            let expected = if traveled < length { traveled } else { 2. * length - traveled };
+            assert!((x - expected).abs() < 0.1, "{} != {}", x, expected);
+        }
+    }
+}
@@ -0,0 +1,632 @@
+// Import of the GTFS static feed (https://gtfs.org/schedule/reference/).
+//
+// Feed is read from the directory or the zip file with these files: stops.txt, routes.txt,
+// trips.txt, stop_times.txt and optionally shapes.txt.
+//
+// Every stop becomes a node and a station (generic nodes and boarding areas are left out). Consecutive stops of the trips are connected by
+// one-way edges, shared by all trips between the same stops. If the trip has a shape, the
+// shape points between the stops become extra nodes, so the track follows the shape.
+// Station is at the end of the first edge arriving at the stop (or at the start of the leaving
+// edge if no edge arrives there). Stops without any edge (not served by the trips) get no station.
+// Every route becomes a line with the edges of its longest trip, every trip gets a timetable.
+// Stops without the time are timed by the distance between the timed stops around them.
+// Lat/lon are projected to meters, with x to the east and y to the south. Origin is the north-west
+// corner of the feed.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+
+use zip::ZipArchive;
+
+use crate::transnet::{Edge, Graph, GraphPos, Node, Station, Stop, Timetable};
+
+
+const EARTH_RADIUS: f64 = 6_371_000.;
+
+
+#[derive(Debug)]
+pub enum GtfsError {
+    Io(std::io::Error),
+    Zip(String),
+    MissingFile(String),
+    Malformed { file: String, line: usize, message: String },
+}
+
+/// Equirectangular projection of lat/lon to meters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection {
+    pub origin_lat: f64,
+    pub origin_lon: f64,
+    /// Length of the degree of longitude relative to the degree of latitude
+    lon_scale: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GtfsLine {
+    pub route_id: String,
+    pub name: String,
+    pub edges: Vec<usize>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GtfsTrip {
+    pub trip_id: String,
+    pub line: usize,
+    pub edges: Vec<usize>,
+    pub timetable: Timetable,
+}
+
+/// Transit network imported from the feed
+#[derive(Clone, Debug, PartialEq)]
+pub struct GtfsNetwork {
+    pub graph: Graph,
+    pub projection: Projection,
+    /// Stations of the served stops, in the order of stops.txt
+    pub stations: Vec<Station>,
+    pub lines: Vec<GtfsLine>,
+    pub trips: Vec<GtfsTrip>,
+}
+
+// Directory or zip file with the feed
+enum Feed {
+    Dir(std::path::PathBuf),
+    Zip(ZipArchive<fs::File>),
+}
+
+// Content of the CSV file
+struct Table {
+    file: String,
+    columns: HashMap<String, usize>,
+    /// Line number and fields
+    rows: Vec<(usize, Vec<String>)>,
+}
+
+// Stop of the trip read from stop_times.txt. Times can be left out
+struct StopTime {
+    sequence: u32,
+    stop: usize,
+    arrival: Option<f32>,
+    departure: Option<f32>,
+}
+
+
+impl fmt::Display for GtfsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GtfsError::Io(err) => write!(f, "Can't read GTFS feed: {}", err),
+            GtfsError::Zip(err) => write!(f, "Can't read GTFS zip: {}", err),
+            GtfsError::MissingFile(file) => write!(f, "GTFS feed has no {}", file),
+            GtfsError::Malformed { file, line, message } => write!(f, "{} line {}: {}", file, line, message),
+        }
+    }
+}
+
+impl std::error::Error for GtfsError {}
+
+impl From<std::io::Error> for GtfsError {
+    fn from(err: std::io::Error) -> Self {
+        GtfsError::Io(err)
+    }
+}
+
+impl From<zip::result::ZipError> for GtfsError {
+    fn from(err: zip::result::ZipError) -> Self {
+        match err {
+            zip::result::ZipError::Io(err) => GtfsError::Io(err),
+            err => GtfsError::Zip(err.to_string()),
+        }
+    }
+}
+
+
+impl Projection {
+    /// Projection with the origin at the given point
+    pub fn new(origin_lat: f64, origin_lon: f64) -> Self {
+        Self { origin_lat, origin_lon, lon_scale: origin_lat.to_radians().cos() }
+    }
+
+    pub fn project(&self, lat: f64, lon: f64) -> Node {
+        let x = EARTH_RADIUS * (lon - self.origin_lon).to_radians() * self.lon_scale;
+        let y = EARTH_RADIUS * (self.origin_lat - lat).to_radians();
+        Node::new(x as f32, y as f32)
+    }
+}
+
+impl GtfsNetwork {
+    /// Load the feed from the directory or the zip file
+    pub fn load(path: &Path) -> Result<Self, GtfsError> {
+        let mut feed = Feed::open(path)?;
+        let stops = Table::load(&mut feed, "stops.txt")?;
+        let routes = Table::load(&mut feed, "routes.txt")?;
+        let trips = Table::load(&mut feed, "trips.txt")?;
+        let stop_times = Table::load(&mut feed, "stop_times.txt")?;
+        let shapes = match Table::load(&mut feed, "shapes.txt") {
+            Err(GtfsError::MissingFile(_)) => None,
+            shapes => Some(shapes?),
+        };
+
+        // Stops
+        let mut stop_ids = HashMap::new();
+        let mut names = vec![];
+        let mut coords = vec![];
+        let (id_col, name_col) = (stops.column("stop_id")?, stops.column("stop_name")?);
+        let (lat_col, lon_col) = (stops.column("stop_lat")?, stops.column("stop_lon")?);
+        let type_col = stops.optional_column("location_type");
+        for (line, row) in &stops.rows {
+            // Generic nodes and boarding areas (inside of the stations) don't need the location
+            if type_col.is_some_and(|col| matches!(row[col].trim(), "3" | "4")) {
+                continue;
+            }
+            stop_ids.insert(row[id_col].clone(), names.len());
+            names.push(row[name_col].clone());
+            coords.push((stops.value::<f64>(*line, row, lat_col)?, stops.value::<f64>(*line, row, lon_col)?));
+        }
+
+        // Shapes
+        let mut shape_points: HashMap<String, Vec<(u32, f64, f64)>> = HashMap::new();
+        if let Some(shapes) = &shapes {
+            let id_col = shapes.column("shape_id")?;
+            let (lat_col, lon_col) = (shapes.column("shape_pt_lat")?, shapes.column("shape_pt_lon")?);
+            let seq_col = shapes.column("shape_pt_sequence")?;
+            for (line, row) in &shapes.rows {
+                let point = (
+                    shapes.value::<u32>(*line, row, seq_col)?,
+                    shapes.value::<f64>(*line, row, lat_col)?,
+                    shapes.value::<f64>(*line, row, lon_col)?,
+                );
+                shape_points.entry(row[id_col].clone()).or_default().push(point);
+            }
+        }
+        for points in shape_points.values_mut() {
+            points.sort_by_key(|p| p.0);
+        }
+
+        let all_coords = coords.iter().copied().chain(shape_points.values().flatten().map(|p| (p.1, p.2)));
+        let (north, west) = all_coords.fold((f64::MIN, f64::MAX), |(n, w), (lat, lon)| (n.max(lat), w.min(lon)));
+        let projection = if coords.is_empty() { Projection::new(0., 0.) } else { Projection::new(north, west) };
+        let nodes: Vec<Node> = coords.iter().map(|&(lat, lon)| projection.project(lat, lon)).collect();
+
+        // Routes
+        let mut route_ids = HashMap::new();
+        let mut lines = vec![];
+        let id_col = routes.column("route_id")?;
+        let short_col = routes.optional_column("route_short_name");
+        let long_col = routes.optional_column("route_long_name");
+        for (_, row) in &routes.rows {
+            let field = |col: Option<usize>| col.map(|c| row[c].clone()).filter(|s| !s.is_empty());
+            let name = field(short_col).or(field(long_col)).unwrap_or_else(|| row[id_col].clone());
+            route_ids.insert(row[id_col].clone(), lines.len());
+            lines.push(GtfsLine { route_id: row[id_col].clone(), name, edges: vec![] });
+        }
+
+        // Stop times of every trip
+        let mut trip_stops: HashMap<String, Vec<StopTime>> = HashMap::new();
+        let (trip_col, stop_col) = (stop_times.column("trip_id")?, stop_times.column("stop_id")?);
+        let (arrival_col, departure_col) = (stop_times.column("arrival_time")?, stop_times.column("departure_time")?);
+        let seq_col = stop_times.column("stop_sequence")?;
+        for (line, row) in &stop_times.rows {
+            let stop = *stop_ids.get(&row[stop_col])
+                .ok_or_else(|| stop_times.error(*line, format!("Unknown stop '{}'", row[stop_col])))?;
+            let time = |col: usize| match row[col].trim() {
+                "" => Ok(None),
+                text => parse_time(text)
+                    .map(Some)
+                    .ok_or_else(|| stop_times.error(*line, format!("Invalid time '{}'", row[col]))),
+            };
+            let (arrival, departure) = (time(arrival_col)?, time(departure_col)?);
+            let sequence = stop_times.value::<u32>(*line, row, seq_col)?;
+            trip_stops.entry(row[trip_col].clone()).or_default().push(StopTime { sequence, stop, arrival, departure });
+        }
+
+        // Trips
+        let mut graph = Graph::new(nodes, vec![]);
+        // Edges between two consecutive stops
+        let mut connections: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        let mut gtfs_trips = vec![];
+        let (route_col, trip_col) = (trips.column("route_id")?, trips.column("trip_id")?);
+        let shape_col = trips.optional_column("shape_id");
+        for (line, row) in &trips.rows {
+            let line_id = *route_ids.get(&row[route_col])
+                .ok_or_else(|| trips.error(*line, format!("Unknown route '{}'", row[route_col])))?;
+            let Some(mut stops) = trip_stops.remove(&row[trip_col]) else {
+                continue;
+            };
+            stops.sort_by_key(|s| s.sequence);
+            let Some(times) = stop_times_of(&stops, &graph.nodes) else {
+                continue;
+            };
+            let shape: Vec<Node> = shape_col
+                .and_then(|col| shape_points.get(&row[col]))
+                .map(|points| points.iter().map(|p| projection.project(p.1, p.2)).collect())
+                .unwrap_or_default();
+
+            let mut edges = vec![];
+            let mut shape_idx = 0;
+            for pair in stops.windows(2) {
+                let (from, to) = (pair[0].stop, pair[1].stop);
+                if from == to {
+                    continue;
+                }
+                // Shape points between the stops
+                let start = nearest(&shape, graph.nodes[from], shape_idx);
+                let end = nearest(&shape, graph.nodes[to], start);
+                shape_idx = end;
+                let connection = connections.entry((from, to)).or_insert_with(|| {
+                    let mut node_ids = vec![from];
+                    for &point in shape.get(start + 1..end).unwrap_or_default() {
+                        node_ids.push(graph.add_node(point));
+                    }
+                    node_ids.push(to);
+                    node_ids.windows(2)
+                        .map(|n| graph.add_edge(Edge::new(n[0], n[1], &graph.nodes)))
+                        .collect()
+                });
+                edges.extend_from_slice(connection);
+            }
+
+            let timetable = Timetable::new(stops.iter().zip(times)
+                .map(|(s, (arrival, departure))| {
+                    let stop = Stop::new(s.stop, departure);
+                    if departure > arrival { stop.with_dwell(departure - arrival) } else { stop }
+                })
+                .collect());
+            if edges.len() > lines[line_id].edges.len() {
+                lines[line_id].edges = edges.clone();
+            }
+            gtfs_trips.push(GtfsTrip { trip_id: row[trip_col].clone(), line: line_id, edges, timetable });
+        }
+
+        // Stops without edges have no position on the track
+        let mut stations = vec![];
+        let mut station_ids = vec![None; names.len()];
+        for (node_id, name) in names.iter().enumerate() {
+            let arriving = graph.edges.iter().position(|e| e.to_node_id == node_id);
+            let leaving = || graph.edges.iter().position(|e| e.from_node_id == node_id);
+            let pos = match arriving {
+                Some(edge_id) => GraphPos::new(edge_id, graph.edges[edge_id].length()),
+                None => match leaving() {
+                    Some(edge_id) => GraphPos::new(edge_id, 0.),
+                    None => continue,
+                },
+            };
+            station_ids[node_id] = Some(stations.len());
+            stations.push(Station::new(name, pos, 0.));
+        }
+        for trip in &mut gtfs_trips {
+            trip.timetable.stops.retain_mut(|stop| match station_ids[stop.station] {
+                Some(station) => {
+                    stop.station = station;
+                    true
+                }
+                None => false,
+            });
+        }
+
+        Ok(Self { graph, projection, stations, lines, trips: gtfs_trips })
+    }
+}
+
+impl Feed {
+    fn open(path: &Path) -> Result<Self, GtfsError> {
+        if path.is_dir() {
+            Ok(Feed::Dir(path.to_owned()))
+        } else if path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip")) {
+            Ok(Feed::Zip(ZipArchive::new(fs::File::open(path)?)?))
+        } else {
+            let message = format!("{} is neither a directory nor a zip file", path.display());
+            Err(GtfsError::Io(std::io::Error::new(std::io::ErrorKind::NotFound, message)))
+        }
+    }
+
+    // Content of the file, None if the feed doesn't have it
+    fn read(&mut self, file: &str) -> Result<Option<String>, GtfsError> {
+        match self {
+            Feed::Dir(dir) => {
+                let path = dir.join(file);
+                if path.exists() { Ok(Some(fs::read_to_string(path)?)) } else { Ok(None) }
+            }
+            Feed::Zip(archive) => {
+                // Files can be in the folder inside the archive
+                let name = archive.file_names()
+                    .find(|name| *name == file || name.ends_with(&format!("/{}", file)))
+                    .map(str::to_owned);
+                let Some(name) = name else {
+                    return Ok(None);
+                };
+                let mut text = String::new();
+                archive.by_name(&name)?.read_to_string(&mut text)?;
+                Ok(Some(text))
+            }
+        }
+    }
+}
+
+impl Table {
+    fn load(feed: &mut Feed, file: &str) -> Result<Self, GtfsError> {
+        match feed.read(file)? {
+            Some(text) => Self::parse(file, &text),
+            None => Err(GtfsError::MissingFile(file.to_owned())),
+        }
+    }
+
+    fn parse(file: &str, text: &str) -> Result<Self, GtfsError> {
+        let mut records = parse_csv(text.trim_start_matches('\u{feff}')).into_iter();
+        let Some((_, header)) = records.next() else {
+            return Err(GtfsError::Malformed { file: file.to_owned(), line: 1, message: "Missing header".to_owned() });
+        };
+        let columns: HashMap<String, usize> = header.into_iter()
+            .enumerate()
+            .map(|(idx, name)| (name.trim().to_owned(), idx))
+            .collect();
+        let mut table = Self { file: file.to_owned(), columns, rows: vec![] };
+        for (line, mut fields) in records {
+            if fields.iter().all(|f| f.is_empty()) {
+                continue;
+            }
+            if fields.len() > table.columns.len() {
+                return Err(table.error(line, format!("Expected {} fields, found {}", table.columns.len(), fields.len())));
+            }
+            // Trailing empty fields may be left out
+            fields.resize(table.columns.len(), String::new());
+            table.rows.push((line, fields));
+        }
+        Ok(table)
+    }
+
+    fn column(&self, name: &str) -> Result<usize, GtfsError> {
+        self.optional_column(name).ok_or_else(|| self.error(1, format!("Missing column '{}'", name)))
+    }
+
+    fn optional_column(&self, name: &str) -> Option<usize> {
+        self.columns.get(name).copied()
+    }
+
+    fn value<T: std::str::FromStr>(&self, line: usize, row: &[String], col: usize) -> Result<T, GtfsError> {
+        row[col].trim().parse().map_err(|_| self.error(line, format!("Invalid number '{}'", row[col])))
+    }
+
+    fn error(&self, line: usize, message: String) -> GtfsError {
+        GtfsError::Malformed { file: self.file.clone(), line, message }
+    }
+}
+
+
+// Records of the CSV file (RFC 4180) with the line where they start
+fn parse_csv(text: &str) -> Vec<(usize, Vec<String>)> {
+    let mut records = vec![];
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            '\r' if !quoted => (),
+            '\n' if !quoted => {
+                fields.push(std::mem::take(&mut field));
+                records.push((start, std::mem::take(&mut fields)));
+                line += 1;
+                start = line;
+            }
+            c => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if !field.is_empty() || !fields.is_empty() {
+        fields.push(field);
+        records.push((start, fields));
+    }
+    records
+}
+
+// Time as HH:MM:SS, which can be after midnight (25:10:00)
+fn parse_time(text: &str) -> Option<f32> {
+    let parts: Vec<u32> = text.trim().split(':').map(|p| p.parse().ok()).collect::<Option<_>>()?;
+    match parts[..] {
+        [h, m, s] if m < 60 && s < 60 => Some((h * 3600 + m * 60 + s) as f32),
+        _ => None,
+    }
+}
+
+// Arrival and departure at every stop of the trip. Stops without the time get it from the timed
+// stops before and after them, by the distance. None if no stop has the time
+fn stop_times_of(stops: &[StopTime], nodes: &[Node]) -> Option<Vec<(f32, f32)>> {
+    let known: Vec<Option<(f32, f32)>> = stops.iter()
+        .map(|s| match (s.arrival, s.departure) {
+            (Some(arrival), Some(departure)) => Some((arrival, departure)),
+            (Some(time), None) | (None, Some(time)) => Some((time, time)),
+            (None, None) => None,
+        })
+        .collect();
+    let timed: Vec<usize> = (0..stops.len()).filter(|&i| known[i].is_some()).collect();
+    if timed.is_empty() {
+        return None;
+    }
+    // Distance from the first stop along the straight lines between the stops
+    let mut along = vec![0.];
+    for pair in stops.windows(2) {
+        let (a, b) = (nodes[pair[0].stop], nodes[pair[1].stop]);
+        along.push(along.last().unwrap() + (b.x - a.x).hypot(b.y - a.y));
+    }
+
+    let times = (0..stops.len())
+        .map(|i| known[i].unwrap_or_else(|| {
+            // Timed stops before and after the stop
+            let after = timed.partition_point(|&t| t < i);
+            let time = match (after.checked_sub(1).map(|p| timed[p]), timed.get(after)) {
+                (Some(prev), Some(&next)) => {
+                    let (start, end) = (known[prev].unwrap().1, known[next].unwrap().0);
+                    let fraction = if along[next] > along[prev] {
+                        (along[i] - along[prev]) / (along[next] - along[prev])
+                    } else {
+                        (i - prev) as f32 / (next - prev) as f32
+                    };
+                    start + (end - start) * fraction
+                }
+                (Some(prev), None) => known[prev].unwrap().1,
+                (None, Some(&next)) => known[next].unwrap().0,
+                (None, None) => unreachable!("Trip has some timed stops"),
+            };
+            (time, time)
+        }))
+        .collect();
+    Some(times)
+}
+
+// Index of the shape point closest to the node, searching from the given index
+fn nearest(shape: &[Node], node: Node, from: usize) -> usize {
+    shape.iter().enumerate()
+        .skip(from)
+        .min_by(|a, b| {
+            let da = (a.1.x - node.x).hypot(a.1.y - node.y);
+            let db = (b.1.x - node.x).hypot(b.1.y - node.y);
+            da.total_cmp(&db)
+        })
+        .map_or(from, |(idx, _)| idx)
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Tram line 1 (with the shape) and bus line 2 which runs after midnight and shares
+    // the track between Beta and Gamma
+    const FEED: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/params/trans/gtfs");
+
+    #[test]
+    fn test_load_feed() {
+        let network = GtfsNetwork::load(Path::new(FEED)).unwrap();
+        // 5 stops and 2 points of the shape
+        assert_eq!(network.graph.nodes.len(), 7);
+        assert_eq!(network.graph.edges.len(), 6);
+        assert_eq!(network.stations[2].name, "Gamma, Central");
+        // Alpha is the westernmost stop, Delta the northernmost one
+        assert_eq!(network.graph.nodes[0].x, 0.);
+        assert_eq!(network.graph.nodes[3].y, 0.);
+        // 0.01 degree of longitude at 50 degrees
+        assert!((network.graph.nodes[1].x - 715.).abs() < 1.);
+
+        let names: Vec<&str> = network.lines.iter().map(|l| l.name.as_str()).collect();
+        assert_eq!(names, vec!["1", "2"]);
+        assert_eq!(network.lines[0].edges, vec![0, 1, 2, 3, 4]);
+        // Bus shares the edges between Beta and Gamma
+        assert_eq!(network.lines[1].edges, vec![1, 2, 5]);
+
+        // Stop times are sorted by the sequence
+        let stops = &network.trips[1].timetable.stops;
+        assert_eq!(stops.iter().map(|s| s.station).collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+        assert_eq!(stops[1], Stop::new(1, 8. * 3600. + 32. * 60. + 30.).with_dwell(30.));
+        assert_eq!(network.trips[2].timetable.stops[0].departure, 25. * 3600. + 600.);
+
+        assert_eq!(network.stations[0].pos, GraphPos::new(0, 0.));
+        assert_eq!(network.stations[1].pos, GraphPos::new(0, network.graph.edges[0].length()));
+    }
+
+    // Feed files zipped to the temporary file. Files can be replaced
+    fn zip_feed(name: &str, replaced: &[(&str, &str)]) -> std::path::PathBuf {
+        use std::io::Write;
+        let path = std::env::temp_dir().join(format!("gtfs_{}_{}.zip", name, std::process::id()));
+        let mut writer = zip::ZipWriter::new(fs::File::create(&path).unwrap());
+        for file in ["stops.txt", "routes.txt", "trips.txt", "stop_times.txt", "shapes.txt"] {
+            let text = fs::read_to_string(Path::new(FEED).join(file)).unwrap();
+            let text = replaced.iter().find(|r| r.0 == file).map_or(text, |r| r.1.to_owned());
+            writer.start_file(format!("feed/{}", file), zip::write::SimpleFileOptions::default()).unwrap();
+            writer.write_all(text.as_bytes()).unwrap();
+        }
+        writer.finish().unwrap();
+        path
+    }
+
+    #[test]
+    fn test_load_zip() {
+        let network = GtfsNetwork::load(Path::new(FEED)).unwrap();
+        let path = zip_feed("copy", &[]);
+        let zipped = GtfsNetwork::load(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(zipped.unwrap(), network);
+
+        // Parent station which no trip serves
+        let stops = fs::read_to_string(Path::new(FEED).join("stops.txt")).unwrap()
+            .replacen("\nB,", "\nP,Parent,50.0000,14.0100\nB,", 1);
+        let path = zip_feed("unserved", &[("stops.txt", &stops)]);
+        let unserved = GtfsNetwork::load(&path);
+        fs::remove_file(&path).unwrap();
+        let unserved = unserved.unwrap();
+        assert_eq!(unserved.graph.nodes.len(), network.graph.nodes.len() + 1);
+        assert_eq!(unserved.stations.len(), 5);
+        let names = |n: &GtfsNetwork| n.stations.iter().map(|s| s.name.clone()).collect::<Vec<_>>();
+        assert_eq!(names(&unserved), names(&network));
+        let timetables = |n: &GtfsNetwork| n.trips.iter().map(|t| t.timetable.clone()).collect::<Vec<_>>();
+        assert_eq!(timetables(&unserved), timetables(&network));
+    }
+
+    #[test]
+    fn test_optional_fields() {
+        let network = GtfsNetwork::load(Path::new(FEED)).unwrap();
+        let read = |file: &str| fs::read_to_string(Path::new(FEED).join(file)).unwrap();
+        // Boarding area without the location, Gamma is not the timepoint of T1
+        let stops = read("stops.txt").replacen(",stop_lon\n", ",stop_lon,location_type\n", 1)
+            + "BA,Platform 1,,,4\n";
+        let stop_times = read("stop_times.txt").replacen("T1,08:05:00,08:05:00,C,3", "T1,,,C,3", 1);
+        let path = zip_feed("optional", &[("stops.txt", &stops), ("stop_times.txt", &stop_times)]);
+        let loaded = GtfsNetwork::load(&path);
+        fs::remove_file(&path).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.graph, network.graph);
+        assert_eq!(loaded.stations, network.stations);
+
+        // Timed by the distance between Beta (leaves 08:02:30) and Delta (arrives 08:08:00)
+        let nodes = &network.graph.nodes;
+        let distance = |a: usize, b: usize| (nodes[b].x - nodes[a].x).hypot(nodes[b].y - nodes[a].y);
+        let fraction = distance(1, 2) / (distance(1, 2) + distance(2, 3));
+        let gamma = loaded.trips[0].timetable.stops[2];
+        assert_eq!(gamma.station, 2);
+        assert!((gamma.departure - (28950. + 330. * fraction)).abs() < 0.1, "{}", gamma.departure);
+        assert_eq!(gamma.dwell, None);
+        assert_eq!(loaded.trips[1], network.trips[1]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(matches!(GtfsNetwork::load(Path::new("feed.zip")), Err(GtfsError::Io(_))));
+        assert!(matches!(GtfsNetwork::load(Path::new("no/such/feed")), Err(GtfsError::Io(_))));
+        let error = GtfsNetwork::load(Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/params/trans"))).unwrap_err();
+        assert_eq!(error.to_string(), "GTFS feed has no stops.txt");
+
+        let table = Table::parse("stops.txt", "stop_id,stop_lat\nA,50.1\nB,north\n").unwrap();
+        let error = table.value::<f64>(3, &table.rows[1].1, table.column("stop_lat").unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "stops.txt line 3: Invalid number 'north'");
+        assert_eq!(table.column("stop_lon").unwrap_err().to_string(), "stops.txt line 1: Missing column 'stop_lon'");
+        assert!(Table::parse("trips.txt", "trip_id\nT1,T2\n").is_err());
+    }
+
+    #[test]
+    fn test_csv() {
+        let records = parse_csv("a,\"b, \"\"c\"\"\"\r\n1,\"multi\nline\"\n2,x");
+        assert_eq!(records, vec![
+            (1, vec!["a".to_owned(), "b, \"c\"".to_owned()]),
+            (2, vec!["1".to_owned(), "multi\nline".to_owned()]),
+            (4, vec!["2".to_owned(), "x".to_owned()]),
+        ]);
+        assert_eq!(parse_time("25:10:00"), Some(90600.));
+        assert_eq!(parse_time("8:61:00"), None);
+    }
+}
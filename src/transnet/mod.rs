@@ -0,0 +1,31 @@
+// Track network
+
+pub mod analysis;
+pub mod consist;
+pub mod dynamics;
+pub mod file;
+pub mod geometry;
+pub mod graph;
+pub mod gtfs;
+pub mod passengers;
+pub mod roads;
+pub mod routing;
+pub mod signals;
+pub mod spatial;
+pub mod tiles;
+pub mod timetable;
+
+pub use analysis::*;
+pub use consist::*;
+pub use dynamics::*;
+pub use file::*;
+pub use geometry::*;
+pub use graph::*;
+pub use gtfs::*;
+pub use passengers::*;
+pub use roads::*;
+pub use routing::*;
+pub use signals::*;
+pub use spatial::*;
+pub use tiles::*;
+pub use timetable::*;
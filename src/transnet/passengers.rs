@@ -0,0 +1,371 @@
+// Passengers: demand between stations, route choice with transfers, boarding and alighting.
+//
+// Every train with a timetable runs a line: the stations of its timetable in order. Trains with
+// the same stops run the same line. Passengers appear at the origin station at the demand rate and
+// choose the route with the fewest stops, where every change of the train counts as
+// `TRANSFER_PENALTY` stops. They board a train of the line they want while it dwells at the
+// station (if there is space) and alight at the end of the leg, where they wait for the next line
+// or leave the network.
+
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::transnet::{Service, Station};
+
+
+/// Transfer is as bad as traveling so many more stops
+pub const TRANSFER_PENALTY: usize = 3;
+
+
+/// Passengers traveling between two stations
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Demand {
+    pub from: usize,
+    pub to: usize,
+    /// Passengers per hour
+    pub rate: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub stops: Vec<usize>,
+    /// Trains go around, from the last stop to the first one
+    pub looped: bool,
+}
+
+/// Part of the trip on a single line
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Leg {
+    pub line: usize,
+    pub from: usize,
+    pub to: usize,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Passenger {
+    pub legs: Vec<Leg>,
+    /// Current leg
+    leg: usize,
+    /// Waiting for the train since
+    since: f32,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct LineStats {
+    pub boarded: usize,
+    pub alighted: usize,
+    /// Passengers who finished the trip on this line
+    pub delivered: usize,
+    /// Time waited by the boarded passengers
+    pub total_wait: f32,
+}
+
+/// Passengers on the whole network
+#[derive(Clone, Debug, PartialEq)]
+pub struct Ridership {
+    pub demand: Vec<Demand>,
+    pub lines: Vec<Line>,
+    /// Line run by every train (None for trains without timetable)
+    pub train_lines: Vec<Option<usize>>,
+    /// Capacity of every train
+    pub capacity: Vec<usize>,
+    pub stats: Vec<LineStats>,
+    /// Passengers who couldn't get to their destination
+    pub unroutable: usize,
+    /// Route of every demand
+    plans: Vec<Option<Vec<Leg>>>,
+    /// Fraction of the passenger not spawned yet, for every demand
+    pending: Vec<f32>,
+    waiting: Vec<Vec<Passenger>>,
+    onboard: Vec<Vec<Passenger>>,
+}
+
+
+impl Demand {
+    pub fn new(from: usize, to: usize, rate: f32) -> Self {
+        Self { from, to, rate }
+    }
+}
+
+impl Line {
+    /// Number of stops from one station to another. None if the line doesn't go there
+    pub fn ride(&self, from: usize, to: usize) -> Option<usize> {
+        let count = self.stops.len();
+        let starts = self.stops.iter().enumerate().filter(|(_, &s)| s == from).map(|(i, _)| i);
+        starts
+            .filter_map(|start| {
+                let ahead = if self.looped { count } else { count - start };
+                (1..ahead).find(|hops| self.stops[(start + hops) % count] == to)
+            })
+            .min()
+    }
+}
+
+impl LineStats {
+    pub fn mean_wait(&self) -> f32 {
+        if self.boarded > 0 { self.total_wait / self.boarded as f32 } else { 0. }
+    }
+}
+
+impl Ridership {
+    /// Lines are taken from the timetables of the trains
+    pub fn new(station_count: usize, demand: Vec<Demand>, services: &[Service], capacity: Vec<usize>) -> Self {
+        let mut lines: Vec<Line> = vec![];
+        let mut train_lines = vec![];
+        for service in services {
+            let timetable = &service.timetable;
+            if timetable.stops.is_empty() {
+                train_lines.push(None);
+                continue;
+            }
+            let line = Line {
+                stops: timetable.stops.iter().map(|s| s.station).collect(),
+                looped: timetable.period.is_some(),
+            };
+            let line_id = lines.iter().position(|l| *l == line).unwrap_or_else(|| {
+                lines.push(line);
+                lines.len() - 1
+            });
+            train_lines.push(Some(line_id));
+        }
+
+        let mut ridership = Self {
+            pending: vec![0.; demand.len()],
+            plans: vec![],
+            demand,
+            stats: vec![LineStats::default(); lines.len()],
+            lines,
+            onboard: vec![vec![]; train_lines.len()],
+            train_lines,
+            capacity,
+            unroutable: 0,
+            waiting: vec![vec![]; station_count],
+        };
+        ridership.plans = ridership.demand.iter().map(|d| ridership.plan(d.from, d.to)).collect();
+        ridership
+    }
+
+    /// Route with the fewest stops and transfers (Dijkstra over the stations)
+    pub fn plan(&self, from: usize, to: usize) -> Option<Vec<Leg>> {
+        let count = self.waiting.len();
+        if from == to || from >= count || to >= count {
+            return None;
+        }
+        let mut costs = vec![usize::MAX; count];
+        let mut prev: Vec<Option<Leg>> = vec![None; count];
+        let mut done = vec![false; count];
+        costs[from] = 0;
+        while let Some(station) = (0..count).filter(|&s| !done[s] && costs[s] < usize::MAX).min_by_key(|&s| costs[s]) {
+            if station == to {
+                break;
+            }
+            done[station] = true;
+            for (line_id, line) in self.lines.iter().enumerate() {
+                for &next in &line.stops {
+                    let Some(hops) = line.ride(station, next) else {
+                        continue;
+                    };
+                    let cost = costs[station] + hops + TRANSFER_PENALTY;
+                    if cost < costs[next] {
+                        costs[next] = cost;
+                        prev[next] = Some(Leg { line: line_id, from: station, to: next });
+                    }
+                }
+            }
+        }
+
+        let mut legs = vec![prev[to]?];
+        while let Some(leg) = prev[legs.last().unwrap().from] {
+            legs.push(leg);
+        }
+        legs.reverse();
+        Some(legs)
+    }
+
+    /// Spawn new passengers, let them board and alight the trains which dwell at the stations
+    pub fn update(&mut self, services: &[Service], time: f32, dt: f32) {
+        for (idx, demand) in self.demand.iter().enumerate() {
+            self.pending[idx] += demand.rate * dt / 3600.;
+            while self.pending[idx] >= 1. {
+                self.pending[idx] -= 1.;
+                match &self.plans[idx] {
+                    Some(legs) => self.waiting[demand.from].push(Passenger { legs: legs.clone(), leg: 0, since: time }),
+                    None => self.unroutable += 1,
+                }
+            }
+        }
+
+        for (train, service) in services.iter().enumerate() {
+            let Some(line) = self.train_lines.get(train).copied().flatten() else {
+                continue;
+            };
+            let Some(record) = service.records.last().filter(|r| service.is_dwelling() && r.departure.is_none()) else {
+                continue;
+            };
+            let station = record.station;
+            let stats = &mut self.stats[line];
+
+            let (alighting, staying) = self.onboard[train].drain(..).partition(|p| p.legs[p.leg].to == station);
+            self.onboard[train] = staying;
+            for mut passenger in alighting {
+                stats.alighted += 1;
+                if passenger.leg + 1 == passenger.legs.len() {
+                    stats.delivered += 1;
+                } else {
+                    passenger.leg += 1;
+                    passenger.since = time;
+                    self.waiting[station].push(passenger);
+                }
+            }
+
+            let capacity = self.capacity.get(train).copied().unwrap_or(0);
+            let waiting = &mut self.waiting[station];
+            let mut idx = 0;
+            while idx < waiting.len() && self.onboard[train].len() < capacity {
+                let leg = waiting[idx].legs[waiting[idx].leg];
+                if leg.line == line && leg.from == station {
+                    let passenger = waiting.remove(idx);
+                    stats.boarded += 1;
+                    stats.total_wait += time - passenger.since;
+                    self.onboard[train].push(passenger);
+                } else {
+                    idx += 1;
+                }
+            }
+        }
+    }
+
+    pub fn waiting_at(&self, station: usize) -> usize {
+        self.waiting.get(station).map_or(0, |w| w.len())
+    }
+
+    pub fn onboard(&self, train: usize) -> usize {
+        self.onboard.get(train).map_or(0, |p| p.len())
+    }
+
+    /// Passengers on the trains of the line
+    pub fn line_load(&self, line: usize) -> usize {
+        self.train_lines.iter().enumerate()
+            .filter(|(_, l)| **l == Some(line))
+            .map(|(train, _)| self.onboard(train))
+            .sum()
+    }
+
+    /// Stations of the line, like "Harbour - Hill"
+    pub fn line_name(&self, line: usize, stations: &[Station]) -> String {
+        let names: Vec<&str> = self.lines[line].stops.iter().map(|&s| stations[s].name.as_str()).collect();
+        names.join(" - ")
+    }
+
+    pub fn delivered(&self) -> usize {
+        self.stats.iter().map(|s| s.delivered).sum()
+    }
+}
+
+impl fmt::Display for Ridership {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>4} {:>8} {:>8} {:>9} {:>6} {:>9}", "line", "boarded", "alighted", "delivered", "load", "mean wait")?;
+        for (line, stats) in self.stats.iter().enumerate() {
+            writeln!(f, "{:>4} {:>8} {:>8} {:>9} {:>6} {:>8.0}s",
+                line, stats.boarded, stats.alighted, stats.delivered, self.line_load(line), stats.mean_wait())?;
+        }
+        let waiting: usize = self.waiting.iter().map(|w| w.len()).sum();
+        write!(f, "waiting: {}, delivered: {}, unroutable: {}", waiting, self.delivered(), self.unroutable)
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Graph, GraphPos, Node, Stop, Timetable};
+
+    // Line 0 goes around A -> B -> C, line 1 shuttles between C and D
+    fn services() -> Vec<Service> {
+        vec![
+            Service::new(Timetable::new(vec![Stop::new(0, 0.), Stop::new(1, 0.), Stop::new(2, 0.)]).repeat(100.)),
+            Service::new(Timetable::new(vec![Stop::new(2, 0.), Stop::new(3, 0.)])),
+            Service::new(Timetable::default()),
+        ]
+    }
+
+    fn stations() -> Vec<Station> {
+        ["A", "B", "C", "D"].iter().enumerate()
+            .map(|(idx, name)| Station::new(name, GraphPos::new(idx, 50.), 10.))
+            .collect()
+    }
+
+    #[test]
+    fn test_lines() {
+        let ridership = Ridership::new(4, vec![], &services(), vec![10, 10, 10]);
+        assert_eq!(ridership.train_lines, vec![Some(0), Some(1), None]);
+        let line = &ridership.lines[0];
+        assert_eq!(line.ride(0, 2), Some(2));
+        // Around the loop
+        assert_eq!(line.ride(2, 1), Some(2));
+        assert_eq!(ridership.lines[1].ride(3, 2), None);
+        assert_eq!(ridership.line_name(1, &stations()), "C - D");
+    }
+
+    #[test]
+    fn test_plan_with_transfer() {
+        let ridership = Ridership::new(4, vec![], &services(), vec![10, 10, 10]);
+        assert_eq!(ridership.plan(1, 3), Some(vec![
+            Leg { line: 0, from: 1, to: 2 },
+            Leg { line: 1, from: 2, to: 3 },
+        ]));
+        assert_eq!(ridership.plan(3, 0), None);
+    }
+
+    #[test]
+    fn test_boarding() {
+        // Stations are in the middle of the edges of the straight line
+        let nodes: Vec<Node> = (0..5).map(|i| Node::new(100. * i as f32, 0.)).collect();
+        let graph = Graph::new(nodes.clone(), (0..4).map(|i| Edge::new(i, i + 1, &nodes)).collect());
+        let stations = stations();
+        let mut services = services();
+        let arrive = |service: &mut Service, station: usize, time: f32| {
+            service.update(&stations, &GraphPos::new(station, 50.), time);
+        };
+        let depart = |service: &mut Service, station: usize, time: f32| {
+            service.max_distance(&graph, &stations, &GraphPos::new(station, 50.), &[0, 1, 2, 3], time);
+        };
+
+        // 1 passenger every 10 seconds from A to D. Nobody can get from D to A
+        let demand = vec![Demand::new(0, 3, 360.), Demand::new(3, 0, 360.)];
+        let mut ridership = Ridership::new(4, demand, &services, vec![2, 10]);
+        ridership.update(&services, 0., 30.);
+        assert_eq!(ridership.waiting_at(0), 3);
+        assert_eq!(ridership.unroutable, 3);
+
+        // Train at A takes only 2 passengers
+        arrive(&mut services[0], 0, 30.);
+        ridership.update(&services, 30., 0.);
+        assert_eq!((ridership.onboard(0), ridership.waiting_at(0)), (2, 1));
+        assert_eq!(ridership.stats[0].mean_wait(), 30.);
+
+        // They get off at C and wait for the shuttle
+        for (station, time) in [(1, 40.), (2, 60.)] {
+            depart(&mut services[0], station - 1, time);
+            arrive(&mut services[0], station, time + 10.);
+            ridership.update(&services, time + 10., 0.);
+        }
+        assert_eq!(ridership.onboard(0), 0);
+        assert_eq!(ridership.stats[0].alighted, 2);
+        assert_eq!(ridership.waiting_at(2), 2);
+
+        arrive(&mut services[1], 2, 80.);
+        ridership.update(&services, 80., 0.);
+        assert_eq!(ridership.line_load(1), 2);
+        depart(&mut services[1], 2, 90.);
+        arrive(&mut services[1], 3, 100.);
+        ridership.update(&services, 100., 0.);
+        assert_eq!(ridership.stats[1].delivered, 2);
+        assert_eq!(ridership.stats[1].mean_wait(), 10.);
+        assert_eq!(ridership.delivered(), 2);
+    }
+}
@@ -0,0 +1,712 @@
+// Road traffic on the graph: lanes, car following and intersections.
+//
+// Roads are one-way edges, a two-way road is a pair of edges. Every edge has some lanes and cars
+// keep their lane (a car entering the next edge takes the lane with the same number, or the last
+// one if there are fewer lanes). Speed of every car follows the Intelligent Driver Model: it
+// accelerates towards the desired speed and keeps a safe gap to the car ahead. A car which has to
+// stop at the intersection sees a stopped car at the stop line.
+// Intersections are either free, controlled by priority (cars coming from minor roads wait for a
+// gap in the traffic on major roads) or by traffic lights cycling through the phases.
+// Cars come from the flows and leave at the end of their route.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::transnet::{Curve, Edge, Geometry, Graph, GraphPos, Location, Node};
+
+
+pub const CAR_LENGTH: f32 = 5.;
+pub const LANE_WIDTH: f32 = 3.5;
+/// Time at the end of the green phase when the cars which can stop should stop
+pub const AMBER_TIME: f32 = 3.;
+/// Gap in the major road traffic (in seconds) accepted by the car on the minor road
+pub const CRITICAL_GAP: f32 = 4.;
+// Physical limit of braking
+const MAX_DECELERATION: f32 = 9.;
+
+
+/// Intelligent Driver Model parameters
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Idm {
+    /// Speed on the empty road, if the edge doesn't have lower speed limit
+    pub desired_speed: f32,
+    /// Desired time to the car ahead
+    pub time_headway: f32,
+    /// Gap to the car ahead when stopped
+    pub min_gap: f32,
+    pub max_acceleration: f32,
+    pub comfortable_deceleration: f32,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Phase {
+    /// Approach edges with the green light
+    pub green: Vec<usize>,
+    pub duration: f32,
+}
+
+/// Rules at the node
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Control {
+    /// Cars don't stop
+    #[default]
+    Free,
+    /// Cars coming from other than major edges give way
+    Priority { major: Vec<usize> },
+    Lights { phases: Vec<Phase> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Light {
+    Green,
+    Amber,
+    Red,
+}
+
+/// Cars going along the route
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Flow {
+    /// Connected edges
+    pub route: Vec<usize>,
+    /// Cars per hour
+    pub rate: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vehicle {
+    pub pos: GraphPos,
+    pub lane: usize,
+    pub speed: f32,
+    route: Vec<usize>,
+    /// Index of the current edge in the route
+    leg: usize,
+    /// Time when the car entered the network
+    departure: f32,
+}
+
+/// Traffic on the edge since the start
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RoadStats {
+    /// Cars which left the edge
+    pub passed: usize,
+    /// Sum of the time spent on the edge by all cars
+    pub vehicle_time: f32,
+    /// Sum of the distance traveled on the edge by all cars
+    pub distance: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Roads {
+    pub graph: Graph,
+    /// Lanes of every edge
+    pub lanes: Vec<usize>,
+    /// Rules of every node
+    pub controls: Vec<Control>,
+    pub idm: Idm,
+    pub vehicles: Vec<Vehicle>,
+    flows: Vec<Flow>,
+    /// Cars of every flow waiting to enter (fractional)
+    pending: Vec<f32>,
+    stats: Vec<RoadStats>,
+    time: f32,
+    delivered: usize,
+    travel_time: f32,
+}
+
+/// Road network in the network file
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct RoadsDef {
+    #[serde(default)]
+    pub nodes: Vec<Node>,
+    #[serde(default)]
+    pub edges: Vec<RoadDef>,
+    #[serde(default)]
+    pub intersections: Vec<IntersectionDef>,
+    #[serde(default)]
+    pub flows: Vec<Flow>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct RoadDef {
+    pub from: usize,
+    pub to: usize,
+    #[serde(default)]
+    pub geometry: Geometry,
+    #[serde(default = "default_lanes")]
+    pub lanes: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed_limit: Option<f32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct IntersectionDef {
+    pub node: usize,
+    pub control: Control,
+}
+
+
+fn default_lanes() -> usize { 1 }
+
+
+impl Default for Idm {
+    fn default() -> Self {
+        Self {
+            desired_speed: 14.,
+            time_headway: 1.5,
+            min_gap: 2.,
+            max_acceleration: 1.5,
+            comfortable_deceleration: 2.,
+        }
+    }
+}
+
+impl Idm {
+    /// Acceleration of the car. Obstacle is the gap to the car ahead and its speed
+    pub fn acceleration(&self, speed: f32, desired_speed: f32, obstacle: Option<(f32, f32)>) -> f32 {
+        let free = 1. - (speed / desired_speed).powi(4);
+        let interaction = obstacle.map_or(0., |(gap, other_speed)| {
+            let braking = (self.max_acceleration * self.comfortable_deceleration).sqrt();
+            let dynamic = speed * self.time_headway + speed * (speed - other_speed) / (2. * braking);
+            let desired_gap = self.min_gap + dynamic.max(0.);
+            (desired_gap / gap.max(0.01)).powi(2)
+        });
+        (self.max_acceleration * (free - interaction)).max(-MAX_DECELERATION)
+    }
+
+    /// Distance needed to stop with comfortable deceleration
+    pub fn stopping_distance(&self, speed: f32) -> f32 {
+        speed * speed / (2. * self.comfortable_deceleration)
+    }
+}
+
+impl RoadStats {
+    pub fn mean_speed(&self) -> Option<f32> {
+        (self.vehicle_time > 0.).then(|| self.distance / self.vehicle_time)
+    }
+}
+
+impl Vehicle {
+    pub fn route(&self) -> &[usize] {
+        &self.route
+    }
+}
+
+
+impl Roads {
+    pub fn new(graph: Graph, lanes: Vec<usize>, controls: Vec<Control>, flows: Vec<Flow>) -> Self {
+        let stats = vec![RoadStats::default(); graph.edges.len()];
+        let pending = vec![0.; flows.len()];
+        Self {
+            graph,
+            lanes,
+            controls,
+            idm: Idm::default(),
+            flows,
+            vehicles: vec![],
+            pending,
+            stats,
+            time: 0.,
+            delivered: 0,
+            travel_time: 0.,
+        }
+    }
+
+    pub fn flows(&self) -> &[Flow] {
+        &self.flows
+    }
+
+    /// Cars start coming from the flow. Returns its id
+    pub fn add_flow(&mut self, flow: Flow) -> usize {
+        self.flows.push(flow);
+        self.pending.push(0.);
+        self.flows.len() - 1
+    }
+
+    /// No more cars come, cars on the roads finish their routes
+    pub fn clear_flows(&mut self) {
+        self.flows.clear();
+        self.pending.clear();
+    }
+
+    /// Time since the start
+    pub fn elapsed(&self) -> f32 {
+        self.time
+    }
+
+    pub fn stats(&self, edge_id: usize) -> RoadStats {
+        self.stats[edge_id]
+    }
+
+    /// Cars which left the edge per hour
+    pub fn throughput(&self, edge_id: usize) -> f32 {
+        if self.time > 0. { self.stats[edge_id].passed as f32 * 3600. / self.time } else { 0. }
+    }
+
+    /// 0 when cars drive at the desired speed, 1 when they don't move
+    pub fn congestion(&self, edge_id: usize) -> f32 {
+        self.stats[edge_id].mean_speed()
+            .map_or(0., |speed| (1. - speed / self.desired_speed(edge_id)).clamp(0., 1.))
+    }
+
+    /// Cars on the edge which almost stopped
+    pub fn queue(&self, edge_id: usize) -> usize {
+        self.vehicles.iter().filter(|v| v.pos.edge_id() == edge_id && v.speed < 1.).count()
+    }
+
+    /// Cars which reached the end of their route
+    pub fn delivered(&self) -> usize {
+        self.delivered
+    }
+
+    pub fn mean_travel_time(&self) -> Option<f32> {
+        (self.delivered > 0).then(|| self.travel_time / self.delivered as f32)
+    }
+
+    /// Light for the cars arriving at the node on the edge. None if the node has no lights
+    pub fn light(&self, node_id: usize, edge_id: usize) -> Option<Light> {
+        let Some(Control::Lights { phases }) = self.controls.get(node_id) else {
+            return None;
+        };
+        let cycle: f32 = phases.iter().map(|p| p.duration).sum();
+        if cycle <= 0. {
+            return None;
+        }
+        let mut time = self.time % cycle;
+        for phase in phases {
+            if time < phase.duration {
+                return Some(if !phase.green.contains(&edge_id) {
+                    Light::Red
+                } else if phase.duration - time <= AMBER_TIME {
+                    Light::Amber
+                } else {
+                    Light::Green
+                });
+            }
+            time -= phase.duration;
+        }
+        Some(Light::Red)
+    }
+
+    /// Location of the car in the middle of its lane. Lanes are on the right side of the edge
+    pub fn location(&self, vehicle: &Vehicle) -> Location {
+        let location = self.graph.pos_to_location(&vehicle.pos);
+        let offset = (vehicle.lane as f32 + 0.5) * LANE_WIDTH;
+        let (sin, cos) = location.heading.sin_cos();
+        let point = Node::new(location.point.x - sin * offset, location.point.y + cos * offset);
+        Location { point, heading: location.heading }
+    }
+
+    /// Add the car at the start of the route. False if there is no room
+    pub fn spawn(&mut self, route: &[usize]) -> bool {
+        let Some(&edge_id) = route.first() else {
+            return false;
+        };
+        // Lane with the most room: distance to the last car in the lane
+        let room = |lane: usize| self.vehicles.iter()
+            .filter(|v| v.pos.edge_id() == edge_id && v.lane == lane)
+            .map(|v| (v.pos.distance() - CAR_LENGTH, v.speed))
+            .fold((f32::INFINITY, 0.), |a, b| if b.0 < a.0 { b } else { a });
+        let Some((lane, (gap, leader_speed))) = (0..self.lanes[edge_id].max(1))
+            .map(|lane| (lane, room(lane)))
+            .max_by(|a, b| a.1.0.total_cmp(&b.1.0)) else {
+            return false;
+        };
+        if gap < self.idm.min_gap {
+            return false;
+        }
+        let desired = self.desired_speed(edge_id);
+        let speed = if gap > self.idm.min_gap + desired * self.idm.time_headway { desired } else { leader_speed };
+        self.vehicles.push(Vehicle {
+            pos: GraphPos::init(edge_id),
+            lane,
+            speed,
+            route: route.to_vec(),
+            leg: 0,
+            departure: self.time,
+        });
+        true
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        self.time += dt;
+        for flow_id in 0..self.flows.len() {
+            self.pending[flow_id] += self.flows[flow_id].rate / 3600. * dt;
+            if self.pending[flow_id] >= 1. {
+                let route = self.flows[flow_id].route.clone();
+                if self.spawn(&route) {
+                    self.pending[flow_id] -= 1.;
+                }
+            }
+        }
+
+        // Accelerations are computed from the state before the move
+        let queues = self.lane_queues();
+        let moves: Vec<(f32, Option<f32>)> = (0..self.vehicles.len())
+            .map(|idx| self.acceleration(idx, &queues))
+            .collect();
+
+        let mut arrived = vec![];
+        for (idx, (acceleration, limit)) in moves.into_iter().enumerate() {
+            let vehicle = &mut self.vehicles[idx];
+            let edge_id = vehicle.pos.edge_id();
+            let mut speed = (vehicle.speed + acceleration * dt).max(0.);
+            let mut distance = (vehicle.speed + speed) / 2. * dt;
+            // Never run into the car ahead or through the stop line
+            if let Some(limit) = limit.filter(|&l| distance >= l) {
+                distance = limit.max(0.);
+                speed = 0.;
+            }
+            vehicle.speed = speed;
+            self.stats[edge_id].vehicle_time += dt;
+
+            // Move through as many edges as the distance covers, each gets its part of the distance
+            let mut edge_id = edge_id;
+            let mut start = vehicle.pos.distance();
+            let mut progress = start + distance;
+            loop {
+                let length = self.graph.edges[edge_id].length();
+                let stats = &mut self.stats[edge_id];
+                stats.distance += progress.min(length) - start;
+                if progress <= length {
+                    vehicle.pos = GraphPos::new(edge_id, progress);
+                    break;
+                }
+                stats.passed += 1;
+                let Some(&next) = vehicle.route.get(vehicle.leg + 1) else {
+                    arrived.push(idx);
+                    break;
+                };
+                vehicle.leg += 1;
+                vehicle.lane = vehicle.lane.min(self.lanes[next].max(1) - 1);
+                progress -= length;
+                start = 0.;
+                edge_id = next;
+            }
+        }
+        for idx in arrived.into_iter().rev() {
+            let vehicle = self.vehicles.remove(idx);
+            self.delivered += 1;
+            self.travel_time += self.time - vehicle.departure;
+        }
+    }
+
+    fn desired_speed(&self, edge_id: usize) -> f32 {
+        self.graph.edges[edge_id].speed_limit.map_or(self.idm.desired_speed, |limit| limit.min(self.idm.desired_speed))
+    }
+
+    // Cars in every lane, ordered by distance on the edge
+    fn lane_queues(&self) -> HashMap<(usize, usize), Vec<usize>> {
+        let mut queues: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (idx, vehicle) in self.vehicles.iter().enumerate() {
+            queues.entry((vehicle.pos.edge_id(), vehicle.lane)).or_default().push(idx);
+        }
+        for queue in queues.values_mut() {
+            queue.sort_by(|&a, &b| self.vehicles[a].pos.distance().total_cmp(&self.vehicles[b].pos.distance()));
+        }
+        queues
+    }
+
+    // Acceleration of the car and the distance it can't go past
+    fn acceleration(&self, idx: usize, queues: &HashMap<(usize, usize), Vec<usize>>) -> (f32, Option<f32>) {
+        let vehicle = &self.vehicles[idx];
+        let edge_id = vehicle.pos.edge_id();
+        let edge = &self.graph.edges[edge_id];
+        let queue = &queues[&(edge_id, vehicle.lane)];
+        let place = queue.iter().position(|&i| i == idx).unwrap_or(0);
+        let remaining = edge.length() - vehicle.pos.distance();
+
+        let obstacle = if let Some(&ahead) = queue.get(place + 1) {
+            let leader = &self.vehicles[ahead];
+            Some((leader.pos.distance() - CAR_LENGTH - vehicle.pos.distance(), leader.speed))
+        } else if let Some(&next) = vehicle.route.get(vehicle.leg + 1) {
+            if self.must_stop(edge.to_node_id, edge_id, remaining, vehicle.speed) {
+                Some((remaining, 0.))
+            } else {
+                let lane = vehicle.lane.min(self.lanes[next].max(1) - 1);
+                queues.get(&(next, lane))
+                    .and_then(|q| q.first())
+                    .map(|&i| &self.vehicles[i])
+                    .map(|leader| (remaining + leader.pos.distance() - CAR_LENGTH, leader.speed))
+            }
+        } else {
+            None
+        };
+        let acceleration = self.idm.acceleration(vehicle.speed, self.desired_speed(edge_id), obstacle);
+        (acceleration, obstacle.map(|(gap, _)| gap))
+    }
+
+    // Car arriving at the node on the edge can't enter the intersection
+    fn must_stop(&self, node_id: usize, edge_id: usize, remaining: f32, speed: f32) -> bool {
+        match self.controls.get(node_id) {
+            None | Some(Control::Free) => false,
+            Some(Control::Lights { .. }) => match self.light(node_id, edge_id) {
+                Some(Light::Red) => true,
+                // Stop if it's still possible
+                Some(Light::Amber) => self.idm.stopping_distance(speed) <= remaining,
+                _ => false,
+            },
+            Some(Control::Priority { major }) => {
+                if major.contains(&edge_id) {
+                    return false;
+                }
+                // Wait for the gap in the traffic on the major roads
+                self.vehicles.iter()
+                    .filter(|v| major.contains(&v.pos.edge_id()) && self.graph.edges[v.pos.edge_id()].to_node_id == node_id)
+                    .any(|v| {
+                        let distance = self.graph.edges[v.pos.edge_id()].length() - v.pos.distance();
+                        distance < CRITICAL_GAP * v.speed.max(1.)
+                    })
+            }
+        }
+    }
+}
+
+impl fmt::Display for Roads {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Road       Cars/h   Speed  Congestion  Queue")?;
+        for edge_id in 0..self.graph.edges.len() {
+            let speed = self.stats[edge_id].mean_speed()
+                .map_or("-".to_owned(), |s| format!("{:.1}", s));
+            writeln!(f, "{:<8} {:>8.0} {:>7} {:>10.0}% {:>6}",
+                edge_id, self.throughput(edge_id), speed, 100. * self.congestion(edge_id), self.queue(edge_id))?;
+        }
+        match self.mean_travel_time() {
+            Some(time) => write!(f, "{} cars arrived, mean travel time {:.1} s", self.delivered, time),
+            None => write!(f, "No cars arrived yet"),
+        }
+    }
+}
+
+
+impl RoadsDef {
+    pub fn to_roads(&self) -> Roads {
+        let edges = self.edges.iter()
+            .map(|def| {
+                let mut edge = Edge::with_geometry(def.from, def.to, def.geometry, &self.nodes);
+                edge.speed_limit = def.speed_limit;
+                edge
+            })
+            .collect();
+        let graph = Graph::new(self.nodes.clone(), edges);
+        let lanes = self.edges.iter().map(|def| def.lanes).collect();
+        let mut controls = vec![Control::Free; self.nodes.len()];
+        for def in &self.intersections {
+            controls[def.node] = def.control.clone();
+        }
+        Roads::new(graph, lanes, controls, self.flows.clone())
+    }
+
+    /// Every problem found in the road network
+    pub fn validate(&self) -> Vec<String> {
+        let mut issues = vec![];
+        let node_ok = |node: usize| node < self.nodes.len();
+        let edge_ok = |edge: usize| edge < self.edges.len();
+
+        for (edge_id, def) in self.edges.iter().enumerate() {
+            for node in [def.from, def.to].into_iter().filter(|&n| !node_ok(n)) {
+                issues.push(format!("Road {} refers to node {} which doesn't exist", edge_id, node));
+            }
+            if node_ok(def.from) && node_ok(def.to) {
+                let length = Curve::new(self.nodes[def.from], self.nodes[def.to], def.geometry).length();
+                if length.is_nan() || length <= 0. {
+                    issues.push(format!("Road {} has zero length", edge_id));
+                }
+            }
+            if def.lanes == 0 {
+                issues.push(format!("Road {} has no lanes", edge_id));
+            }
+        }
+
+        for def in &self.intersections {
+            if !node_ok(def.node) {
+                issues.push(format!("Intersection refers to node {} which doesn't exist", def.node));
+            }
+            let (edges, durations): (Vec<usize>, Vec<f32>) = match &def.control {
+                Control::Free => (vec![], vec![]),
+                Control::Priority { major } => (major.clone(), vec![]),
+                Control::Lights { phases } => (
+                    phases.iter().flat_map(|p| p.green.clone()).collect(),
+                    phases.iter().map(|p| p.duration).collect(),
+                ),
+            };
+            for edge in edges {
+                if !edge_ok(edge) {
+                    issues.push(format!("Intersection at node {} refers to road {} which doesn't exist", def.node, edge));
+                } else if self.edges[edge].to != def.node {
+                    issues.push(format!("Road {} doesn't lead to the intersection at node {}", edge, def.node));
+                }
+            }
+            if durations.iter().any(|d| d.is_nan() || *d <= 0.) {
+                issues.push(format!("Intersection at node {} has phase without duration", def.node));
+            }
+        }
+
+        for (flow_id, flow) in self.flows.iter().enumerate() {
+            if flow.route.is_empty() {
+                issues.push(format!("Flow {} has empty route", flow_id));
+            }
+            for edge in flow.route.iter().filter(|&&e| !edge_ok(e)) {
+                issues.push(format!("Flow {} refers to road {} which doesn't exist", flow_id, edge));
+            }
+            if flow.route.iter().all(|&e| edge_ok(e)) {
+                for pair in flow.route.windows(2).filter(|p| self.edges[p[0]].to != self.edges[p[1]].from) {
+                    issues.push(format!("Flow {}: road {} doesn't connect to road {}", flow_id, pair[0], pair[1]));
+                }
+            }
+            if flow.rate.is_nan() || flow.rate < 0. {
+                issues.push(format!("Flow {} has negative rate", flow_id));
+            }
+        }
+        issues
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //       3
+    //       |
+    //  1 -> 0 -> 2
+    //
+    // Straight road 1 -> 0 -> 2 (edges 0, 1) and the side road 3 -> 0 (edge 2)
+    fn crossing(control: Control) -> Roads {
+        let nodes = vec![Node::new(200., 0.), Node::new(0., 0.), Node::new(400., 0.), Node::new(200., -200.)];
+        let edges = vec![Edge::new(1, 0, &nodes), Edge::new(0, 2, &nodes), Edge::new(3, 0, &nodes)];
+        let graph = Graph::new(nodes, edges);
+        let controls = vec![control, Control::Free, Control::Free, Control::Free];
+        Roads::new(graph, vec![1, 2, 1], controls, vec![])
+    }
+
+    fn run(roads: &mut Roads, seconds: f32) {
+        for _ in 0..(seconds * 10.) as usize {
+            roads.update(0.1);
+        }
+    }
+
+    #[test]
+    fn test_idm() {
+        let idm = Idm::default();
+        assert!((idm.acceleration(0., 14., None) - idm.max_acceleration).abs() < 1e-6);
+        assert!(idm.acceleration(14., 14., None).abs() < 1e-6);
+        // Stopped car close ahead
+        assert!(idm.acceleration(10., 14., Some((10., 0.))) < -idm.comfortable_deceleration);
+        // Leader far away doesn't matter
+        let far = idm.acceleration(10., 14., Some((1000., 10.)));
+        assert!((far - idm.acceleration(10., 14., None)).abs() < 0.01);
+        assert_eq!(idm.acceleration(20., 14., Some((0., 0.))), -MAX_DECELERATION);
+    }
+
+    #[test]
+    fn test_following() {
+        let mut roads = crossing(Control::Free);
+        roads.add_flow(Flow { route: vec![0, 1], rate: 1200. });
+        for _ in 0..1200 {
+            roads.update(0.1);
+            // Cars never overlap
+            for a in &roads.vehicles {
+                for b in roads.vehicles.iter().filter(|b| b.pos.edge_id() == a.pos.edge_id() && b.lane == a.lane) {
+                    let gap = b.pos.distance() - a.pos.distance();
+                    assert!(gap <= 0. || gap >= CAR_LENGTH - 1e-3);
+                }
+            }
+        }
+        // 1 car every 3 seconds
+        assert!(roads.delivered() >= 25, "{}", roads.delivered());
+        assert!((900. ..1200.).contains(&roads.throughput(0)), "{}", roads.throughput(0));
+        assert!(roads.congestion(0) < 0.2);
+        assert!(roads.mean_travel_time().unwrap() > 400. / 14.);
+    }
+
+    #[test]
+    fn test_short_edges() {
+        // Four edges 10 long
+        let nodes: Vec<Node> = (0..5).map(|i| Node::new(i as f32 * 10., 0.)).collect();
+        let edges = (0..4).map(|i| Edge::new(i, i + 1, &nodes)).collect();
+        let mut roads = Roads::new(Graph::new(nodes, edges), vec![1; 4], vec![Control::Free; 5], vec![]);
+        assert!(roads.spawn(&[0, 1, 2, 3]));
+        roads.vehicles[0].speed = roads.idm.desired_speed;
+
+        // 28 m in one step: through two edges to the third one
+        roads.update(2.);
+        assert_eq!(roads.vehicles[0].pos, GraphPos::new(2, 8.));
+        assert_eq!(roads.vehicles[0].leg, 2);
+        for (edge_id, distance, passed) in [(0, 10., 1), (1, 10., 1), (2, 8., 0)] {
+            assert!((roads.stats(edge_id).distance - distance).abs() < 1e-4);
+            assert_eq!(roads.stats(edge_id).passed, passed);
+        }
+
+        // Leaves at the end of the route
+        roads.update(2.);
+        assert!(roads.vehicles.is_empty());
+        assert_eq!(roads.delivered(), 1);
+        assert!((roads.stats(3).distance - 10.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_lights() {
+        let phases = vec![Phase { green: vec![2], duration: 30. }, Phase { green: vec![0], duration: 30. }];
+        let mut roads = crossing(Control::Lights { phases });
+        assert_eq!(roads.light(0, 0), Some(Light::Red));
+        assert_eq!(roads.light(0, 2), Some(Light::Green));
+        assert_eq!(roads.light(1, 0), None);
+        assert!(roads.spawn(&[0, 1]));
+        run(&mut roads, 28.);
+        // Waiting at the stop line
+        let vehicle = &roads.vehicles[0];
+        assert_eq!(vehicle.pos.edge_id(), 0);
+        assert!(vehicle.speed < 0.1 && vehicle.pos.distance() > 190.);
+        assert_eq!(roads.queue(0), 1);
+        assert_eq!(roads.light(0, 2), Some(Light::Amber));
+        run(&mut roads, 10.);
+        assert_eq!(roads.vehicles[0].pos.edge_id(), 1);
+        assert!(roads.congestion(0) > 0.2);
+    }
+
+    #[test]
+    fn test_priority() {
+        let mut roads = crossing(Control::Priority { major: vec![0] });
+        // Car every 3 seconds on the major road
+        roads.add_flow(Flow { route: vec![0, 1], rate: 1200. });
+        roads.spawn(&[2, 1]);
+        run(&mut roads, 40.);
+        let side = &roads.vehicles[0];
+        assert_eq!(side.route(), &[2, 1]);
+        assert_eq!(side.pos.edge_id(), 2);
+        assert!(side.speed < 0.5 && side.pos.distance() > 190.);
+
+        // Goes when the major road is empty
+        roads.clear_flows();
+        run(&mut roads, 60.);
+        assert!(roads.vehicles.is_empty());
+        assert_eq!(roads.stats(2).passed, 1);
+    }
+
+    #[test]
+    fn test_validate() {
+        let text = r#"
+            nodes = [{ x = 0.0, y = 0.0 }, { x = 100.0, y = 0.0 }]
+            edges = [{ from = 0, to = 1, lanes = 2 }, { from = 1, to = 0, lanes = 0 }]
+            intersections = [{ node = 1, control = { type = "lights", phases = [{ green = [1], duration = 0.0 }] } }]
+            flows = [{ route = [0, 1], rate = 100.0 }, { route = [0, 0, 5], rate = -1.0 }]
+        "#;
+        let def: RoadsDef = toml::from_str(text).unwrap();
+        assert_eq!(def.validate(), vec![
+            "Road 1 has no lanes".to_owned(),
+            "Road 1 doesn't lead to the intersection at node 1".to_owned(),
+            "Intersection at node 1 has phase without duration".to_owned(),
+            "Flow 1 refers to road 5 which doesn't exist".to_owned(),
+            "Flow 1 has negative rate".to_owned(),
+        ]);
+        let roads = def.to_roads();
+        assert_eq!(roads.lanes, vec![2, 0]);
+        assert!(matches!(roads.controls[1], Control::Lights { .. }));
+    }
+}
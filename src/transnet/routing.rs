@@ -0,0 +1,268 @@
+// Route planning on the track network.
+//
+// Route is an ordered list of edges, starting with the edge of the current position.
+// Direction on each edge follows from the node where the previous edge ends.
+// Route respects edge directions and junction rules, the same way as `Graph::update_pos`.
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fmt;
+
+use crate::transnet::{Graph, GraphPos};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Destination {
+    /// Route ends on the edge which leads to this node
+    Node(usize),
+    /// Route ends with this edge
+    Edge(usize),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum RouteError {
+    InvalidNode(usize),
+    InvalidEdge(usize),
+    Unreachable { from_edge: usize, to: Destination },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub edges: Vec<usize>,
+    /// Distance from the start position to the end of the route
+    pub length: f32,
+}
+
+// Item in the priority queue. Ordered by the smallest estimated cost
+struct QueueItem {
+    estimate: f32,
+    state: usize,
+}
+
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidNode(id) => write!(f, "Node {} doesn't exist", id),
+            RouteError::InvalidEdge(id) => write!(f, "Edge {} doesn't exist", id),
+            RouteError::Unreachable { from_edge, to } =>
+                write!(f, "{:?} can't be reached from edge {}", to, from_edge),
+        }
+    }
+}
+
+impl std::error::Error for RouteError {}
+
+impl PartialEq for QueueItem {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueueItem {}
+
+impl PartialOrd for QueueItem {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for QueueItem {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.total_cmp(&self.estimate)
+            .then_with(|| other.state.cmp(&self.state))
+    }
+}
+
+
+impl Graph {
+    /// Find the shortest route (A*) from the given position to the destination.
+    /// Trains only move forward, in the current direction of travel.
+    pub fn find_route(&self, from: &GraphPos, to: Destination) -> Result<Route, RouteError> {
+        let start = from.edge_id();
+        if start >= self.edges.len() {
+            return Err(RouteError::InvalidEdge(start));
+        }
+        // Route ends at one of these nodes
+        let target_nodes = match to {
+            Destination::Node(id) if id >= self.nodes.len() => return Err(RouteError::InvalidNode(id)),
+            Destination::Node(id) => vec![id],
+            Destination::Edge(id) if id >= self.edges.len() => return Err(RouteError::InvalidEdge(id)),
+            // Edge can be reached in either direction
+            Destination::Edge(id) => vec![self.edges[id].from_node_id, self.edges[id].to_node_id],
+        };
+        // Search state is edge traveled in the given direction: 2 * edge_id + reversed
+        let edge_of = |state: usize| state / 2;
+        let end_node = |state: usize| self.edges[state / 2].end_node(state % 2 == 1);
+        let is_target = |state: usize| match to {
+            Destination::Node(id) => end_node(state) == id,
+            Destination::Edge(id) => edge_of(state) == id,
+        };
+        // Straight line never overestimates the distance along the track
+        let heuristic = |state: usize| {
+            if is_target(state) {
+                return 0.;
+            }
+            let a = self.nodes[end_node(state)];
+            target_nodes.iter()
+                .map(|&id| {
+                    let b = self.nodes[id];
+                    ((b.x - a.x).powi(2) + (b.y - a.y).powi(2)).sqrt()
+                })
+                .fold(f32::INFINITY, f32::min)
+        };
+
+        // Cost of reaching the end of the edge
+        let mut costs = vec![f32::INFINITY; 2 * self.edges.len()];
+        let mut parents: Vec<Option<usize>> = vec![None; 2 * self.edges.len()];
+        let mut queue = BinaryHeap::new();
+        let start_state = 2 * start + from.is_reversed() as usize;
+        costs[start_state] = self.edges[start].length() - from.progress(self.edges[start].length());
+        queue.push(QueueItem { estimate: costs[start_state] + heuristic(start_state), state: start_state });
+
+        while let Some(QueueItem { estimate, state }) = queue.pop() {
+            if estimate > costs[state] + heuristic(state) {
+                continue;
+            }
+            if is_target(state) {
+                let mut states = vec![state];
+                while let Some(parent) = parents[*states.last().unwrap()] {
+                    states.push(parent);
+                }
+                let edges = states.into_iter().rev().map(edge_of).collect();
+                return Ok(Route { edges, length: costs[state] });
+            }
+            let node_id = end_node(state);
+            for (next_id, next) in self.edges.iter().enumerate() {
+                let Some(reversed) = self.turn(node_id, edge_of(state), next_id) else {
+                    continue;
+                };
+                let next_state = 2 * next_id + reversed as usize;
+                let cost = costs[state] + next.length();
+                if next_state != start_state && cost < costs[next_state] {
+                    costs[next_state] = cost;
+                    parents[next_state] = Some(state);
+                    queue.push(QueueItem { estimate: cost + heuristic(next_state), state: next_state });
+                }
+            }
+        }
+
+        Err(RouteError::Unreachable { from_edge: start, to })
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Junction, Node};
+
+    // 0 ---> 1 ---> 2
+    // ^      |      |
+    // |      v      v
+    // 5 <--- 4 <--- 3      6 (isolated)
+    fn graph() -> Graph {
+        let nodes = vec![
+            Node::new(0., 0.), Node::new(100., 0.), Node::new(200., 0.),
+            Node::new(200., 100.), Node::new(100., 100.), Node::new(0., 100.),
+            Node::new(500., 500.),
+        ];
+        let edges = vec![
+            Edge::new(0, 1, &nodes),
+            Edge::new(1, 2, &nodes),
+            Edge::new(2, 3, &nodes),
+            Edge::new(3, 4, &nodes),
+            Edge::new(4, 5, &nodes),
+            Edge::new(5, 0, &nodes),
+            Edge::new(1, 4, &nodes),
+        ];
+        Graph::new(nodes, edges)
+    }
+
+    #[test]
+    fn test_shortest_route() {
+        let graph = graph();
+        let route = graph.find_route(&GraphPos::new(0, 40.), Destination::Node(5)).unwrap();
+        assert_eq!(route.edges, vec![0, 6, 4]);
+        assert_eq!(route.length, 260.);
+
+        let route = graph.find_route(&GraphPos::init(0), Destination::Edge(3)).unwrap();
+        assert_eq!(route.edges, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_route_around_loop() {
+        let graph = graph();
+        // Start node is behind the train so it has to go around
+        let route = graph.find_route(&GraphPos::new(1, 10.), Destination::Node(1)).unwrap();
+        assert_eq!(route.edges, vec![1, 2, 3, 4, 5, 0]);
+        let route = graph.find_route(&GraphPos::init(0), Destination::Edge(0)).unwrap();
+        assert_eq!(route.edges, vec![0]);
+    }
+
+    #[test]
+    fn test_update_pos_follows_route() {
+        let graph = graph();
+        let route = graph.find_route(&GraphPos::init(0), Destination::Node(5)).unwrap();
+        let mut pos = GraphPos::init(0);
+        let mut visited = vec![pos.edge_id()];
+        for _ in 0..100 {
+            pos = graph.update_pos(&pos, &route.edges, 7.);
+            if visited.last() != Some(&pos.edge_id()) {
+                visited.push(pos.edge_id());
+            }
+        }
+        assert_eq!(visited, route.edges);
+        assert_eq!(pos, GraphPos::new(4, 100.));
+    }
+
+    #[test]
+    fn test_route_to_reversed_edge() {
+        //  0 ---e3--- 1 <--e1-- 2 <--e0-- 3
+        //   ^                   |
+        //    `-------e2--------'
+        // Target edge 3 is reached backward from node 1 (60 + 20) or forward from node 0 (63 + 20)
+        let nodes = vec![Node::new(0., 0.), Node::new(0., 20.), Node::new(60., 20.), Node::new(120., 20.)];
+        let edges = vec![
+            Edge::new(3, 2, &nodes),
+            Edge::new(2, 1, &nodes),
+            Edge::new(2, 0, &nodes),
+            Edge::new(0, 1, &nodes).bidirectional(),
+        ];
+        let graph = Graph::new(nodes, edges);
+        let route = graph.find_route(&GraphPos::init(0), Destination::Edge(3)).unwrap();
+        assert_eq!(route.edges, vec![0, 1, 3]);
+        assert_eq!(route.length, 140.);
+    }
+
+    #[test]
+    fn test_route_respects_rules() {
+        let mut graph = graph();
+        // Edge 6 can be traveled back to node 1
+        graph.edges[6] = Edge::new(1, 4, &graph.nodes).bidirectional();
+        let route = graph.find_route(&GraphPos::new(3, 50.), Destination::Node(2)).unwrap();
+        assert_eq!(route.edges, vec![3, 6, 1]);
+
+        graph.set_junction(4, Junction::Forbidden(vec![(3, 6)]));
+        let route = graph.find_route(&GraphPos::new(3, 50.), Destination::Node(2)).unwrap();
+        assert_eq!(route.edges, vec![3, 4, 5, 0, 1]);
+
+        // Train follows the route in the right direction
+        let mut pos = GraphPos::new_reversed(6, 10.);
+        pos = graph.update_pos(&pos, &[6, 1], 20.);
+        assert_eq!(pos, GraphPos::new(1, 10.));
+    }
+
+    #[test]
+    fn test_errors() {
+        let graph = graph();
+        assert_eq!(
+            graph.find_route(&GraphPos::init(0), Destination::Node(6)),
+            Err(RouteError::Unreachable { from_edge: 0, to: Destination::Node(6) }));
+        assert_eq!(graph.find_route(&GraphPos::init(0), Destination::Node(7)), Err(RouteError::InvalidNode(7)));
+        assert_eq!(graph.find_route(&GraphPos::init(9), Destination::Node(1)), Err(RouteError::InvalidEdge(9)));
+    }
+}
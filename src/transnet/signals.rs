@@ -0,0 +1,530 @@
+// Block signalling. Keeps trains from driving into each other.
+//
+// Every edge belongs to a block (by default each edge is a separate block). Train has to reserve
+// the next block before entering it, otherwise it stops at the signal at the end of the edge.
+// Signals are at the nodes:
+//  * Block signal reserves the whole next block.
+//  * Path signal reserves only the edges on the train route. Reservation continues through the
+//    following blocks until the train reaches a block signal, where it can safely wait.
+//    Two trains can pass the same junction block at once, if their paths don't cross.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::transnet::{Graph, GraphPos};
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Signal {
+    #[default]
+    Block,
+    Path,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SignalError {
+    InvalidEdge(usize),
+    /// Edge is already reserved by another train
+    Occupied { edge: usize, train: usize },
+}
+
+pub struct Signalling {
+    /// Block of every edge
+    blocks: Vec<usize>,
+    /// End nodes of every edge
+    nodes: Vec<(usize, usize)>,
+    /// Signal at every node
+    signals: Vec<Signal>,
+    /// Train which reserved (or occupies) the edge
+    reservations: Vec<Option<usize>>,
+    /// Train stopped at red signal -> train holding the reservation
+    waiting: HashMap<usize, usize>,
+    /// Edges covered by the whole train, for trains longer than a point.
+    /// Reservations of such trains are released when the last car leaves the edge.
+    occupied: HashMap<usize, Vec<usize>>,
+}
+
+
+impl fmt::Display for SignalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignalError::InvalidEdge(id) => write!(f, "Edge {} doesn't exist", id),
+            SignalError::Occupied { edge, train } =>
+                write!(f, "Edge {} is reserved by train {}", edge, train),
+        }
+    }
+}
+
+impl std::error::Error for SignalError {}
+
+
+impl Signalling {
+    pub fn new(graph: &Graph) -> Self {
+        Self {
+            blocks: (0..graph.edges.len()).collect(),
+            nodes: graph.edges.iter().map(|e| (e.from_node_id, e.to_node_id)).collect(),
+            signals: vec![Signal::Block; graph.nodes.len()],
+            reservations: vec![None; graph.edges.len()],
+            waiting: HashMap::new(),
+            occupied: HashMap::new(),
+        }
+    }
+
+    /// Join edges into a single block
+    pub fn set_block(&mut self, edges: &[usize]) {
+        let block = self.blocks.iter().max().map_or(0, |b| b + 1);
+        for &edge_id in edges {
+            self.blocks[edge_id] = block;
+        }
+    }
+
+    pub fn set_signal(&mut self, node_id: usize, signal: Signal) {
+        self.signals[node_id] = signal;
+    }
+
+    pub fn block(&self, edge_id: usize) -> usize {
+        self.blocks[edge_id]
+    }
+
+    pub fn reserved_by(&self, edge_id: usize) -> Option<usize> {
+        self.reservations[edge_id]
+    }
+
+    /// Train holding the reservation which this train is waiting for
+    pub fn waiting_for(&self, train: usize) -> Option<usize> {
+        self.waiting.get(&train).copied()
+    }
+
+    /// Put the train on the network. It reserves the whole block of its edge
+    pub fn place_train(&mut self, train: usize, pos: &GraphPos) -> Result<(), SignalError> {
+        let edge_id = pos.edge_id();
+        if edge_id >= self.blocks.len() {
+            return Err(SignalError::InvalidEdge(edge_id));
+        }
+        let edges = self.block_edges(self.blocks[edge_id]);
+        if let Some((edge, other)) = self.conflict(train, &edges) {
+            return Err(SignalError::Occupied { edge, train: other });
+        }
+        for edge in edges {
+            self.reservations[edge] = Some(train);
+        }
+        Ok(())
+    }
+
+    /// Put the train which covers the given edges on the network. It reserves blocks of all the edges
+    pub fn place_consist(&mut self, train: usize, edges: &[usize]) -> Result<(), SignalError> {
+        if let Some(&edge_id) = edges.iter().find(|&&e| e >= self.blocks.len()) {
+            return Err(SignalError::InvalidEdge(edge_id));
+        }
+        let mut block_edges: Vec<usize> = edges.iter().flat_map(|&e| self.block_edges(self.blocks[e])).collect();
+        block_edges.sort();
+        block_edges.dedup();
+        if let Some((edge, other)) = self.conflict(train, &block_edges) {
+            return Err(SignalError::Occupied { edge, train: other });
+        }
+        for edge in block_edges {
+            self.reservations[edge] = Some(train);
+        }
+        self.occupied.insert(train, edges.to_vec());
+        Ok(())
+    }
+
+    /// Update edges covered by the train after it moved. Releases edges (and blocks) the train left
+    pub fn set_occupied(&mut self, train: usize, edges: Vec<usize>) {
+        let old_edges = self.occupied.remove(&train).unwrap_or_default();
+        for edge in old_edges.into_iter().filter(|e| !edges.contains(e)) {
+            let block = self.blocks[edge];
+            let left_block = !edges.iter().any(|&e| self.blocks[e] == block);
+            for (other_edge, reservation) in self.reservations.iter_mut().enumerate() {
+                let released = other_edge == edge || (left_block && self.blocks[other_edge] == block);
+                if released && *reservation == Some(train) {
+                    *reservation = None;
+                }
+            }
+        }
+        self.occupied.insert(train, edges);
+    }
+
+    /// Train which covers the edge (only for trains with the occupied edges set)
+    pub fn occupied_by(&self, edge_id: usize) -> Option<usize> {
+        self.occupied.iter().find(|(_, edges)| edges.contains(&edge_id)).map(|(&train, _)| train)
+    }
+
+    /// Release all the reservations of the train
+    pub fn remove_train(&mut self, train: usize) {
+        for reservation in self.reservations.iter_mut().filter(|r| **r == Some(train)) {
+            *reservation = None;
+        }
+        self.waiting.remove(&train);
+        self.occupied.remove(&train);
+    }
+
+    /// Move the train along the route, like `Graph::update_pos`, but stop at red signals.
+    /// Only forward movement is supported.
+    pub fn advance(
+        &mut self, graph: &Graph, train: usize, pos: &GraphPos, route: &[usize], distance: f32,
+    ) -> GraphPos {
+        let mut pos = *pos;
+        let mut distance = distance.max(0.);
+        loop {
+            let length = graph.edges[pos.edge_id()].length();
+            let remaining = length - pos.progress(length);
+            if distance <= remaining {
+                return graph.update_pos(&pos, route, distance);
+            }
+            let Some((next, reversed)) = graph.next_edge(&pos, route) else {
+                // End of the route
+                return graph.update_pos(&pos, route, remaining);
+            };
+            if let Err(other) = self.reserve_next(graph, train, &pos, route, next) {
+                self.waiting.insert(train, other);
+                return graph.update_pos(&pos, route, remaining);
+            }
+            self.waiting.remove(&train);
+            self.leave(train, pos.edge_id(), next);
+            distance -= remaining;
+            pos = GraphPos::with_progress(next, reversed, 0., graph.edges[next].length());
+        }
+    }
+
+    /// Distance to the next red signal (or the end of the route). Blocks up to `lookahead` ahead
+    /// are reserved, so the train can brake in time. Infinity if the way is clear.
+    pub fn clear_distance(
+        &mut self, graph: &Graph, train: usize, pos: &GraphPos, route: &[usize], lookahead: f32,
+    ) -> f32 {
+        let length = graph.edges[pos.edge_id()].length();
+        let mut distance = length - pos.progress(length);
+        let mut pos = *pos;
+        while distance < lookahead {
+            let Some((next, reversed)) = graph.next_edge(&pos, route) else {
+                return distance;
+            };
+            if let Err(other) = self.reserve_next(graph, train, &pos, route, next) {
+                self.waiting.insert(train, other);
+                return distance;
+            }
+            self.waiting.remove(&train);
+            let length = graph.edges[next].length();
+            distance += length;
+            pos = GraphPos::with_progress(next, reversed, length, length);
+        }
+        f32::INFINITY
+    }
+
+    /// Trains waiting for each other in a cycle, so none of them can move
+    pub fn deadlock(&self) -> Option<Vec<usize>> {
+        let mut trains: Vec<usize> = self.waiting.keys().copied().collect();
+        trains.sort();
+        for start in trains {
+            let mut chain = vec![start];
+            while let Some(next) = self.waiting_for(*chain.last().unwrap()) {
+                if let Some(idx) = chain.iter().position(|&t| t == next) {
+                    let mut cycle = chain[idx..].to_vec();
+                    cycle.sort();
+                    return Some(cycle);
+                }
+                chain.push(next);
+            }
+        }
+        None
+    }
+
+    fn block_edges(&self, block: usize) -> Vec<usize> {
+        (0..self.blocks.len()).filter(|&e| self.blocks[e] == block).collect()
+    }
+
+    // Reserve the next edge (and whatever the signal requires). Returns the train blocking the way
+    fn reserve_next(
+        &mut self, graph: &Graph, train: usize, pos: &GraphPos, route: &[usize], next: usize,
+    ) -> Result<(), usize> {
+        if self.reservations[next] == Some(train) {
+            return Ok(());
+        }
+        let node_id = graph.edges[pos.edge_id()].end_node(pos.is_reversed());
+        let edges = if self.blocks[next] == self.blocks[pos.edge_id()] {
+            vec![next]
+        } else {
+            match self.signals[node_id] {
+                Signal::Block => self.block_edges(self.blocks[next]),
+                Signal::Path => self.path_ahead(graph, pos, route),
+            }
+        };
+        if let Some((_, other)) = self.conflict(train, &edges) {
+            return Err(other);
+        }
+        for edge in edges {
+            self.reservations[edge] = Some(train);
+        }
+        Ok(())
+    }
+
+    // Edges on the route after the current one, up to the block signal or the end of the route
+    fn path_ahead(&self, graph: &Graph, pos: &GraphPos, route: &[usize]) -> Vec<usize> {
+        let mut edges: Vec<usize> = vec![];
+        let start = pos.edge_id();
+        let mut pos = *pos;
+        while let Some((next, reversed)) = graph.next_edge(&pos, route) {
+            if next == start || edges.contains(&next) {
+                break;
+            }
+            let node_id = graph.edges[pos.edge_id()].end_node(pos.is_reversed());
+            let new_block = edges.last().is_some_and(|&e| self.blocks[e] != self.blocks[next]);
+            if new_block && self.signals[node_id] == Signal::Block {
+                break;
+            }
+            edges.push(next);
+            let length = graph.edges[next].length();
+            pos = GraphPos::with_progress(next, reversed, length, length);
+        }
+        edges
+    }
+
+    // First edge (and train) which prevents the train from reserving the given edges.
+    // Edge is blocked when another train reserved it, or an edge from the same block which
+    // shares the node with it (paths crossing at the junction), or when it leads to the node
+    // covered by another train.
+    fn conflict(&self, train: usize, edges: &[usize]) -> Option<(usize, usize)> {
+        for (&other, covered) in self.occupied.iter().filter(|(&t, _)| t != train) {
+            for pair in covered.windows(2) {
+                let (a1, a2) = self.nodes[pair[0]];
+                let (b1, b2) = self.nodes[pair[1]];
+                let node = if a1 == b1 || a1 == b2 { a1 } else { a2 };
+                if let Some(&edge) = edges.iter().find(|&&e| self.nodes[e].0 == node || self.nodes[e].1 == node) {
+                    return Some((edge, other));
+                }
+            }
+        }
+        for &edge in edges {
+            for (other_edge, reservation) in self.reservations.iter().enumerate() {
+                let Some(other) = reservation.filter(|&t| t != train) else {
+                    continue;
+                };
+                if other_edge == edge || (self.blocks[other_edge] == self.blocks[edge]
+                    && self.touching(other_edge, edge)) {
+                    return Some((edge, other));
+                }
+            }
+        }
+        None
+    }
+
+    fn touching(&self, a: usize, b: usize) -> bool {
+        let (a1, a2) = self.nodes[a];
+        let (b1, b2) = self.nodes[b];
+        a1 == b1 || a1 == b2 || a2 == b1 || a2 == b2
+    }
+
+    // Train left the edge. Release it, or the whole block if the train entered the next one.
+    // Long trains release edges in `set_occupied` instead.
+    fn leave(&mut self, train: usize, from: usize, to: usize) {
+        if self.occupied.contains_key(&train) {
+            return;
+        }
+        let block = self.blocks[from];
+        for (edge, reservation) in self.reservations.iter_mut().enumerate() {
+            let released = if self.blocks[to] != block { self.blocks[edge] == block } else { edge == from };
+            if released && *reservation == Some(train) {
+                *reservation = None;
+            }
+        }
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Node};
+
+    struct TestTrain {
+        pos: GraphPos,
+        route: Vec<usize>,
+        speed: f32,
+    }
+
+    impl TestTrain {
+        fn new(edge_id: usize, route: &[usize], speed: f32) -> Self {
+            Self { pos: GraphPos::init(edge_id), route: route.to_vec(), speed }
+        }
+    }
+
+    // Move all trains for the given number of steps. Checks that trains never share an edge.
+    // Returns true if all of them reached the end of their routes
+    fn run(graph: &Graph, signals: &mut Signalling, trains: &mut [TestTrain], steps: usize) -> bool {
+        for (id, train) in trains.iter().enumerate() {
+            signals.place_train(id, &train.pos).unwrap();
+        }
+        for _ in 0..steps {
+            for (id, train) in trains.iter_mut().enumerate() {
+                train.pos = signals.advance(graph, id, &train.pos, &train.route, train.speed);
+                assert_eq!(signals.reserved_by(train.pos.edge_id()), Some(id));
+            }
+            let mut edges: Vec<usize> = trains.iter().map(|t| t.pos.edge_id()).collect();
+            edges.sort();
+            edges.dedup();
+            assert_eq!(edges.len(), trains.len(), "Trains collided");
+        }
+        trains.iter().all(|t| {
+            let length = graph.edges[t.pos.edge_id()].length();
+            t.pos.edge_id() == *t.route.last().unwrap() && t.pos.progress(length) == length
+        })
+    }
+
+    // Square loop: 0 -> 1 -> 2 -> 3 -> 0
+    fn square() -> Graph {
+        let nodes = vec![
+            Node::new(0., 0.), Node::new(100., 0.), Node::new(100., 100.), Node::new(0., 100.),
+        ];
+        let edges = (0..4).map(|i| Edge::new(i, (i + 1) % 4, &nodes)).collect();
+        Graph::new(nodes, edges)
+    }
+
+    // Single track between two stations, split into two blocks at node 2:
+    //
+    //  0 --e0--> 1 ==e1== 2 ==e2== 3 --e3--> 4
+    //            ^                 |
+    //           e5                 e4
+    //            |                 v
+    //            6                 5
+    //
+    // Train 0 goes from node 0 to node 4, train 1 from node 5 to node 6
+    fn single_track() -> Graph {
+        let nodes = vec![
+            Node::new(0., 0.), Node::new(100., 0.), Node::new(200., 0.), Node::new(300., 0.),
+            Node::new(400., 0.), Node::new(300., 100.), Node::new(100., 100.),
+        ];
+        let edges = vec![
+            Edge::new(0, 1, &nodes),
+            Edge::new(1, 2, &nodes).bidirectional(),
+            Edge::new(2, 3, &nodes).bidirectional(),
+            Edge::new(3, 4, &nodes),
+            Edge::new(5, 3, &nodes),
+            Edge::new(1, 6, &nodes),
+        ];
+        Graph::new(nodes, edges)
+    }
+
+    #[test]
+    fn test_trains_keep_distance() {
+        let graph = square();
+        let mut signals = Signalling::new(&graph);
+        let route = [0, 1, 2, 3];
+        // Fast train right behind the slow one
+        let mut trains = [TestTrain::new(1, &route, 1.), TestTrain::new(0, &route, 7.)];
+        run(&graph, &mut signals, &mut trains, 1000);
+        assert_eq!(signals.deadlock(), None);
+    }
+
+    #[test]
+    fn test_red_signal() {
+        let graph = square();
+        let mut signals = Signalling::new(&graph);
+        signals.place_train(0, &GraphPos::init(1)).unwrap();
+        signals.place_train(1, &GraphPos::init(0)).unwrap();
+        assert_eq!(
+            signals.place_train(2, &GraphPos::new(0, 50.)),
+            Err(SignalError::Occupied { edge: 0, train: 1 }));
+
+        let pos = signals.advance(&graph, 1, &GraphPos::new(0, 90.), &[0, 1], 20.);
+        assert_eq!(pos, GraphPos::new(0, 100.));
+        assert_eq!(signals.waiting_for(1), Some(0));
+
+        signals.remove_train(0);
+        assert_eq!(signals.clear_distance(&graph, 1, &pos, &[0, 1], 50.), f32::INFINITY);
+        // Route ends at the end of edge 1
+        assert_eq!(signals.clear_distance(&graph, 1, &pos, &[0, 1], 500.), 100.);
+        assert_eq!(signals.reserved_by(1), Some(1));
+        let pos = signals.advance(&graph, 1, &pos, &[0, 1], 20.);
+        assert_eq!(pos, GraphPos::new(1, 20.));
+        assert_eq!(signals.reserved_by(0), None);
+        assert_eq!(signals.waiting_for(1), None);
+    }
+
+    #[test]
+    fn test_loop_deadlock() {
+        // Every block of the loop is taken, nobody can move
+        let graph = square();
+        let mut signals = Signalling::new(&graph);
+        let route = [0, 1, 2, 3];
+        let mut trains: Vec<TestTrain> = (0..4).map(|e| TestTrain::new(e, &route, 5.)).collect();
+        run(&graph, &mut signals, &mut trains, 100);
+        assert_eq!(signals.deadlock(), Some(vec![0, 1, 2, 3]));
+    }
+
+    #[test]
+    fn test_single_track_deadlock() {
+        let graph = single_track();
+        let mut trains = [TestTrain::new(0, &[0, 1, 2, 3], 3.), TestTrain::new(4, &[4, 2, 1, 5], 3.)];
+
+        // With block signals both trains enter the single track from the opposite sides
+        let mut signals = Signalling::new(&graph);
+        assert!(!run(&graph, &mut signals, &mut trains, 200));
+        assert_eq!(signals.deadlock(), Some(vec![0, 1]));
+
+        // Path signals reserve the whole single track, so the other train waits before entering it
+        let mut trains = [TestTrain::new(0, &[0, 1, 2, 3], 3.), TestTrain::new(4, &[4, 2, 1, 5], 3.)];
+        let mut signals = Signalling::new(&graph);
+        for node_id in [1, 2, 3] {
+            signals.set_signal(node_id, Signal::Path);
+        }
+        assert!(run(&graph, &mut signals, &mut trains, 200));
+        assert_eq!(signals.deadlock(), None);
+    }
+
+    #[test]
+    fn test_path_signals_at_junction() {
+        //  0 --e0--> 2 --e2--> 4
+        //            |
+        //  1 --e1--> 3 --e3--> 5
+        //
+        // e2, e3 and e4 (2 -> 3) form the junction block
+        let nodes = vec![
+            Node::new(0., 0.), Node::new(0., 100.), Node::new(100., 0.),
+            Node::new(100., 100.), Node::new(200., 0.), Node::new(200., 100.),
+        ];
+        let graph = Graph::new(nodes.clone(), vec![
+            Edge::new(0, 2, &nodes),
+            Edge::new(1, 3, &nodes),
+            Edge::new(2, 4, &nodes),
+            Edge::new(3, 5, &nodes),
+            Edge::new(2, 3, &nodes),
+        ]);
+        let setup = |signal: Signal| {
+            let mut signals = Signalling::new(&graph);
+            signals.set_block(&[2, 3, 4]);
+            signals.set_signal(2, signal);
+            signals.set_signal(3, signal);
+            signals.place_train(0, &GraphPos::new(0, 99.)).unwrap();
+            signals.place_train(1, &GraphPos::new(1, 99.)).unwrap();
+            signals
+        };
+
+        // Block signal lets only one train into the junction
+        let mut signals = setup(Signal::Block);
+        let pos = signals.advance(&graph, 0, &GraphPos::new(0, 99.), &[0, 2], 2.);
+        assert_eq!(pos, GraphPos::new(2, 1.));
+        let pos = signals.advance(&graph, 1, &GraphPos::new(1, 99.), &[1, 3], 2.);
+        assert_eq!(pos, GraphPos::new(1, 100.));
+
+        // Paths don't cross, both trains can go
+        let mut signals = setup(Signal::Path);
+        let pos = signals.advance(&graph, 0, &GraphPos::new(0, 99.), &[0, 2], 2.);
+        assert_eq!(pos, GraphPos::new(2, 1.));
+        let pos = signals.advance(&graph, 1, &GraphPos::new(1, 99.), &[1, 3], 2.);
+        assert_eq!(pos, GraphPos::new(3, 1.));
+
+        // Crossing path has to wait
+        let mut signals = setup(Signal::Path);
+        let pos = signals.advance(&graph, 0, &GraphPos::new(0, 99.), &[0, 4, 3], 2.);
+        assert_eq!(pos, GraphPos::new(4, 1.));
+        let pos = signals.advance(&graph, 1, &GraphPos::new(1, 99.), &[1, 3], 2.);
+        assert_eq!(pos, GraphPos::new(1, 100.));
+        assert_eq!(signals.waiting_for(1), Some(0));
+    }
+}
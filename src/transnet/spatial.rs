@@ -0,0 +1,289 @@
+// Spatial queries on the track graph: nearest node, nearest edge, edges in the area.
+//
+// Queries use a uniform grid. Every cell lists the nodes inside it and the edges which pass
+// through it (curves are approximated by short segments). Index doesn't follow changes of the
+// graph, it has to be built again after editing.
+
+use std::collections::HashMap;
+
+use crate::transnet::{Graph, GraphPos, Node};
+
+
+/// Default size of the grid cell
+pub const INDEX_CELL_SIZE: f32 = 50.;
+// Max length of the segment approximating the curve
+const SEGMENT_LENGTH: f32 = 5.;
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SpatialIndex {
+    cell_size: f32,
+    nodes: HashMap<(i32, i32), Vec<usize>>,
+    edges: HashMap<(i32, i32), Vec<usize>>,
+    /// Range of the occupied cells: min and max
+    bounds: Option<((i32, i32), (i32, i32))>,
+}
+
+/// Point on the edge closest to the query point
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EdgeHit {
+    pub edge_id: usize,
+    pub pos: GraphPos,
+    pub point: Node,
+    pub distance: f32,
+}
+
+
+impl Graph {
+    pub fn spatial_index(&self) -> SpatialIndex {
+        SpatialIndex::new(self, INDEX_CELL_SIZE)
+    }
+}
+
+impl SpatialIndex {
+    pub fn new(graph: &Graph, cell_size: f32) -> Self {
+        let mut index = Self { cell_size, nodes: HashMap::new(), edges: HashMap::new(), bounds: None };
+        for (node_id, node) in graph.nodes.iter().enumerate() {
+            let cell = index.cell(*node);
+            index.nodes.entry(cell).or_default().push(node_id);
+            index.extend_bounds(cell);
+        }
+        for (edge_id, edge) in graph.edges.iter().enumerate() {
+            let mut cells = vec![];
+            for segment in edge.curve().points(SEGMENT_LENGTH).windows(2) {
+                let (min_x, min_y) = index.cell(Node::new(segment[0].x.min(segment[1].x), segment[0].y.min(segment[1].y)));
+                let (max_x, max_y) = index.cell(Node::new(segment[0].x.max(segment[1].x), segment[0].y.max(segment[1].y)));
+                for x in min_x..=max_x {
+                    for y in min_y..=max_y {
+                        cells.push((x, y));
+                    }
+                }
+            }
+            cells.sort();
+            cells.dedup();
+            for cell in cells {
+                index.edges.entry(cell).or_default().push(edge_id);
+                index.extend_bounds(cell);
+            }
+        }
+        index
+    }
+
+    /// Closest node not further than `max_distance`
+    pub fn nearest_node(&self, graph: &Graph, point: Node, max_distance: f32) -> Option<usize> {
+        let mut best: Option<(usize, f32)> = None;
+        self.search(point, max_distance, |cell| {
+            for &node_id in self.nodes.get(&cell).into_iter().flatten() {
+                let distance = distance(graph.nodes[node_id], point);
+                if distance <= max_distance && best.is_none_or(|b| distance < b.1) {
+                    best = Some((node_id, distance));
+                }
+            }
+            best.map(|b| b.1)
+        });
+        best.map(|b| b.0)
+    }
+
+    /// Closest point on any edge not further than `max_distance`
+    pub fn nearest_edge(&self, graph: &Graph, point: Node, max_distance: f32) -> Option<EdgeHit> {
+        let mut best: Option<EdgeHit> = None;
+        let mut checked = vec![];
+        self.search(point, max_distance, |cell| {
+            for &edge_id in self.edges.get(&cell).into_iter().flatten() {
+                if checked.contains(&edge_id) {
+                    continue;
+                }
+                checked.push(edge_id);
+                let hit = project(graph, edge_id, point);
+                if hit.distance <= max_distance && best.is_none_or(|b| hit.distance < b.distance) {
+                    best = Some(hit);
+                }
+            }
+            best.map(|b| b.distance)
+        });
+        best
+    }
+
+    /// Edges passing within the radius from the point, ordered by id
+    pub fn edges_in_radius(&self, graph: &Graph, point: Node, radius: f32) -> Vec<usize> {
+        let min = Node::new(point.x - radius, point.y - radius);
+        let max = Node::new(point.x + radius, point.y + radius);
+        self.candidates(min, max).into_iter()
+            .filter(|&edge_id| project(graph, edge_id, point).distance <= radius)
+            .collect()
+    }
+
+    /// Edges passing through the rectangle, ordered by id
+    pub fn edges_in_rect(&self, graph: &Graph, min: Node, max: Node) -> Vec<usize> {
+        let inside = |p: &Node| p.x >= min.x && p.x <= max.x && p.y >= min.y && p.y <= max.y;
+        self.candidates(min, max).into_iter()
+            .filter(|&edge_id| {
+                let points = graph.edges[edge_id].curve().points(SEGMENT_LENGTH);
+                points.iter().any(inside) || points.windows(2).any(|s| segment_crosses_rect(s[0], s[1], min, max))
+            })
+            .collect()
+    }
+
+    fn cell(&self, point: Node) -> (i32, i32) {
+        ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32)
+    }
+
+    fn extend_bounds(&mut self, cell: (i32, i32)) {
+        self.bounds = Some(match self.bounds {
+            None => (cell, cell),
+            Some((min, max)) => ((min.0.min(cell.0), min.1.min(cell.1)), (max.0.max(cell.0), max.1.max(cell.1))),
+        });
+    }
+
+    // Edges listed in the cells overlapping the rectangle
+    fn candidates(&self, min: Node, max: Node) -> Vec<usize> {
+        let (min_x, min_y) = self.cell(min);
+        let (max_x, max_y) = self.cell(max);
+        let mut edges = vec![];
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                edges.extend(self.edges.get(&(x, y)).into_iter().flatten());
+            }
+        }
+        edges.sort();
+        edges.dedup();
+        edges
+    }
+
+    // Visit cells in rings around the point until the best distance found by `visit` is closer
+    // than the next ring, or the search leaves the indexed area or `max_distance`
+    fn search(&self, point: Node, max_distance: f32, mut visit: impl FnMut((i32, i32)) -> Option<f32>) {
+        let Some((min, max)) = self.bounds else {
+            return;
+        };
+        let (cx, cy) = self.cell(point);
+        let max_ring = [cx - min.0, max.0 - cx, cy - min.1, max.1 - cy].into_iter().max().unwrap_or(0).max(0);
+        let max_ring = max_ring.min((max_distance / self.cell_size).ceil().min(i32::MAX as f32) as i32 + 1);
+        for ring in 0..=max_ring {
+            let mut best = None;
+            for x in cx - ring..=cx + ring {
+                for y in cy - ring..=cy + ring {
+                    if (x - cx).abs() == ring || (y - cy).abs() == ring {
+                        best = visit((x, y)).or(best);
+                    }
+                }
+            }
+            // Everything in the next ring is at least this far
+            if best.is_some_and(|b| b <= ring as f32 * self.cell_size) {
+                break;
+            }
+        }
+    }
+}
+
+
+fn distance(a: Node, b: Node) -> f32 {
+    (a.x - b.x).hypot(a.y - b.y)
+}
+
+// Closest point on the edge
+fn project(graph: &Graph, edge_id: usize, point: Node) -> EdgeHit {
+    let curve = graph.edges[edge_id].curve();
+    let points = curve.points(SEGMENT_LENGTH);
+    // Points are equally spaced along the curve
+    let step = curve.length() / (points.len() - 1).max(1) as f32;
+    let mut best = EdgeHit { edge_id, pos: GraphPos::init(edge_id), point: points[0], distance: distance(points[0], point) };
+    for (idx, segment) in points.windows(2).enumerate() {
+        let (a, b) = (segment[0], segment[1]);
+        let (dx, dy) = (b.x - a.x, b.y - a.y);
+        let len2 = dx * dx + dy * dy;
+        let t = if len2 > 0. { (((point.x - a.x) * dx + (point.y - a.y) * dy) / len2).clamp(0., 1.) } else { 0. };
+        let closest = Node::new(a.x + t * dx, a.y + t * dy);
+        let dist = distance(closest, point);
+        if dist < best.distance {
+            let along = ((idx as f32 + t) * step).min(curve.length());
+            best = EdgeHit { edge_id, pos: GraphPos::new(edge_id, along), point: closest, distance: dist };
+        }
+    }
+    best
+}
+
+fn segment_crosses_rect(a: Node, b: Node, min: Node, max: Node) -> bool {
+    let corners = [min, Node::new(max.x, min.y), max, Node::new(min.x, max.y)];
+    (0..4).any(|i| segments_intersect(a, b, corners[i], corners[(i + 1) % 4]))
+}
+
+fn segments_intersect(a: Node, b: Node, c: Node, d: Node) -> bool {
+    let cross = |o: Node, p: Node, q: Node| (p.x - o.x) * (q.y - o.y) - (p.y - o.y) * (q.x - o.x);
+    let (d1, d2) = (cross(c, d, a), cross(c, d, b));
+    let (d3, d4) = (cross(a, b, c), cross(a, b, d));
+    d1 * d2 <= 0. && d3 * d4 <= 0. && !(d1 == 0. && d2 == 0.)
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Geometry};
+
+    // Square 0-1-2-3 with side 100 and arc 1 -> 3 bulging out of the square to the right
+    fn network() -> Graph {
+        let nodes = vec![Node::new(0., 0.), Node::new(100., 0.), Node::new(100., 100.), Node::new(0., 100.)];
+        let edges = vec![
+            Edge::new(0, 1, &nodes),
+            Edge::new(1, 2, &nodes),
+            Edge::new(2, 3, &nodes),
+            Edge::new(3, 0, &nodes),
+            Edge::with_geometry(1, 2, Geometry::Arc { center: Node::new(100., 50.), clockwise: true }, &nodes),
+        ];
+        Graph::new(nodes, edges)
+    }
+
+    #[test]
+    fn test_nearest_node() {
+        let graph = network();
+        let index = SpatialIndex::new(&graph, 30.);
+        assert_eq!(index.nearest_node(&graph, Node::new(90., 95.), 20.), Some(2));
+        assert_eq!(index.nearest_node(&graph, Node::new(50., 50.), 20.), None);
+        // Far from the graph
+        assert_eq!(index.nearest_node(&graph, Node::new(-500., 0.), 1000.), Some(0));
+    }
+
+    #[test]
+    fn test_nearest_edge() {
+        let graph = network();
+        let index = graph.spatial_index();
+        let hit = index.nearest_edge(&graph, Node::new(30., 4.), 10.).unwrap();
+        assert_eq!(hit.edge_id, 0);
+        assert!((hit.pos.distance() - 30.).abs() < 1e-4);
+        assert_eq!(hit.distance, 4.);
+
+        // Top of the arc is 50 right of the square, a quarter of the arc from the start
+        let hit = index.nearest_edge(&graph, Node::new(160., 50.), 20.).unwrap();
+        assert_eq!(hit.edge_id, 4);
+        assert!((hit.pos.distance() - graph.edges[4].length() / 2.).abs() < 0.5);
+        assert!((hit.distance - 10.).abs() < 0.1);
+        assert_eq!(index.nearest_edge(&graph, Node::new(50., 50.), 20.), None);
+    }
+
+    #[test]
+    fn test_area_queries() {
+        let graph = network();
+        let index = SpatialIndex::new(&graph, 30.);
+        assert_eq!(index.edges_in_radius(&graph, Node::new(100., 50.), 10.), vec![1]);
+        assert_eq!(index.edges_in_radius(&graph, Node::new(100., 50.), 50.), vec![0, 1, 2, 4]);
+        // Rectangle inside the square touches nothing, crossing the right side finds the arc too
+        assert!(index.edges_in_rect(&graph, Node::new(10., 10.), Node::new(90., 90.)).is_empty());
+        assert_eq!(index.edges_in_rect(&graph, Node::new(90., 40.), Node::new(200., 60.)), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_many_edges() {
+        // Grid of 40 x 40 nodes, horizontal edges only
+        let nodes: Vec<Node> = (0..1600).map(|i| Node::new(10. * (i % 40) as f32, 10. * (i / 40) as f32)).collect();
+        let edges = (0..1600).filter(|i| i % 40 != 39).map(|i| Edge::new(i, i + 1, &nodes)).collect();
+        let graph = Graph::new(nodes, edges);
+        let index = graph.spatial_index();
+        let hit = index.nearest_edge(&graph, Node::new(205., 302.), 5.).unwrap();
+        assert_eq!(graph.edges[hit.edge_id].from_node_id, 30 * 40 + 20);
+        assert!((hit.pos.distance() - 5.).abs() < 1e-4);
+    }
+}
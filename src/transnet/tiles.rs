@@ -0,0 +1,344 @@
+// Track pieces placed on the tile map.
+//
+// Every piece connects two sides of its tile. Graph nodes are at the middles of the tile sides,
+// so pieces on the neighbouring tiles share the node. Curves are quarter circles around the tile
+// corner. Several pieces on one tile make a junction (or a crossing). Train can't go from one
+// piece to another piece of the same tile, that would be turning back.
+// The graph is built again after every change, edge ids are ordered by tile and piece.
+
+use std::collections::HashMap;
+
+use crate::transnet::{Edge, Geometry, Graph, Junction, Node};
+
+
+/// Track piece named by the sides of the tile it connects
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrackPiece {
+    Horizontal, // ━
+    Vertical,   // ┃
+    NorthWest,  // ┛
+    NorthEast,  // ┗
+    SouthWest,  // ┓
+    SouthEast,  // ┏
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Side {
+    North,
+    South,
+    West,
+    East,
+}
+
+/// Tile map with track pieces and the graph built from them
+#[derive(Clone, Debug)]
+pub struct TileTracks {
+    width: usize,
+    height: usize,
+    tile_size: f32,
+    pieces: Vec<Vec<TrackPiece>>,
+    graph: Graph,
+    /// Tile index and piece of every edge
+    edge_pieces: Vec<(usize, TrackPiece)>,
+}
+
+
+impl TrackPiece {
+    pub const ALL: [TrackPiece; 6] = [
+        TrackPiece::Horizontal, TrackPiece::Vertical,
+        TrackPiece::NorthWest, TrackPiece::NorthEast, TrackPiece::SouthWest, TrackPiece::SouthEast,
+    ];
+
+    // Sides in the order of the edge direction
+    fn sides(&self) -> (Side, Side) {
+        match self {
+            TrackPiece::Horizontal => (Side::West, Side::East),
+            TrackPiece::Vertical => (Side::North, Side::South),
+            TrackPiece::NorthWest => (Side::North, Side::West),
+            TrackPiece::NorthEast => (Side::North, Side::East),
+            TrackPiece::SouthWest => (Side::South, Side::West),
+            TrackPiece::SouthEast => (Side::South, Side::East),
+        }
+    }
+}
+
+impl Side {
+    // Middle of the side in half tiles: tile (x, y) spans 2x..2x+2
+    fn key(&self, x: usize, y: usize) -> (usize, usize) {
+        match self {
+            Side::North => (2 * x + 1, 2 * y),
+            Side::South => (2 * x + 1, 2 * y + 2),
+            Side::West => (2 * x, 2 * y + 1),
+            Side::East => (2 * x + 2, 2 * y + 1),
+        }
+    }
+}
+
+
+impl TileTracks {
+    pub fn new(width: usize, height: usize, tile_size: f32) -> Self {
+        Self {
+            width,
+            height,
+            tile_size,
+            pieces: vec![vec![]; width * height],
+            graph: Graph::new(vec![], vec![]),
+            edge_pieces: vec![],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn tile_size(&self) -> f32 {
+        self.tile_size
+    }
+
+    pub fn graph(&self) -> &Graph {
+        &self.graph
+    }
+
+    pub fn pieces_at(&self, x: usize, y: usize) -> &[TrackPiece] {
+        self.tile_idx(x, y).map(|idx| self.pieces[idx].as_slice()).unwrap_or(&[])
+    }
+
+    /// Add the piece to the tile. False if the tile is outside of the map or already has it
+    pub fn put(&mut self, x: usize, y: usize, piece: TrackPiece) -> bool {
+        let Some(idx) = self.tile_idx(x, y) else {
+            return false;
+        };
+        if self.pieces[idx].contains(&piece) {
+            return false;
+        }
+        self.pieces[idx].push(piece);
+        self.rebuild();
+        true
+    }
+
+    /// Remove the piece from the tile. False if it's not there
+    pub fn remove(&mut self, x: usize, y: usize, piece: TrackPiece) -> bool {
+        let Some(idx) = self.tile_idx(x, y) else {
+            return false;
+        };
+        let count = self.pieces[idx].len();
+        self.pieces[idx].retain(|&p| p != piece);
+        if self.pieces[idx].len() == count {
+            return false;
+        }
+        self.rebuild();
+        true
+    }
+
+    /// Remove all pieces from the tile. False if there were none
+    pub fn clear(&mut self, x: usize, y: usize) -> bool {
+        match self.tile_idx(x, y) {
+            Some(idx) if !self.pieces[idx].is_empty() => {
+                self.pieces[idx].clear();
+                self.rebuild();
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Tile and piece which the edge was built from
+    pub fn piece_of(&self, edge_id: usize) -> Option<(usize, usize, TrackPiece)> {
+        self.edge_pieces.get(edge_id).map(|&(idx, piece)| (idx % self.width, idx / self.width, piece))
+    }
+
+    /// Edge built from the piece on the tile
+    pub fn edge_of(&self, x: usize, y: usize, piece: TrackPiece) -> Option<usize> {
+        let idx = self.tile_idx(x, y)?;
+        self.edge_pieces.iter().position(|&p| p == (idx, piece))
+    }
+
+    /// Tile which contains the point
+    pub fn tile_at(&self, point: Node) -> Option<(usize, usize)> {
+        if point.x < 0. || point.y < 0. {
+            return None;
+        }
+        let (x, y) = ((point.x / self.tile_size) as usize, (point.y / self.tile_size) as usize);
+        self.tile_idx(x, y).map(|_| (x, y))
+    }
+
+    fn tile_idx(&self, x: usize, y: usize) -> Option<usize> {
+        (x < self.width && y < self.height).then_some(y * self.width + x)
+    }
+
+    fn rebuild(&mut self) {
+        let half = self.tile_size / 2.;
+        let mut nodes = vec![];
+        let mut node_ids = HashMap::new();
+        let mut node_id = |key: (usize, usize), nodes: &mut Vec<Node>| {
+            *node_ids.entry(key).or_insert_with(|| {
+                nodes.push(Node::new(key.0 as f32 * half, key.1 as f32 * half));
+                nodes.len() - 1
+            })
+        };
+
+        let mut ends = vec![];
+        self.edge_pieces.clear();
+        for (idx, pieces) in self.pieces.iter().enumerate() {
+            let (x, y) = (idx % self.width, idx / self.width);
+            for &piece in pieces {
+                let (start, end) = piece.sides();
+                let from = node_id(start.key(x, y), &mut nodes);
+                let to = node_id(end.key(x, y), &mut nodes);
+                ends.push((from, to, piece, x, y));
+                self.edge_pieces.push((idx, piece));
+            }
+        }
+
+        let edges = ends.iter()
+            .map(|&(from, to, piece, x, y)| {
+                let geometry = match piece {
+                    TrackPiece::Horizontal | TrackPiece::Vertical => Geometry::Straight,
+                    _ => {
+                        let (start, end) = (nodes[from], nodes[to]);
+                        let center = self.corner(x, y, start, end);
+                        // Positive cross product is the clockwise turn on the screen
+                        let cross = (start.x - center.x) * (end.y - center.y) - (start.y - center.y) * (end.x - center.x);
+                        Geometry::Arc { center, clockwise: cross > 0. }
+                    }
+                };
+                Edge::with_geometry(from, to, geometry, &nodes).bidirectional()
+            })
+            .collect();
+        self.graph = Graph::new(nodes, edges);
+
+        // Turns between pieces of the same tile would be turning back
+        let mut forbidden = vec![vec![]; self.graph.nodes.len()];
+        for (a, &(tile_a, _)) in self.edge_pieces.iter().enumerate() {
+            for (b, &(tile_b, _)) in self.edge_pieces.iter().enumerate() {
+                if a == b || tile_a != tile_b {
+                    continue;
+                }
+                let (ea, eb) = (&self.graph.edges[a], &self.graph.edges[b]);
+                for node in [ea.from_node_id, ea.to_node_id] {
+                    if node == eb.from_node_id || node == eb.to_node_id {
+                        forbidden[node].push((a, b));
+                    }
+                }
+            }
+        }
+        for (node_id, turns) in forbidden.into_iter().enumerate().filter(|(_, t)| !t.is_empty()) {
+            self.graph.set_junction(node_id, Junction::Forbidden(turns));
+        }
+    }
+
+    // Corner of the tile shared by both sides
+    fn corner(&self, x: usize, y: usize, start: Node, end: Node) -> Node {
+        let (left, top) = (x as f32 * self.tile_size, y as f32 * self.tile_size);
+        let pick = |value: f32, low: f32| if value == low { low } else { low + self.tile_size };
+        // One end is in the middle of the vertical side, the other in the middle of the horizontal one
+        let (vertical, horizontal) = if start.x == left || start.x == left + self.tile_size { (start, end) } else { (end, start) };
+        Node::new(pick(vertical.x, left), pick(horizontal.y, top))
+    }
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+    use crate::transnet::GraphPos;
+
+    // 2x2 loop: ┏┓
+    //           ┗┛
+    fn round() -> TileTracks {
+        let mut tracks = TileTracks::new(3, 2, 100.);
+        tracks.put(0, 0, TrackPiece::SouthEast);
+        tracks.put(1, 0, TrackPiece::SouthWest);
+        tracks.put(0, 1, TrackPiece::NorthEast);
+        tracks.put(1, 1, TrackPiece::NorthWest);
+        tracks
+    }
+
+    #[test]
+    fn test_straight() {
+        let mut tracks = TileTracks::new(4, 2, 100.);
+        assert!(tracks.put(0, 1, TrackPiece::Horizontal));
+        assert!(tracks.put(1, 1, TrackPiece::Horizontal));
+        assert!(!tracks.put(1, 1, TrackPiece::Horizontal));
+        assert!(!tracks.put(4, 1, TrackPiece::Horizontal));
+
+        let graph = tracks.graph();
+        assert_eq!(graph.nodes, vec![Node::new(0., 150.), Node::new(100., 150.), Node::new(200., 150.)]);
+        assert_eq!(graph.edges.len(), 2);
+        assert_eq!((graph.edges[1].from_node_id, graph.edges[1].to_node_id), (1, 2));
+        assert_eq!(graph.edges[0].length(), 100.);
+        assert_eq!(graph.turn(1, 0, 1), Some(false));
+        assert_eq!(graph.turn(1, 1, 0), Some(true));
+
+        assert_eq!(tracks.tile_at(Node::new(150., 120.)), Some((1, 1)));
+        assert_eq!(tracks.tile_at(Node::new(450., 120.)), None);
+        assert!(tracks.clear(0, 1));
+        assert_eq!(tracks.graph().edges.len(), 1);
+        assert_eq!(tracks.piece_of(0), Some((1, 1, TrackPiece::Horizontal)));
+    }
+
+    #[test]
+    fn test_curves() {
+        let tracks = round();
+        let graph = tracks.graph();
+        assert_eq!(graph.nodes.len(), 4);
+        for edge in &graph.edges {
+            assert!((edge.length() - PI / 4. * 100.).abs() < 1e-3);
+            // Curve stays inside the loop
+            for point in edge.curve().points(5.) {
+                let radius = ((point.x - 100.).powi(2) + (point.y - 100.).powi(2)).sqrt();
+                assert!(radius > 49. && radius < 71.);
+            }
+        }
+
+        // Train goes round and gets back to the start
+        let route: Vec<usize> = vec![0, 1, 3, 2];
+        let mut pos = GraphPos::new(0, 10.);
+        let start = graph.pos_to_location(&pos).point;
+        for _ in 0..100 {
+            pos = graph.update_pos(&pos, &route, PI * 100. / 100.);
+        }
+        let end = graph.pos_to_location(&pos).point;
+        assert_eq!(pos.edge_id(), 0);
+        assert!((start.x - end.x).abs() < 0.1 && (start.y - end.y).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_junction() {
+        // ━┳━
+        //  ┃
+        let mut tracks = TileTracks::new(3, 2, 100.);
+        tracks.put(0, 0, TrackPiece::Horizontal);
+        tracks.put(1, 0, TrackPiece::Horizontal);
+        tracks.put(1, 0, TrackPiece::SouthWest);
+        tracks.put(2, 0, TrackPiece::Horizontal);
+        tracks.put(1, 1, TrackPiece::Vertical);
+        let graph = tracks.graph();
+        let west = tracks.edge_of(0, 0, TrackPiece::Horizontal).unwrap();
+        let straight = tracks.edge_of(1, 0, TrackPiece::Horizontal).unwrap();
+        let curve = tracks.edge_of(1, 0, TrackPiece::SouthWest).unwrap();
+        let south = tracks.edge_of(1, 1, TrackPiece::Vertical).unwrap();
+        let node = graph.edges[west].to_node_id;
+
+        // Switch: both ways from the west
+        assert!(graph.turn(node, west, straight).is_some());
+        assert!(graph.turn(node, west, curve).is_some());
+        // But not from one piece of the tile onto the other one
+        assert_eq!(graph.turn(node, straight, curve), None);
+        assert_eq!(graph.turn(node, curve, straight), None);
+        assert!(graph.turn(graph.edges[south].from_node_id, south, curve).is_some());
+
+        assert!(tracks.remove(1, 0, TrackPiece::SouthWest));
+        assert!(!tracks.remove(1, 0, TrackPiece::SouthWest));
+        assert_eq!(tracks.edge_of(1, 0, TrackPiece::SouthWest), None);
+        assert_eq!(tracks.graph().junctions[node], Junction::Free);
+    }
+}
@@ -0,0 +1,377 @@
+// Stations, timetables and the simulation clock.
+//
+// Train with a timetable stops at every station on the list. It waits at least the dwell time
+// and doesn't leave before the departure time. Arrivals and departures are recorded,
+// so it is possible to check how punctual the trains are.
+
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::transnet::{Graph, GraphPos};
+
+
+/// Train which leaves up to so many seconds late is still on time
+pub const ON_TIME_DELAY: f32 = 60.;
+// Distance at which train is at the station
+const STOP_TOLERANCE: f32 = 0.01;
+
+
+/// Simulation time in seconds
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Clock {
+    pub time: f32,
+    /// Simulated seconds per real second
+    pub rate: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Station {
+    pub name: String,
+    /// Position of the platform. Trains in both directions stop there
+    pub pos: GraphPos,
+    /// Default dwell time in seconds
+    pub dwell: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Stop {
+    pub station: usize,
+    /// Scheduled departure in seconds
+    pub departure: f32,
+    /// Overrides dwell time of the station
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dwell: Option<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Timetable {
+    pub stops: Vec<Stop>,
+    /// Timetable repeats with this period (for trains running in a loop)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub period: Option<f32>,
+}
+
+/// What really happened at the stop
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StopRecord {
+    pub station: usize,
+    pub scheduled: f32,
+    pub arrival: f32,
+    pub departure: Option<f32>,
+}
+
+/// Train following the timetable
+#[derive(Clone, Debug, PartialEq)]
+pub struct Service {
+    pub timetable: Timetable,
+    /// Number of stops already visited (including the repeats)
+    visited: usize,
+    /// Train waits at the station until this time
+    dwell_until: Option<f32>,
+    pub records: Vec<StopRecord>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReportRow {
+    pub train: usize,
+    pub station: String,
+    pub scheduled: f32,
+    pub arrival: f32,
+    pub departure: Option<f32>,
+    /// Seconds after the scheduled departure. Train which didn't leave yet is late by the time waited
+    pub delay: f32,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PunctualityReport {
+    pub rows: Vec<ReportRow>,
+    pub mean_delay: f32,
+    pub max_delay: f32,
+    /// Fraction of stops left on time
+    pub on_time: f32,
+}
+
+
+impl Clock {
+    pub fn new(rate: f32) -> Self {
+        Self { time: 0., rate }
+    }
+
+    /// Advance by real time. Returns simulated time step
+    pub fn tick(&mut self, dt: f32) -> f32 {
+        let sim_dt = dt * self.rate;
+        self.time += sim_dt;
+        sim_dt
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", format_time(self.time))
+    }
+}
+
+impl Station {
+    pub fn new(name: &str, pos: GraphPos, dwell: f32) -> Self {
+        Self { name: name.to_owned(), pos, dwell }
+    }
+}
+
+impl Stop {
+    pub fn new(station: usize, departure: f32) -> Self {
+        Self { station, departure, dwell: None }
+    }
+
+    pub fn with_dwell(self, dwell: f32) -> Self {
+        Self { dwell: Some(dwell), ..self }
+    }
+}
+
+impl Timetable {
+    pub fn new(stops: Vec<Stop>) -> Self {
+        Self { stops, period: None }
+    }
+
+    pub fn repeat(self, period: f32) -> Self {
+        Self { period: Some(period), ..self }
+    }
+
+    /// Stop with the departure time for the given run (0 is the first visit of the first stop)
+    pub fn stop(&self, idx: usize) -> Option<Stop> {
+        let count = self.stops.len();
+        if count == 0 {
+            return None;
+        }
+        let repeat = idx / count;
+        let stop = self.stops[idx % count];
+        match self.period {
+            None if repeat > 0 => None,
+            None => Some(stop),
+            Some(period) => Some(Stop { departure: stop.departure + repeat as f32 * period, ..stop }),
+        }
+    }
+}
+
+impl Service {
+    pub fn new(timetable: Timetable) -> Self {
+        Self { timetable, visited: 0, dwell_until: None, records: vec![] }
+    }
+
+    pub fn next_stop(&self) -> Option<Stop> {
+        self.timetable.stop(self.visited)
+    }
+
+    pub fn is_dwelling(&self) -> bool {
+        self.dwell_until.is_some()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_stop().is_none()
+    }
+
+    /// Distance the train can travel now: nothing while waiting at the station,
+    /// otherwise up to the next stop.
+    pub fn max_distance(
+        &mut self, graph: &Graph, stations: &[Station], pos: &GraphPos, route: &[usize], time: f32,
+    ) -> f32 {
+        if let Some(until) = self.dwell_until {
+            if time < until {
+                return 0.;
+            }
+            if let Some(record) = self.records.last_mut() {
+                record.departure = Some(time);
+            }
+            self.dwell_until = None;
+            self.visited += 1;
+        }
+        match self.next_stop() {
+            Some(stop) => distance_to(graph, pos, &stations[stop.station].pos, route).unwrap_or(f32::MAX),
+            None => f32::MAX,
+        }
+    }
+
+    /// Check if the train arrived at the next stop (after it moved)
+    pub fn update(&mut self, stations: &[Station], pos: &GraphPos, time: f32) {
+        let Some(stop) = self.next_stop() else {
+            return;
+        };
+        let station = &stations[stop.station];
+        if self.dwell_until.is_none() && pos.edge_id() == station.pos.edge_id()
+            && (pos.distance() - station.pos.distance()).abs() < STOP_TOLERANCE {
+            let dwell = stop.dwell.unwrap_or(station.dwell);
+            self.dwell_until = Some((time + dwell).max(stop.departure));
+            self.records.push(StopRecord {
+                station: stop.station,
+                scheduled: stop.departure,
+                arrival: time,
+                departure: None,
+            });
+        }
+    }
+}
+
+impl StopRecord {
+    pub fn delay(&self, time: f32) -> f32 {
+        (self.departure.unwrap_or(time) - self.scheduled).max(0.)
+    }
+}
+
+impl PunctualityReport {
+    /// Report for all the trains at the given time
+    pub fn new(services: &[Service], stations: &[Station], time: f32) -> Self {
+        let rows: Vec<ReportRow> = services.iter().enumerate()
+            .flat_map(|(train, service)| service.records.iter().map(move |r| (train, r)))
+            .map(|(train, record)| ReportRow {
+                train,
+                station: stations[record.station].name.clone(),
+                scheduled: record.scheduled,
+                arrival: record.arrival,
+                departure: record.departure,
+                delay: record.delay(time),
+            })
+            .collect();
+        if rows.is_empty() {
+            return Self::default();
+        }
+        let count = rows.len() as f32;
+        let mean_delay = rows.iter().map(|r| r.delay).sum::<f32>() / count;
+        let max_delay = rows.iter().map(|r| r.delay).fold(0., f32::max);
+        let on_time = rows.iter().filter(|r| r.delay <= ON_TIME_DELAY).count() as f32 / count;
+        Self { rows, mean_delay, max_delay, on_time }
+    }
+}
+
+impl fmt::Display for PunctualityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:>5}  {:<16} {:>9} {:>9} {:>9} {:>7}", "train", "station", "scheduled", "arrival", "departure", "delay")?;
+        for row in &self.rows {
+            let departure = row.departure.map_or("-".to_owned(), format_time);
+            writeln!(f, "{:>5}  {:<16} {:>9} {:>9} {:>9} {:>6.0}s",
+                row.train, row.station, format_time(row.scheduled), format_time(row.arrival), departure, row.delay)?;
+        }
+        write!(f, "mean delay: {:.0}s, max delay: {:.0}s, on time: {:.0}%",
+            self.mean_delay, self.max_delay, 100. * self.on_time)
+    }
+}
+
+
+// Time as hh:mm:ss
+fn format_time(time: f32) -> String {
+    let secs = time.max(0.) as u32;
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+// Distance along the route to the target position. None if target is not on the way
+fn distance_to(graph: &Graph, pos: &GraphPos, target: &GraphPos, route: &[usize]) -> Option<f32> {
+    let length = graph.edges[pos.edge_id()].length();
+    let progress = pos.progress(length);
+    if pos.edge_id() == target.edge_id() {
+        let target_progress = if pos.is_reversed() { length - target.distance() } else { target.distance() };
+        if target_progress >= progress - STOP_TOLERANCE {
+            return Some((target_progress - progress).max(0.));
+        }
+    }
+    let mut distance = length - progress;
+    let mut pos = *pos;
+    // Each edge of the route can be visited once (or twice if the train is not on the route yet)
+    for _ in 0..=route.len() {
+        let (edge_id, reversed) = graph.next_edge(&pos, route)?;
+        let length = graph.edges[edge_id].length();
+        if edge_id == target.edge_id() {
+            let target_progress = if reversed { length - target.distance() } else { target.distance() };
+            return Some(distance + target_progress);
+        }
+        distance += length;
+        pos = GraphPos::with_progress(edge_id, reversed, length, length);
+    }
+    None
+}
+
+
+/// ------------------------------------------------------------------------------------------------
+/// Module unit tests
+/// ------------------------------------------------------------------------------------------------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transnet::{Edge, Node};
+
+    // Square loop 0 -> 1 -> 2 -> 3 -> 0, every side is 100 long
+    fn square() -> Graph {
+        let nodes = vec![
+            Node::new(0., 0.), Node::new(100., 0.), Node::new(100., 100.), Node::new(0., 100.),
+        ];
+        let edges = (0..4).map(|i| Edge::new(i, (i + 1) % 4, &nodes)).collect();
+        Graph::new(nodes, edges)
+    }
+
+    fn stations() -> Vec<Station> {
+        vec![
+            Station::new("North", GraphPos::new(0, 50.), 20.),
+            Station::new("South", GraphPos::new(2, 50.), 20.),
+        ]
+    }
+
+    // Run the train with the given speed for the given time (in 1s steps)
+    fn run(service: &mut Service, speed: f32, seconds: usize) -> GraphPos {
+        let graph = square();
+        let stations = stations();
+        let route = [0, 1, 2, 3];
+        let mut pos = GraphPos::init(0);
+        for time in 0..seconds {
+            let time = time as f32;
+            let distance = service.max_distance(&graph, &stations, &pos, &route, time).min(speed);
+            pos = graph.update_pos(&pos, &route, distance);
+            service.update(&stations, &pos, time);
+        }
+        pos
+    }
+
+    #[test]
+    fn test_distance_to() {
+        let graph = square();
+        let route = [0, 1, 2, 3];
+        assert_eq!(distance_to(&graph, &GraphPos::new(0, 20.), &GraphPos::new(0, 50.), &route), Some(30.));
+        assert_eq!(distance_to(&graph, &GraphPos::new(0, 20.), &GraphPos::new(2, 50.), &route), Some(230.));
+        // Station behind the train is reached after the whole loop
+        assert_eq!(distance_to(&graph, &GraphPos::new(0, 60.), &GraphPos::new(0, 50.), &route), Some(390.));
+        assert_eq!(distance_to(&graph, &GraphPos::new(0, 60.), &GraphPos::new(0, 50.), &[0, 1]), None);
+    }
+
+    #[test]
+    fn test_dwell_and_departure() {
+        let timetable = Timetable::new(vec![Stop::new(0, 100.), Stop::new(1, 130.).with_dwell(5.)]);
+        let mut service = Service::new(timetable);
+        run(&mut service, 10., 400);
+
+        assert!(service.is_finished());
+        // Train keeps going around the loop
+        assert!(!service.is_dwelling());
+        let records = &service.records;
+        // Early at North, waits for the departure time
+        assert_eq!(records[0], StopRecord { station: 0, scheduled: 100., arrival: 4., departure: Some(100.) });
+        // 200 to South takes 20s, 5s dwell
+        assert_eq!(records[1].arrival, 119.);
+        assert_eq!(records[1].departure, Some(130.));
+    }
+
+    #[test]
+    fn test_punctuality() {
+        // Timetable expects faster train, repeats every 60s
+        let timetable = Timetable::new(vec![Stop::new(0, 10.), Stop::new(1, 40.)]).repeat(60.);
+        let mut service = Service::new(timetable);
+        run(&mut service, 10., 400);
+        assert_eq!(service.records[2].scheduled, 70.);
+
+        let report = PunctualityReport::new(&[service], &stations(), 400.);
+        assert_eq!(report.rows[0].station, "North");
+        assert_eq!(report.rows[0].delay, 14.);
+        assert_eq!(report.rows[1].delay, 23.);
+        // Still waiting at the station
+        assert_eq!(report.rows.last().unwrap().departure, None);
+        assert!(report.max_delay > ON_TIME_DELAY);
+        assert!(report.on_time > 0. && report.on_time < 1.);
+        assert!(report.to_string().contains("00:00:10"));
+    }
+}